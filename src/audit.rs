@@ -0,0 +1,103 @@
+use able_seaman::release::plan::PlanSummary;
+use serde::Serialize;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Entry of the audit log, written as one line of JSON
+#[derive(Clone, Debug, Serialize)]
+pub struct Record<'a> {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+
+    /// Local user who ran the command, if known
+    pub user: Option<String>,
+
+    pub release: &'a str,
+
+    /// Command which has been run, e.g. `deploy`
+    pub action: &'a str,
+
+    /// Outcome of the command, e.g. `installed` or `failed`
+    pub result: &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<PlanSummary>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<'a> Record<'a> {
+    /// Record of an action on the release which has just finished.
+    pub fn new(release: &'a str, action: &'a str, result: &'a str) -> Self {
+        Record {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            user: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok(),
+            release,
+            action,
+            result,
+            plan: None,
+            error: None,
+        }
+    }
+
+    pub fn with_plan(mut self, plan: PlanSummary) -> Self {
+        self.plan = Some(plan);
+        self
+    }
+
+    pub fn with_error<E: fmt::Display>(mut self, error: &E) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// Append-only log of the changes made to releases
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+}
+
+impl AuditLog {
+    /// Create a log which appends to the given file, or discards all records without a path.
+    pub fn new(path: Option<&Path>) -> Self {
+        AuditLog {
+            path: path.map(Path::to_path_buf),
+        }
+    }
+
+    /// Append the record. The command has already taken effect at this point, hence failures are
+    /// only reported.
+    pub fn append(&self, record: &Record<'_>) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        if let Err(error) = write_line(path, record) {
+            eprintln!("Failed to write to audit log {}: {}", path.display(), error);
+        }
+    }
+}
+
+fn write_line(path: &Path, record: &Record<'_>) -> Result<(), io::Error> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+
+    // A single write keeps lines of concurrent writers from interleaving.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
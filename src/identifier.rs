@@ -1,12 +1,21 @@
+use crate::k8s::api_resource::ToApiResource;
 use crate::k8s::api_resource::TryToApiResource;
+use kube::core::ApiResource;
 use kube::core::GroupVersionKind;
 use serde::Deserialize;
 use serde::Serialize;
+use std::cmp::Ordering;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Identifier {
     gvk: GroupVersionKind,
     name: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
 }
 
 impl Identifier {
@@ -16,7 +25,10 @@ impl Identifier {
     {
         let name = object.meta().name.clone()?;
         let api_resource = object.try_to_api_resource()?;
-        Some(Self::from_api_resource(name, &api_resource))
+        Some(
+            Self::from_api_resource(name, &api_resource)
+                .with_namespace(object.meta().namespace.clone()),
+        )
     }
 
     pub fn from_api_resource(name: String, api_resource: &kube::core::ApiResource) -> Self {
@@ -26,6 +38,146 @@ impl Identifier {
             version: api_resource.version.clone(),
         };
 
-        Identifier { gvk, name }
+        Identifier {
+            gvk,
+            name,
+            namespace: None,
+        }
+    }
+
+    /// Place the identified object in the given namespace.
+    pub fn with_namespace(mut self, namespace: Option<String>) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    /// Place the identified object in the given namespace unless it already specifies one.
+    pub fn with_default_namespace(mut self, namespace: &str) -> Self {
+        self.namespace.get_or_insert_with(|| namespace.to_string());
+        self
+    }
+
+    /// Name of the identified object
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Namespace of the identified object, if known
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Kind of the identified object
+    pub fn kind(&self) -> &str {
+        self.gvk.kind.as_str()
+    }
+}
+
+impl Identifier {
+    fn sort_key(&self) -> (&str, &str, &str, Option<&str>, &str) {
+        (
+            self.gvk.group.as_str(),
+            self.gvk.version.as_str(),
+            self.gvk.kind.as_str(),
+            self.namespace.as_deref(),
+            self.name.as_str(),
+        )
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+/// Formats the identifier like `apps/v1 Deployment/frontend`, followed by the namespace if known.
+impl fmt::Display for Identifier {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        if !self.gvk.group.is_empty() {
+            write!(formatter, "{}/", self.gvk.group)?;
+        }
+
+        write!(
+            formatter,
+            "{} {}/{}",
+            self.gvk.version, self.gvk.kind, self.name
+        )?;
+
+        if let Some(namespace) = &self.namespace {
+            write!(formatter, " in namespace {}", namespace)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ToApiResource for Identifier {
+    fn to_api_resource(&self) -> ApiResource {
+        ApiResource::from_gvk(&self.gvk)
+    }
+}
+
+/// Error that occurs when parsing a malformed target
+#[derive(Clone, Debug)]
+pub struct TargetError {
+    pub input: String,
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "Invalid target {:?}: Expected Kind/name",
+            self.input
+        )
+    }
+}
+
+impl error::Error for TargetError {}
+
+/// Object selected by kind and name, e.g. `Deployment/frontend`. The kind is matched
+/// case-insensitively.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub kind: String,
+    pub name: String,
+}
+
+impl Target {
+    /// Does the target select the identified object?
+    pub fn matches(&self, identifier: &Identifier) -> bool {
+        identifier.kind().eq_ignore_ascii_case(self.kind.as_str())
+            && identifier.name() == self.name.as_str()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(formatter, "{}/{}", self.kind, self.name)
+    }
+}
+
+impl FromStr for Target {
+    type Err = TargetError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (kind, name) = input
+            .split_once('/')
+            .filter(|(kind, name)| !kind.is_empty() && !name.is_empty())
+            .ok_or_else(|| TargetError {
+                input: input.to_string(),
+            })?;
+
+        Ok(Target {
+            kind: kind.to_string(),
+            name: name.to_string(),
+        })
     }
 }
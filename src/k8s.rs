@@ -1,11 +1,18 @@
+pub mod access;
 pub mod annotations;
 pub mod api_resource;
+pub mod condition;
 pub mod labels;
 pub mod lock;
+pub mod merge;
+pub mod openapi;
+pub mod selector;
 pub mod transaction;
 
 use crate::meta;
+use kube::ResourceExt;
 use serde::Serialize;
+use std::cmp::Ordering;
 use std::fmt;
 
 const VERSION_KEY: &str = const_format::concatcp!(meta::CRATE_NAME, "/version");
@@ -13,12 +20,96 @@ const VERSION_KEY: &str = const_format::concatcp!(meta::CRATE_NAME, "/version");
 #[derive(Clone, Copy, Debug)]
 pub struct CrateVersion;
 
+impl CrateVersion {
+    /// Version with which the object has been annotated
+    pub fn of<R: ResourceExt>(object: &R) -> Option<String> {
+        object.annotations().get(VERSION_KEY).cloned()
+    }
+
+    /// Compare the major component of the given version to that of this crate. Returns `None` if
+    /// the version is malformed.
+    pub fn compare(version: &str) -> Option<Ordering> {
+        let major = |version: &str| version.split('.').next()?.parse::<u64>().ok();
+        Some(major(version)?.cmp(&major(meta::CRATE_VERSION)?))
+    }
+}
+
 impl annotations::ToAnnotation for CrateVersion {
     fn to_annotation(&self) -> (&'static str, String) {
         (VERSION_KEY, meta::CRATE_VERSION.to_string())
     }
 }
 
+const FAILURE_KEY: &str = const_format::concatcp!(meta::CRATE_NAME, "/failure");
+
+/// Failure that caused a lock to be retained
+#[derive(Clone, Debug)]
+pub struct LockFailure(pub String);
+
+impl LockFailure {
+    /// Failure with which the lock has been annotated when it was retained
+    pub fn of<R: ResourceExt>(object: &R) -> Option<Self> {
+        object
+            .annotations()
+            .get(FAILURE_KEY)
+            .cloned()
+            .map(LockFailure)
+    }
+}
+
+impl annotations::ToAnnotation for LockFailure {
+    fn to_annotation(&self) -> (&'static str, String) {
+        (FAILURE_KEY, self.0.clone())
+    }
+}
+
+const VERIFIED_AT_KEY: &str = const_format::concatcp!(meta::CRATE_NAME, "/last-verified-at");
+
+const VERIFY_RESULT_KEY: &str = const_format::concatcp!(meta::CRATE_NAME, "/last-verify-result");
+
+/// Outcome of the last verification of a release, which is recorded on its state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LastVerification {
+    /// Seconds since the Unix epoch
+    pub verified_at: u64,
+
+    /// Did the objects in the cluster match the release?
+    pub in_sync: bool,
+}
+
+impl LastVerification {
+    /// Verification with which the object has been annotated
+    pub fn of<R: ResourceExt>(object: &R) -> Option<Self> {
+        let annotations = object.annotations();
+        let verified_at = annotations.get(VERIFIED_AT_KEY)?.parse().ok()?;
+        let in_sync = match annotations.get(VERIFY_RESULT_KEY)?.as_str() {
+            "ok" => true,
+            "drift" => false,
+            _ => return None,
+        };
+
+        Some(LastVerification {
+            verified_at,
+            in_sync,
+        })
+    }
+
+    /// Result as it is recorded, either "ok" or "drift"
+    pub fn result(&self) -> &'static str {
+        if self.in_sync {
+            "ok"
+        } else {
+            "drift"
+        }
+    }
+
+    pub fn to_annotations(&self) -> annotations::Annotations {
+        annotations::Annotations::new()
+            .set(VERIFIED_AT_KEY, self.verified_at.to_string())
+            .set(VERIFY_RESULT_KEY, self.result().to_string())
+    }
+}
+
 const TYPE_KEY: &str = const_format::concatcp!(meta::CRATE_NAME, "/type");
 
 #[derive(Clone, Copy, Debug, Serialize)]
@@ -26,6 +117,8 @@ pub enum ObjectType {
     Lock,
     ReleaseState,
     Managed,
+    Checkpoint,
+    ReleaseStateChunk,
 }
 
 impl fmt::Display for ObjectType {
@@ -34,6 +127,8 @@ impl fmt::Display for ObjectType {
             ObjectType::Lock => "lock",
             ObjectType::ReleaseState => "release-state",
             ObjectType::Managed => "managed",
+            ObjectType::Checkpoint => "checkpoint",
+            ObjectType::ReleaseStateChunk => "release-state-chunk",
         })
     }
 }
@@ -49,8 +144,39 @@ const RELEASE_KEY: &str = const_format::concatcp!(meta::CRATE_NAME, "/release");
 #[derive(Clone, Debug, Serialize)]
 pub struct ReleaseName(pub String);
 
+impl ReleaseName {
+    /// Name of the release with which the object is labelled
+    pub fn of<R: ResourceExt>(object: &R) -> Option<Self> {
+        object.labels().get(RELEASE_KEY).cloned().map(ReleaseName)
+    }
+}
+
 impl labels::ToLabel for ReleaseName {
     fn to_label(&self) -> (&'static str, String) {
         (RELEASE_KEY, self.0.clone())
     }
 }
+
+const MANAGED_BY_KEY: &str = "app.kubernetes.io/managed-by";
+
+/// Recommended label naming the tool which manages the object
+#[derive(Clone, Copy, Debug)]
+pub struct ManagedBy;
+
+impl labels::ToLabel for ManagedBy {
+    fn to_label(&self) -> (&'static str, String) {
+        (MANAGED_BY_KEY, meta::CRATE_NAME.to_string())
+    }
+}
+
+const INSTANCE_KEY: &str = "app.kubernetes.io/instance";
+
+/// Recommended label naming the instance of an application, which is the release
+#[derive(Clone, Debug)]
+pub struct Instance(pub String);
+
+impl labels::ToLabel for Instance {
+    fn to_label(&self) -> (&'static str, String) {
+        (INSTANCE_KEY, self.0.clone())
+    }
+}
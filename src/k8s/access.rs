@@ -0,0 +1,102 @@
+use crate::objects::Objects;
+use k8s_openapi::api::authorization::v1::ResourceAttributes;
+use k8s_openapi::api::authorization::v1::SelfSubjectAccessReview;
+use k8s_openapi::api::authorization::v1::SelfSubjectAccessReviewSpec;
+use kube::api;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Verbs needed to deploy and delete objects
+pub const DEPLOY_VERBS: [&str; 4] = ["get", "create", "patch", "delete"];
+
+/// Action on a kind of resource which requires permission
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Access {
+    pub verb: String,
+    pub group: String,
+    pub resource: String,
+
+    /// Namespace of the resource, unless it is cluster-scoped
+    pub namespace: Option<String>,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(formatter, "{} {}", self.verb, self.resource)?;
+
+        if !self.group.is_empty() {
+            write!(formatter, ".{}", self.group)?;
+        }
+
+        match &self.namespace {
+            Some(namespace) => write!(formatter, " in namespace {}", namespace),
+            None => write!(formatter, " (cluster-scoped)"),
+        }
+    }
+}
+
+/// Access needed to deploy and delete the objects in the given namespace. Kinds listed in
+/// `cluster_scoped` by group and kind are not namespaced.
+pub fn required_access(
+    objects: &Objects,
+    namespace: &str,
+    cluster_scoped: &HashSet<(String, String)>,
+) -> Vec<Access> {
+    let mut accesses = BTreeSet::new();
+
+    for (_, object) in objects.iter() {
+        let resource = &object.api_resource;
+        let is_cluster_scoped =
+            cluster_scoped.contains(&(resource.group.clone(), resource.kind.clone()));
+
+        for verb in DEPLOY_VERBS.iter() {
+            accesses.insert(Access {
+                verb: verb.to_string(),
+                group: resource.group.clone(),
+                resource: resource.plural.clone(),
+                namespace: Some(namespace.to_string()).filter(|_| !is_cluster_scoped),
+            });
+        }
+    }
+
+    accesses.into_iter().collect()
+}
+
+/// Ask the API server whether the current user has the given access.
+pub async fn is_allowed(client: kube::Client, access: &Access) -> Result<bool, kube::Error> {
+    let reviews: kube::Api<SelfSubjectAccessReview> = kube::Api::all(client);
+
+    let review = SelfSubjectAccessReview {
+        spec: SelfSubjectAccessReviewSpec {
+            resource_attributes: Some(ResourceAttributes {
+                verb: Some(access.verb.clone()),
+                group: Some(access.group.clone()),
+                resource: Some(access.resource.clone()),
+                namespace: access.namespace.clone(),
+                ..ResourceAttributes::default()
+            }),
+            ..SelfSubjectAccessReviewSpec::default()
+        },
+        ..SelfSubjectAccessReview::default()
+    };
+
+    let result = reviews.create(&api::PostParams::default(), &review).await?;
+    Ok(result.status.map_or(false, |status| status.allowed))
+}
+
+/// Find the accesses which the current user lacks.
+pub async fn find_denied(
+    client: &kube::Client,
+    accesses: &[Access],
+) -> Result<Vec<Access>, kube::Error> {
+    let mut denied = Vec::new();
+
+    for access in accesses {
+        if !is_allowed(client.clone(), access).await? {
+            denied.push(access.clone());
+        }
+    }
+
+    Ok(denied)
+}
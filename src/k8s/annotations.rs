@@ -23,6 +23,22 @@ impl Annotations {
         self.set(name, value)
     }
 
+    /// Drop the annotation with the given name from the set.
+    pub fn remove(mut self, name: &'static str) -> Self {
+        self.annotations.remove(name);
+        self
+    }
+
+    /// Render the annotations in the same `name=value` format as a label selector.
+    pub fn to_selector(&self) -> String {
+        self.annotations
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .as_slice()
+            .join(",")
+    }
+
     pub fn apply_to<'a, R: ResourceExt>(&self, subject: &'a mut R) -> &'a mut R {
         subject.annotations_mut().extend(
             self.annotations
@@ -33,6 +49,17 @@ impl Annotations {
 
         subject
     }
+
+    /// Remove the annotations in this set from the subject, regardless of their values.
+    pub fn apply_removal_to<'a, R: ResourceExt>(&self, subject: &'a mut R) -> &'a mut R {
+        let annotations = subject.annotations_mut();
+
+        for name in self.annotations.keys() {
+            annotations.remove(*name);
+        }
+
+        subject
+    }
 }
 
 impl Default for Annotations {
@@ -1,8 +1,10 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::APIResource;
 use kube::core::ApiResource;
 use kube::core::DynamicObject;
 use kube::core::GroupVersionKind;
 use kube::core::TypeMeta;
 use std::collections::HashSet;
+use std::fmt;
 
 fn split_api_version(api_version: &str) -> (&str, &str) {
     if let Some((group, version)) = api_version.split_once('/') {
@@ -45,10 +47,52 @@ impl TryToApiResource for DynamicObject {
     }
 }
 
+/// Group version whose resources could not be discovered
+#[derive(Clone, Debug)]
+pub struct SkippedGroup {
+    pub group_version: String,
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedGroup {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "Skipped discovery of {}: {}",
+            self.group_version, self.reason
+        )
+    }
+}
+
+/// List the resources of the group version. Unless discovery is strict, failures are added to the
+/// skipped groups and yield no resources, so one broken aggregated API does not hide all others.
+async fn list_group_resources(
+    client: &kube::Client,
+    group_version: &str,
+    strict: bool,
+    skipped: &mut Vec<SkippedGroup>,
+) -> Result<Vec<APIResource>, kube::Error> {
+    match client.list_api_group_resources(group_version).await {
+        Ok(list) => Ok(list.resources),
+        Err(error) if !strict => {
+            skipped.push(SkippedGroup {
+                group_version: group_version.to_string(),
+                reason: error.to_string(),
+            });
+            Ok(Vec::new())
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Find the resources which can be listed. With `strict` discovery, failing to discover any group
+/// is an error, otherwise such groups are skipped and returned along with the resources.
 pub async fn find_api_resources(
     client: &kube::Client,
-) -> Result<HashSet<ApiResource>, kube::Error> {
+    strict: bool,
+) -> Result<(HashSet<ApiResource>, Vec<SkippedGroup>), kube::Error> {
     let mut resources = HashSet::new();
+    let mut skipped = Vec::new();
 
     for core_version in client.list_core_api_versions().await?.versions {
         let core_resources = client
@@ -81,11 +125,11 @@ pub async fn find_api_resources(
 
     for group in client.list_api_groups().await?.groups {
         for version in group.versions {
-            let group_resources = client
-                .list_api_group_resources(version.group_version.as_str())
-                .await?;
+            let group_resources =
+                list_group_resources(client, version.group_version.as_str(), strict, &mut skipped)
+                    .await?;
 
-            for group_resource in group_resources.resources {
+            for group_resource in group_resources {
                 if !(group_resource.verbs.contains(&"get".to_string())
                     && group_resource.verbs.contains(&"list".to_string()))
                 {
@@ -114,5 +158,44 @@ pub async fn find_api_resources(
         }
     }
 
-    Ok(resources)
+    Ok((resources, skipped))
+}
+
+/// Find the kinds of resources which are not namespaced, identified by group and kind. See
+/// `find_api_resources` regarding `strict` discovery.
+pub async fn find_cluster_scoped_kinds(
+    client: &kube::Client,
+    strict: bool,
+) -> Result<(HashSet<(String, String)>, Vec<SkippedGroup>), kube::Error> {
+    let mut kinds = HashSet::new();
+    let mut skipped = Vec::new();
+
+    for core_version in client.list_core_api_versions().await?.versions {
+        let core_resources = client
+            .list_core_api_resources(core_version.as_str())
+            .await?;
+
+        for core_resource in core_resources.resources {
+            if !core_resource.namespaced {
+                kinds.insert((String::new(), core_resource.kind));
+            }
+        }
+    }
+
+    for group in client.list_api_groups().await?.groups {
+        for version in group.versions {
+            let group_resources =
+                list_group_resources(client, version.group_version.as_str(), strict, &mut skipped)
+                    .await?;
+
+            for group_resource in group_resources {
+                if !group_resource.namespaced {
+                    let group_name = group_resource.group.unwrap_or_else(|| group.name.clone());
+                    kinds.insert((group_name, group_resource.kind));
+                }
+            }
+        }
+    }
+
+    Ok((kinds, skipped))
 }
@@ -0,0 +1,95 @@
+use kube::core::DynamicObject;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error that occurs when parsing a malformed wait condition
+#[derive(Clone, Debug)]
+pub struct Error {
+    pub input: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "Invalid wait condition {:?}: {}",
+            self.input, self.reason
+        )
+    }
+}
+
+impl error::Error for Error {}
+
+/// Condition an object has to reach, e.g. `Certificate/frontend:condition=Ready`
+///
+/// The condition is met once `status.conditions` contains an entry of the given type whose status
+/// is `True`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WaitCondition {
+    pub kind: String,
+    pub name: String,
+    pub condition: String,
+}
+
+impl WaitCondition {
+    /// Does the object satisfy the condition?
+    pub fn is_met(&self, object: &DynamicObject) -> bool {
+        has_condition(object, self.condition.as_str(), "True")
+    }
+}
+
+impl fmt::Display for WaitCondition {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "{}/{}:condition={}",
+            self.kind, self.name, self.condition
+        )
+    }
+}
+
+impl FromStr for WaitCondition {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = |reason: &str| Error {
+            input: input.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let (target, predicate) = input
+            .split_once(':')
+            .ok_or_else(|| error("Expected Kind/name:condition=Type"))?;
+
+        let (kind, name) = target
+            .split_once('/')
+            .filter(|(kind, name)| !kind.is_empty() && !name.is_empty())
+            .ok_or_else(|| error("Target must be of the form Kind/name"))?;
+
+        let condition = predicate
+            .strip_prefix("condition=")
+            .filter(|condition| !condition.is_empty())
+            .ok_or_else(|| error("Predicate must be of the form condition=Type"))?;
+
+        Ok(WaitCondition {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            condition: condition.to_string(),
+        })
+    }
+}
+
+/// Check whether the object's `status.conditions` contains a condition of the given type with the
+/// given status.
+pub fn has_condition(object: &DynamicObject, condition_type: &str, status: &str) -> bool {
+    object.data["status"]["conditions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|condition| {
+            condition["type"].as_str() == Some(condition_type)
+                && condition["status"].as_str() == Some(status)
+        })
+}
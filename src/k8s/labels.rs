@@ -1,3 +1,4 @@
+use crate::k8s::selector::Selector;
 use kube::ResourceExt;
 use std::collections::HashMap;
 
@@ -23,16 +24,33 @@ impl Labels {
         self.set(name, value)
     }
 
-    pub fn to_listparams(&self) -> kube::api::ListParams {
-        let it = self
-            .labels
+    /// Drop the label with the given name from the set.
+    pub fn remove(mut self, name: &'static str) -> Self {
+        self.labels.remove(name);
+        self
+    }
+
+    pub fn to_selector(&self) -> String {
+        self.labels
             .iter()
             .map(|(name, value)| format!("{}={}", name, value))
             .collect::<Vec<String>>()
             .as_slice()
-            .join(",");
+            .join(",")
+    }
+
+    pub fn to_listparams(&self) -> kube::api::ListParams {
+        kube::api::ListParams::default().labels(self.to_selector().as_str())
+    }
+
+    /// Combine these labels with an additional user-supplied selector.
+    pub fn to_listparams_with(&self, selector: &Selector) -> kube::api::ListParams {
+        if selector.is_empty() {
+            return self.to_listparams();
+        }
 
-        kube::api::ListParams::default().labels(it.as_str())
+        let combined = format!("{},{}", self.to_selector(), selector);
+        kube::api::ListParams::default().labels(combined.as_str())
     }
 
     pub fn apply_to<'a, R: ResourceExt>(&self, subject: &'a mut R) -> &'a mut R {
@@ -45,6 +63,17 @@ impl Labels {
 
         subject
     }
+
+    /// Remove the labels in this set from the subject, regardless of their values.
+    pub fn apply_removal_to<'a, R: ResourceExt>(&self, subject: &'a mut R) -> &'a mut R {
+        let labels = subject.labels_mut();
+
+        for name in self.labels.keys() {
+            labels.remove(*name);
+        }
+
+        subject
+    }
 }
 
 impl Default for Labels {
@@ -79,6 +108,8 @@ pub trait WithLabels {
     fn with_labels(self, labels: &Labels) -> Self;
 
     fn with_label<L: ToLabel>(self, label: &L) -> Self;
+
+    fn has_label<L: ToLabel>(&self, label: &L) -> bool;
 }
 
 impl<R: ResourceExt> WithLabels for R {
@@ -92,4 +123,9 @@ impl<R: ResourceExt> WithLabels for R {
         self.labels_mut().insert(name.to_string(), value);
         self
     }
+
+    fn has_label<L: ToLabel>(&self, label: &L) -> bool {
+        let (name, value) = label.to_label();
+        self.labels().get(name) == Some(&value)
+    }
 }
@@ -1,47 +1,170 @@
 use crate::k8s;
+use crate::k8s::annotations::ToAnnotation;
 use crate::k8s::annotations::WithAnnotations;
 use crate::k8s::labels;
 use crate::k8s::labels::WithLabels;
+use crate::metrics;
+use crate::utils::backoff::Backoff;
 use futures::StreamExt;
 use futures::TryStreamExt;
 use kube::api;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::error;
+use std::fmt;
 use std::fmt::Debug;
+use std::time::Duration;
+use std::time::Instant;
 
+#[derive(Debug)]
+pub enum Error {
+    Kube(kube::Error),
+
+    /// An object with the name of the lock exists, but it isn't a lock
+    Foreign {
+        name: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::Kube(error) => write!(formatter, "Kubernetes error: {}", error),
+            Error::Foreign { name } => write!(
+                formatter,
+                "Cannot lock: {} exists but is not labelled as a lock",
+                name
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Kube(error) => Some(error),
+            Error::Foreign { .. } => None,
+        }
+    }
+}
+
+impl From<kube::Error> for Error {
+    fn from(error: kube::Error) -> Self {
+        Error::Kube(error)
+    }
+}
+
+/// Wait until the lock with the given name no longer exists. Objects that aren't locks are never
+/// waited for, since nothing would delete them.
 async fn wait_for_deletion<SomeResource>(
     api: &kube::Api<SomeResource>,
     name: &str,
-) -> Result<(), kube::Error>
+) -> Result<(), Error>
 where
-    SomeResource: Clone + DeserializeOwned + Debug + kube::ResourceExt,
+    SomeResource: Clone + DeserializeOwned + Debug + kube::Resource,
 {
-    let mut stream = api
-        .watch(
-            &labels::Labels::from(k8s::ObjectType::Lock)
-                .to_listparams()
-                .timeout(10),
-            "0",
-        )
-        .await?
-        .boxed();
+    // The watch ends after a timeout without the deletion having been observed, in which case the
+    // object is looked up again in case the event was missed.
+    loop {
+        let resource_version = match check_lookup(name, api.get(name).await)? {
+            Lookup::Deleted => return Ok(()),
+            Lookup::Exists { resource_version } => resource_version,
+        };
 
-    while let Some(event) = stream.try_next().await? {
-        match event {
-            api::WatchEvent::Deleted(deletion) if deletion.name() == name => {
-                return Ok(());
-            }
+        if watch_for_deletion(api, name, &resource_version).await? {
+            return Ok(());
+        }
+    }
+}
+
+/// Outcome of looking up a lock while waiting for its deletion
+#[derive(Debug, PartialEq)]
+enum Lookup {
+    /// The lock is gone, so creating it may be attempted again.
+    Deleted,
+
+    /// The lock exists at the given resource version, from which its deletion is watched for.
+    Exists { resource_version: String },
+}
 
-            _ => {}
+/// Decide how to go on waiting for the deletion of the lock with the given name, given the result
+/// of looking it up.
+fn check_lookup<SomeResource>(
+    name: &str,
+    result: Result<SomeResource, kube::Error>,
+) -> Result<Lookup, Error>
+where
+    SomeResource: kube::Resource,
+{
+    match result {
+        Ok(object) if !object.has_label(&k8s::ObjectType::Lock) => Err(Error::Foreign {
+            name: name.to_string(),
+        }),
+        Ok(object) => Ok(Lookup::Exists {
+            resource_version: object.meta().resource_version.clone().unwrap_or_default(),
+        }),
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(Lookup::Deleted),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Has creating a lock failed because the lock is held already?
+fn is_held<SomeResource>(result: &Result<SomeResource, kube::Error>) -> bool {
+    matches!(
+        result,
+        Err(kube::Error::Api(kube::error::ErrorResponse { reason, code: 409, .. }))
+            if reason == "AlreadyExists"
+    )
+}
+
+/// Does the event end watching for a deletion? Returns whether the deletion has been observed if
+/// so.
+fn ends_watch<SomeResource>(event: &api::WatchEvent<SomeResource>) -> Option<bool> {
+    match event {
+        api::WatchEvent::Deleted(_) => Some(true),
+
+        // The resource version may have expired already, e.g. with "410 Gone".
+        api::WatchEvent::Error(_) => Some(false),
+
+        _ => None,
+    }
+}
+
+/// Watch the object with the given name, starting at the given resource version, so that a
+/// deletion after the version has been retrieved cannot be missed. Returns whether the deletion
+/// has been observed before the watch ended.
+async fn watch_for_deletion<SomeResource>(
+    api: &kube::Api<SomeResource>,
+    name: &str,
+    resource_version: &str,
+) -> Result<bool, kube::Error>
+where
+    SomeResource: Clone + DeserializeOwned + Debug + kube::Resource,
+{
+    let params = labels::Labels::from(k8s::ObjectType::Lock)
+        .to_listparams()
+        .fields(format!("metadata.name={}", name).as_str())
+        .timeout(10);
+    let mut stream = api.watch(&params, resource_version).await?.boxed();
+
+    while let Some(event) = stream.try_next().await? {
+        if let Some(deleted) = ends_watch(&event) {
+            return Ok(deleted);
         }
     }
 
-    Ok(())
+    Ok(false)
 }
 
+/// Exclusive lock represented by an object in the cluster
+///
+/// A lock must be released explicitly using `release` (or kept using `retain`) and awaited. A lock
+/// that is dropped instead, e.g. because its future has been cancelled, only schedules its
+/// deletion on the current Tokio runtime without waiting for it. That deletion is lost when the
+/// runtime shuts down first, in which case the lock remains until it is deleted manually.
 pub struct Lock<'a, T>
 where
-    T: Clone + DeserializeOwned + Debug,
+    T: Clone + DeserializeOwned + Debug + Send + Sync + 'static,
 {
     api: &'a kube::Api<T>,
     name: String,
@@ -50,9 +173,17 @@ where
 
 impl<'a, T> Lock<'a, T>
 where
-    T: kube::Resource + Default + Clone + Debug + DeserializeOwned + Serialize,
+    T: kube::Resource
+        + Default
+        + Clone
+        + Debug
+        + DeserializeOwned
+        + Serialize
+        + Send
+        + Sync
+        + 'static,
 {
-    pub async fn new(api: &'a kube::Api<T>, name: String) -> Result<Lock<'a, T>, kube::Error> {
+    pub async fn new(api: &'a kube::Api<T>, name: String) -> Result<Lock<'a, T>, Error> {
         Lock::new_with(api, name, <T as Default>::default()).await
     }
 
@@ -60,26 +191,32 @@ where
         api: &'a kube::Api<T>,
         name: String,
         mut lock_value: T,
-    ) -> Result<Lock<'a, T>, kube::Error> {
+    ) -> Result<Lock<'a, T>, Error> {
         lock_value.meta_mut().name = Some(name.clone());
         lock_value = lock_value
             .with_label(&k8s::ObjectType::Lock)
             .with_annotation(&k8s::CrateVersion);
 
+        let started = Instant::now();
+        // Another process may take the lock between its deletion and our attempt to create it.
+        // Backing off keeps waiting processes from retrying in lockstep.
+        let mut backoff = Backoff::new(Duration::from_millis(100), 2.0)
+            .with_max(Duration::from_secs(5))
+            .with_jitter(0.5);
+
         let _locked_value = loop {
-            match api.create(&api::PostParams::default(), &lock_value).await {
-                Err(kube::Error::Api(kube::error::ErrorResponse {
-                    reason, code: 409, ..
-                })) if reason == "AlreadyExists" => {
-                    wait_for_deletion(api, &name).await?;
-                }
-
-                result => {
-                    break result?;
-                }
+            let result = api.create(&api::PostParams::default(), &lock_value).await;
+
+            if !is_held(&result) {
+                break result?;
             }
+
+            wait_for_deletion(api, &name).await?;
+            backoff.sleep().await;
         };
 
+        metrics::observe_lock_wait(started.elapsed());
+
         Ok(Lock {
             api,
             name,
@@ -87,6 +224,27 @@ where
         })
     }
 
+    /// Keep the lock in place and annotate it with the given failure, e.g. because the cluster is
+    /// in a state that someone has to inspect before another operation may proceed. The lock has to
+    /// be removed manually afterwards.
+    pub async fn retain(mut self, failure: String) -> Result<(), kube::Error> {
+        // The lock stays, whether the annotation can be added or not.
+        self.deleted = true;
+
+        let (key, value) = k8s::LockFailure(failure).to_annotation();
+        let patch = serde_json::json!({ "metadata": { "annotations": { key: value } } });
+
+        self.api
+            .patch(
+                self.name.as_str(),
+                &api::PatchParams::default(),
+                &api::Patch::Merge(patch),
+            )
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn release(mut self) -> Result<(), kube::Error> {
         self.api
             .delete(self.name.as_str(), &api::DeleteParams::default())
@@ -98,20 +256,164 @@ where
 
 impl<'a, T> Drop for Lock<'a, T>
 where
-    T: Clone + DeserializeOwned + Debug,
+    T: Clone + DeserializeOwned + Debug + Send + Sync + 'static,
 {
     fn drop(&mut self) {
         if self.deleted {
             return;
         }
 
-        let deletion = futures::executor::block_on(
-            self.api
-                .delete(self.name.as_str(), &api::DeleteParams::default()),
-        );
+        // Blocking on the deletion here may stall or deadlock the runtime worker we're running on,
+        // therefore the deletion is handed to the runtime as a detached task. It must not keep the
+        // runtime from shutting down, so it is not waited for anywhere.
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let api = self.api.clone();
+                let name = self.name.clone();
+
+                runtime.spawn(async move {
+                    let deletion = api
+                        .delete(name.as_str(), &api::DeleteParams::default())
+                        .await;
+
+                    if let Err(err) = deletion {
+                        eprintln!("Failed to delete locking ConfigMap {}: {}", name, err);
+                    }
+                });
+            }
+
+            Err(_) => {
+                eprintln!(
+                    "Failed to delete locking ConfigMap {}: No runtime available",
+                    self.name
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::core::v1::ConfigMap;
+    use std::convert::TryFrom;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn dropping_a_held_lock_does_not_hang_the_runtime() {
+        let runtime = tokio::runtime::Runtime::new().expect("Runtime must start");
+
+        runtime.block_on(async {
+            // Nothing listens on the discard port, so the deletion can only fail.
+            let config = kube::Config::new("http://127.0.0.1:9".parse().unwrap());
+            let client = kube::Client::try_from(config).expect("Client must be created");
+            let api: kube::Api<ConfigMap> = kube::Api::namespaced(client, "default");
+
+            drop(Lock {
+                api: &api,
+                name: "able-seaman-lock-drop-test".to_string(),
+                deleted: false,
+            });
+        });
+
+        let started = Instant::now();
+        runtime.shutdown_timeout(Duration::from_secs(10));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    fn api_error(code: u16, reason: &str) -> kube::Error {
+        kube::Error::Api(kube::error::ErrorResponse {
+            status: "Failure".to_string(),
+            message: reason.to_string(),
+            reason: reason.to_string(),
+            code,
+        })
+    }
+
+    fn config_map(labelled: bool, resource_version: &str) -> ConfigMap {
+        let mut config_map = ConfigMap::default();
+        config_map.metadata.resource_version = Some(resource_version.to_string());
 
-        if let Err(err) = deletion {
-            eprintln!("Failed to delete locking ConfigMap {}: {}", self.name, err);
+        if labelled {
+            config_map.with_label(&k8s::ObjectType::Lock)
+        } else {
+            config_map
         }
     }
+
+    #[test]
+    fn only_existing_locks_are_waited_for() {
+        assert_eq!(
+            check_lookup("lock", Ok(config_map(true, "42"))).unwrap(),
+            Lookup::Exists {
+                resource_version: "42".to_string()
+            }
+        );
+        assert_eq!(
+            check_lookup::<ConfigMap>("lock", Err(api_error(404, "NotFound"))).unwrap(),
+            Lookup::Deleted
+        );
+        assert!(matches!(
+            check_lookup("lock", Ok(config_map(false, "42"))),
+            Err(Error::Foreign { .. })
+        ));
+        assert!(matches!(
+            check_lookup::<ConfigMap>("lock", Err(api_error(403, "Forbidden"))),
+            Err(Error::Kube(_))
+        ));
+    }
+
+    #[test]
+    fn only_conflicting_creations_wait_for_the_lock() {
+        assert!(is_held::<ConfigMap>(&Err(api_error(409, "AlreadyExists"))));
+        assert!(!is_held::<ConfigMap>(&Err(api_error(409, "Conflict"))));
+        assert!(!is_held::<ConfigMap>(&Err(api_error(403, "Forbidden"))));
+        assert!(!is_held(&Ok(config_map(true, "42"))));
+    }
+
+    #[test]
+    fn watching_ends_on_deletion_or_error() {
+        assert_eq!(
+            ends_watch(&api::WatchEvent::Deleted(config_map(true, "43"))),
+            Some(true)
+        );
+        assert_eq!(
+            ends_watch::<ConfigMap>(&api::WatchEvent::Error(kube::error::ErrorResponse {
+                status: "Failure".to_string(),
+                message: "too old resource version".to_string(),
+                reason: "Expired".to_string(),
+                code: 410,
+            })),
+            Some(false)
+        );
+        assert_eq!(
+            ends_watch(&api::WatchEvent::Modified(config_map(true, "43"))),
+            None
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a Kubernetes cluster"]
+    async fn concurrent_lockers_are_mutually_exclusive() {
+        let client = kube::Client::try_default()
+            .await
+            .expect("Cluster must be configured");
+        let api: kube::Api<ConfigMap> = kube::Api::default_namespaced(client);
+        let holders = AtomicUsize::new(0);
+
+        let lockers = (0..8).map(|_| async {
+            let lock = Lock::new(&api, "able-seaman-lock-stress-test".to_string())
+                .await
+                .expect("Lock must be acquired eventually");
+
+            assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            holders.fetch_sub(1, Ordering::SeqCst);
+
+            lock.release().await.expect("Lock must be released");
+        });
+
+        futures::future::join_all(lockers).await;
+    }
 }
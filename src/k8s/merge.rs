@@ -0,0 +1,59 @@
+use serde_json::Map;
+use serde_json::Value;
+
+/// Compute a JSON merge patch like `kubectl apply` does on the client side. Fields that have been
+/// removed since the last applied configuration are deleted, fields whose desired value differs
+/// from the live object are set. Fields which have never been part of the configuration are left
+/// untouched.
+pub fn three_way_patch(last_applied: &Value, desired: &Value, live: &Value) -> Value {
+    match (desired, live) {
+        (Value::Object(desired), Value::Object(live)) => {
+            let empty = Map::new();
+            let last_applied = last_applied.as_object().unwrap_or(&empty);
+
+            Value::Object(object_patch(last_applied, desired, live))
+        }
+
+        _ => desired.clone(),
+    }
+}
+
+fn object_patch(
+    last_applied: &Map<String, Value>,
+    desired: &Map<String, Value>,
+    live: &Map<String, Value>,
+) -> Map<String, Value> {
+    let mut patch = Map::new();
+
+    for (key, desired_value) in desired {
+        match live.get(key) {
+            Some(live_value) if live_value == desired_value => {}
+
+            Some(Value::Object(live_value)) if desired_value.is_object() => {
+                let empty = Map::new();
+                let last_applied_value = last_applied
+                    .get(key)
+                    .and_then(Value::as_object)
+                    .unwrap_or(&empty);
+                let desired_value = desired_value.as_object().unwrap_or(&empty);
+
+                let nested = object_patch(last_applied_value, desired_value, live_value);
+                if !nested.is_empty() {
+                    patch.insert(key.clone(), Value::Object(nested));
+                }
+            }
+
+            _ => {
+                patch.insert(key.clone(), desired_value.clone());
+            }
+        }
+    }
+
+    for key in last_applied.keys() {
+        if !desired.contains_key(key) && live.contains_key(key) {
+            patch.insert(key.clone(), Value::Null);
+        }
+    }
+
+    patch
+}
@@ -0,0 +1,249 @@
+use crate::objects::Object;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Structural problem of an object with regards to the OpenAPI schema
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub path: VecDeque<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let path = self.path.iter().cloned().collect::<Vec<String>>().join(".");
+        write!(formatter, "{}: {}", path, self.message)
+    }
+}
+
+/// OpenAPI (v2) schema published by the API server
+#[derive(Clone, Debug)]
+pub struct Schema {
+    definitions: Map<String, Value>,
+    kinds: HashMap<(String, String, String), String>,
+}
+
+impl Schema {
+    /// Fetch the schema from the API server.
+    pub async fn fetch(client: &kube::Client) -> Result<Self, kube::Error> {
+        let request = http::Request::get("/openapi/v2").body(Vec::new())?;
+        let document: Value = serde_json::from_str(client.request_text(request).await?.as_str())?;
+        Ok(Self::from_document(document))
+    }
+
+    /// Extract the schema definitions from an OpenAPI (v2) document.
+    pub fn from_document(document: Value) -> Self {
+        let definitions = match document {
+            Value::Object(mut document) => match document.remove("definitions") {
+                Some(Value::Object(definitions)) => definitions,
+                _ => Map::new(),
+            },
+            _ => Map::new(),
+        };
+
+        let mut kinds = HashMap::new();
+
+        for (name, definition) in &definitions {
+            let gvks = definition
+                .get("x-kubernetes-group-version-kind")
+                .and_then(Value::as_array);
+
+            for gvk in gvks.into_iter().flatten() {
+                let field = |key: &str| gvk.get(key).and_then(Value::as_str).unwrap_or("");
+                kinds.insert(
+                    (
+                        field("group").to_string(),
+                        field("version").to_string(),
+                        field("kind").to_string(),
+                    ),
+                    name.clone(),
+                );
+            }
+        }
+
+        Schema { definitions, kinds }
+    }
+
+    /// Validate an object against the schema. Objects whose kind is not part of the schema are
+    /// not validated.
+    pub fn validate(&self, object: &Object) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        let key = (
+            object.api_resource.group.clone(),
+            object.api_resource.version.clone(),
+            object.api_resource.kind.clone(),
+        );
+
+        let definition = match self
+            .kinds
+            .get(&key)
+            .and_then(|name| self.definitions.get(name))
+        {
+            Some(definition) => definition,
+            None => return violations,
+        };
+
+        match serde_json::to_value(&object.dyn_object) {
+            Ok(value) => self.check(definition, &value, VecDeque::new(), &mut violations),
+            Err(error) => violations.push(Violation {
+                path: VecDeque::new(),
+                message: format!("Object cannot be serialized: {}", error),
+            }),
+        }
+
+        violations
+    }
+
+    fn check(
+        &self,
+        schema: &Value,
+        value: &Value,
+        path: VecDeque<String>,
+        violations: &mut Vec<Violation>,
+    ) {
+        // Null is accepted in place of any value.
+        if value.is_null() {
+            return;
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            // Quantities are declared as strings but numbers are accepted, too.
+            if reference.ends_with(".Quantity") {
+                if !(value.is_string() || value.is_number()) {
+                    violations.push(Violation {
+                        path,
+                        message: format!("Expected quantity, found {}", describe(value)),
+                    });
+                }
+
+                return;
+            }
+
+            let target = reference
+                .strip_prefix("#/definitions/")
+                .and_then(|name| self.definitions.get(name));
+
+            if let Some(target) = target {
+                self.check(target, value, path, violations);
+            }
+
+            return;
+        }
+
+        if schema.get("x-kubernetes-preserve-unknown-fields") == Some(&Value::Bool(true)) {
+            return;
+        }
+
+        let int_or_string = schema.get("format").and_then(Value::as_str) == Some("int-or-string")
+            || schema.get("x-kubernetes-int-or-string") == Some(&Value::Bool(true));
+
+        match (schema.get("type").and_then(Value::as_str), value) {
+            (Some("string"), Value::String(_)) => {}
+
+            (Some("string"), Value::Number(_)) if int_or_string => {}
+
+            (Some("integer"), Value::Number(number)) if number.is_i64() || number.is_u64() => {}
+
+            (Some("number"), Value::Number(_)) => {}
+
+            (Some("boolean"), Value::Bool(_)) => {}
+
+            (Some("array"), Value::Array(items)) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (index, item) in items.iter().enumerate() {
+                        let mut path = path.clone();
+                        path.push_back(format!("{}", index));
+                        self.check(item_schema, item, path, violations);
+                    }
+                }
+            }
+
+            (Some("object"), Value::Object(fields)) | (None, Value::Object(fields)) => {
+                self.check_fields(schema, fields, path, violations);
+            }
+
+            (None, _) if int_or_string => {
+                if !(value.is_string() || value.is_number()) {
+                    violations.push(Violation {
+                        path,
+                        message: format!("Expected integer or string, found {}", describe(value)),
+                    });
+                }
+            }
+
+            (None, _) => {}
+
+            (Some(expected), _) => {
+                violations.push(Violation {
+                    path,
+                    message: format!("Expected {}, found {}", expected, describe(value)),
+                });
+            }
+        }
+    }
+
+    fn check_fields(
+        &self,
+        schema: &Value,
+        fields: &Map<String, Value>,
+        path: VecDeque<String>,
+        violations: &mut Vec<Violation>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+
+        // Free-form objects can't be validated any further.
+        if properties.is_none() && additional.is_none() {
+            return;
+        }
+
+        let required = schema.get("required").and_then(Value::as_array);
+
+        for name in required.into_iter().flatten().filter_map(Value::as_str) {
+            if !fields.contains_key(name) {
+                violations.push(Violation {
+                    path: path.clone(),
+                    message: format!("Missing required field {:?}", name),
+                });
+            }
+        }
+
+        for (key, value) in fields {
+            let mut path = path.clone();
+            path.push_back(key.clone());
+
+            match (
+                properties.and_then(|properties| properties.get(key)),
+                additional,
+            ) {
+                (Some(field_schema), _) => self.check(field_schema, value, path, violations),
+
+                (None, Some(Value::Bool(true))) => {}
+
+                (None, Some(additional)) if additional.is_object() => {
+                    self.check(additional, value, path, violations)
+                }
+
+                (None, _) => violations.push(Violation {
+                    path,
+                    message: format!("Unknown field {:?}", key),
+                }),
+            }
+        }
+    }
+}
+
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Requirement::Equals(key, value) => write!(formatter, "{}={}", key, value),
+            Requirement::NotEquals(key, value) => write!(formatter, "{}!={}", key, value),
+            Requirement::Exists(key) => write!(formatter, "{}", key),
+            Requirement::NotExists(key) => write!(formatter, "!{}", key),
+        }
+    }
+}
+
+/// Label selector supplied by the user, e.g. `team=payments,env!=dev,tier`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+impl Selector {
+    /// Selector that matches everything
+    pub fn new() -> Self {
+        Selector {
+            requirements: Vec::new(),
+        }
+    }
+
+    /// Does the selector match everything?
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    /// Check whether the given labels satisfy all requirements of the selector.
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(labels))
+    }
+}
+
+impl Default for Selector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let requirements = self
+            .requirements
+            .iter()
+            .map(|requirement| requirement.to_string())
+            .collect::<Vec<String>>();
+
+        formatter.write_str(requirements.join(",").as_str())
+    }
+}
+
+/// Error that occurs when parsing a malformed label selector
+#[derive(Clone, Debug)]
+pub struct Error {
+    pub requirement: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "Invalid label selector requirement {:?}: {}",
+            self.requirement, self.reason
+        )
+    }
+}
+
+impl error::Error for Error {}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
+}
+
+fn check_name(name: &str, what: &str, allow_empty: bool) -> Result<(), String> {
+    if name.is_empty() {
+        return if allow_empty {
+            Ok(())
+        } else {
+            Err(format!("{} must not be empty", what))
+        };
+    }
+
+    if name.len() > 63 {
+        return Err(format!("{} must be at most 63 characters long", what));
+    }
+
+    let starts_and_ends_alphanumeric = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric());
+
+    if !(starts_and_ends_alphanumeric && name.chars().all(is_name_char)) {
+        return Err(format!(
+            "{} must consist of alphanumeric characters, '-', '_' or '.' and must start and end with an alphanumeric character",
+            what
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_key(key: &str) -> Result<(), String> {
+    match key.split_once('/') {
+        Some((prefix, name)) => {
+            let valid_prefix = !prefix.is_empty()
+                && prefix.len() <= 253
+                && prefix.split('.').all(|part| {
+                    !part.is_empty()
+                        && part
+                            .chars()
+                            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                        && !part.starts_with('-')
+                        && !part.ends_with('-')
+                });
+
+            if !valid_prefix {
+                return Err("Key prefix must be a DNS subdomain".to_string());
+            }
+
+            check_name(name, "Key name", false)
+        }
+
+        None => check_name(key, "Key", false),
+    }
+}
+
+fn parse_requirement(input: &str) -> Result<Requirement, String> {
+    if input.contains(|c: char| c == '(' || c == ')') || input.contains(" in ") {
+        return Err("Set-based requirements are not supported".to_string());
+    }
+
+    let requirement = if let Some(key) = input.strip_prefix('!') {
+        Requirement::NotExists(key.trim().to_string())
+    } else if let Some((key, value)) = input.split_once("!=") {
+        Requirement::NotEquals(key.trim().to_string(), value.trim().to_string())
+    } else if let Some((key, value)) = input.split_once("==") {
+        Requirement::Equals(key.trim().to_string(), value.trim().to_string())
+    } else if let Some((key, value)) = input.split_once('=') {
+        Requirement::Equals(key.trim().to_string(), value.trim().to_string())
+    } else {
+        Requirement::Exists(input.to_string())
+    };
+
+    match &requirement {
+        Requirement::Equals(key, value) | Requirement::NotEquals(key, value) => {
+            check_key(key)?;
+            check_name(value, "Value", true)?;
+        }
+
+        Requirement::Exists(key) | Requirement::NotExists(key) => {
+            check_key(key)?;
+        }
+    }
+
+    Ok(requirement)
+}
+
+impl FromStr for Selector {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let requirements = input
+            .split(',')
+            .map(|requirement| {
+                let requirement = requirement.trim();
+                parse_requirement(requirement).map_err(|reason| Error {
+                    requirement: requirement.to_string(),
+                    reason,
+                })
+            })
+            .collect::<Result<Vec<Requirement>, Error>>()?;
+
+        Ok(Selector { requirements })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn equality_requires_the_value() {
+        let selector: Selector = "team=payments,app.kubernetes.io/part-of==shop"
+            .parse()
+            .unwrap();
+
+        assert!(selector.matches(&labels(&[
+            ("team", "payments"),
+            ("app.kubernetes.io/part-of", "shop")
+        ])));
+        assert!(!selector.matches(&labels(&[
+            ("team", "search"),
+            ("app.kubernetes.io/part-of", "shop")
+        ])));
+        assert!(!selector.matches(&labels(&[("team", "payments")])));
+    }
+
+    #[test]
+    fn inequality_matches_other_values_and_missing_keys() {
+        let selector: Selector = "env!=dev".parse().unwrap();
+
+        assert!(selector.matches(&labels(&[("env", "prod")])));
+        assert!(selector.matches(&labels(&[])));
+        assert!(!selector.matches(&labels(&[("env", "dev")])));
+    }
+
+    #[test]
+    fn existence_checks_the_key_only() {
+        let selector: Selector = "tier, !canary".parse().unwrap();
+
+        assert!(selector.matches(&labels(&[("tier", "")])));
+        assert!(!selector.matches(&labels(&[("tier", "web"), ("canary", "true")])));
+        assert!(!selector.matches(&labels(&[])));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let selector: Selector = "team == payments,env!=dev,tier,!canary".parse().unwrap();
+
+        assert_eq!(selector.to_string(), "team=payments,env!=dev,tier,!canary");
+        assert_eq!(selector.to_string().parse::<Selector>().unwrap(), selector);
+    }
+
+    #[test]
+    fn set_based_requirements_are_rejected() {
+        for input in &["env in (dev,prod)", "env notin (dev)", "(env)"] {
+            let error = input.parse::<Selector>().unwrap_err();
+            assert_eq!(error.reason, "Set-based requirements are not supported");
+        }
+    }
+
+    #[test]
+    fn malformed_requirements_are_rejected() {
+        assert!("".parse::<Selector>().is_err());
+        assert!("team=".parse::<Selector>().is_ok());
+        assert!("-team=payments".parse::<Selector>().is_err());
+        assert!("Example.com/team=payments".parse::<Selector>().is_err());
+        assert!("team=pay ments".parse::<Selector>().is_err());
+    }
+}
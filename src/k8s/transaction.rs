@@ -1,17 +1,32 @@
+use crate::k8s::annotations::Annotations;
+use crate::k8s::api_resource::TryToApiResource;
+use crate::k8s::labels::Labels;
+use crate::k8s::merge;
 use crate::meta::CRATE_NAME;
 use crate::objects::Object;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
 use kube::api;
 use kube::core::DynamicObject;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::error;
 use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use std::time::Instant;
 
-#[derive(Debug)]
+/// Interval at which a deleted object is polled while waiting for it to disappear
+const REMOVAL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Maximum time to wait for a deleted object to disappear
+const REMOVAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
 pub enum Action {
     Create,
     Apply,
     Delete,
+    Recreate,
 }
 
 impl fmt::Display for Action {
@@ -40,6 +55,30 @@ pub enum Error {
         action: Action,
         object_name: String,
     },
+
+    Timeout {
+        action: Action,
+        object_name: String,
+    },
+
+    /// The object exists but is not labelled as part of the release it is to be deleted from
+    NotManaged {
+        kind: String,
+        object_name: String,
+    },
+}
+
+impl Error {
+    /// Was the error caused by an attempt to change an immutable field?
+    pub fn is_immutable_field_change(&self) -> bool {
+        match self {
+            Error::Kube {
+                kube_error: kube::Error::Api(response),
+                ..
+            } => response.code == 422 && response.message.contains("field is immutable"),
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -66,6 +105,21 @@ impl fmt::Display for Error {
                 "Kubernetes error while trying to {} {}: {}",
                 action, object_name, kube_error
             ),
+
+            Error::Timeout {
+                action,
+                object_name,
+            } => write!(
+                formatter,
+                "Timed out while trying to {} {}",
+                action, object_name
+            ),
+
+            Error::NotManaged { kind, object_name } => write!(
+                formatter,
+                "Refusing to delete {} {} because it is not labelled as part of the release",
+                kind, object_name
+            ),
         }
     }
 }
@@ -79,10 +133,47 @@ impl error::Error for Error {
     }
 }
 
+/// Fail with a timeout error if the action on the given object doesn't complete in time. Without
+/// a timeout, the action may take as long as it needs.
+pub async fn with_timeout<T, SomeFuture>(
+    timeout: Option<Duration>,
+    action: Action,
+    object: &Object,
+    future: SomeFuture,
+) -> Result<T, Error>
+where
+    SomeFuture: Future<Output = Result<T, Error>>,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return future.await,
+    };
+
+    match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result,
+        Err(_elapsed) => Err(Error::Timeout {
+            action,
+            object_name: object.name().cloned().unwrap_or_default(),
+        }),
+    }
+}
+
 pub async fn apply<SomeResource>(
     api: &kube::Api<SomeResource>,
     object: &SomeResource,
 ) -> Result<SomeResource, Error>
+where
+    SomeResource: kube::ResourceExt + Clone + fmt::Debug + Serialize + DeserializeOwned,
+{
+    apply_as(api, object, CRATE_NAME).await
+}
+
+/// Apply the object on behalf of the given field manager.
+pub async fn apply_as<SomeResource>(
+    api: &kube::Api<SomeResource>,
+    object: &SomeResource,
+    field_manager: &str,
+) -> Result<SomeResource, Error>
 where
     SomeResource: kube::ResourceExt + Clone + fmt::Debug + Serialize + DeserializeOwned,
 {
@@ -93,7 +184,7 @@ where
     let patched = api
         .patch(
             name.as_str(),
-            &api::PatchParams::apply(CRATE_NAME).force(),
+            &api::PatchParams::apply(field_manager).force(),
             &api::Patch::Apply(object.clone()),
         )
         .await
@@ -106,10 +197,198 @@ where
     Ok(patched)
 }
 
-pub async fn apply_object(client: kube::Client, object: &Object) -> Result<EndResult, Error> {
+pub async fn apply_object(
+    client: kube::Client,
+    object: &Object,
+    field_manager: &str,
+) -> Result<EndResult, Error> {
+    let api = kube::Api::default_namespaced_with(client, &object.api_resource);
+
+    let patched = apply_as(&api, &object.dyn_object, field_manager).await?;
+
+    Ok(EndResult {
+        client: api.into_client(),
+        result_object: patched,
+    })
+}
+
+/// Apply the status of the object through the status subresource, as regular requests ignore the
+/// status of resources which have one. Keeps the given result if the object has no status or its
+/// resource has no status subresource.
+pub async fn apply_status(
+    result: EndResult,
+    object: &Object,
+    field_manager: &str,
+) -> Result<EndResult, Error> {
+    let status = match object.dyn_object.data.get("status") {
+        Some(status) => status.clone(),
+        None => return Ok(result),
+    };
+
+    let api: kube::Api<DynamicObject> =
+        kube::Api::default_namespaced_with(result.client, &object.api_resource);
+
+    let name = object.name().ok_or(Error::NeedName {
+        object_rep: format!("{:?}", object.dyn_object),
+    })?;
+
+    let mut patch = DynamicObject::new(name.as_str(), &object.api_resource);
+    patch.data = serde_json::json!({ "status": status });
+
+    let patched = api
+        .patch_status(
+            name.as_str(),
+            &api::PatchParams::apply(field_manager).force(),
+            &api::Patch::Apply(patch),
+        )
+        .await;
+
+    match patched {
+        Ok(patched) => Ok(EndResult {
+            client: api.into_client(),
+            result_object: patched,
+        }),
+
+        // Without a status subresource, the status has been written along with the object.
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(EndResult {
+            client: api.into_client(),
+            result_object: result.result_object,
+        }),
+
+        Err(kube_error) => Err(Error::Kube {
+            kube_error,
+            action: Action::Apply,
+            object_name: name.clone(),
+        }),
+    }
+}
+
+/// Fetch the live version of the object, if it exists.
+pub async fn get_object(
+    client: kube::Client,
+    object: &Object,
+) -> Result<Option<DynamicObject>, kube::Error> {
+    let api: kube::Api<DynamicObject> =
+        kube::Api::default_namespaced_with(client, &object.api_resource);
+
+    let name = match object.name() {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    match api.get(name.as_str()).await {
+        Ok(live) => Ok(Some(live)),
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Set the given labels on the live version of the object, leaving its other labels untouched.
+pub async fn set_labels(
+    client: kube::Client,
+    object: &Object,
+    labels: &Labels,
+) -> Result<kube::Client, Error> {
+    let api: kube::Api<DynamicObject> =
+        kube::Api::default_namespaced_with(client, &object.api_resource);
+
+    let name = object.name().ok_or(Error::NeedName {
+        object_rep: format!("{:?}", object.dyn_object),
+    })?;
+
+    let mut labelled = DynamicObject::new(name.as_str(), &object.api_resource);
+    labels.apply_to(&mut labelled);
+    let patch = serde_json::json!({ "metadata": { "labels": labelled.metadata.labels } });
+
+    api.patch(
+        name.as_str(),
+        &api::PatchParams::default(),
+        &api::Patch::Merge(patch),
+    )
+    .await
+    .map_err(|kube_error| Error::Kube {
+        kube_error,
+        action: Action::Apply,
+        object_name: name.clone(),
+    })?;
+
+    Ok(api.into_client())
+}
+
+/// Strip the given labels and annotations from the live version of the object.
+pub async fn strip_metadata(
+    client: kube::Client,
+    object: &Object,
+    labels: &Labels,
+    annotations: &Annotations,
+) -> Result<kube::Client, Error> {
+    let api: kube::Api<DynamicObject> =
+        kube::Api::default_namespaced_with(client, &object.api_resource);
+
+    let name = object.name().ok_or(Error::NeedName {
+        object_rep: format!("{:?}", object.dyn_object),
+    })?;
+
+    let to_error = |kube_error| Error::Kube {
+        kube_error,
+        action: Action::Apply,
+        object_name: name.clone(),
+    };
+
+    let mut live = api.get(name.as_str()).await.map_err(to_error)?;
+    labels.apply_removal_to(&mut live);
+    annotations.apply_removal_to(&mut live);
+
+    // Replacing carries the resource version, so concurrent changes are not overwritten.
+    api.replace(name.as_str(), &api::PostParams::default(), &live)
+        .await
+        .map_err(to_error)?;
+
+    Ok(api.into_client())
+}
+
+/// Upgrade the object using a three-way merge between the last applied configuration, the desired
+/// configuration and the live object. Changes made to the live object outside of the
+/// configuration are preserved.
+pub async fn merge_object(
+    client: kube::Client,
+    object: &Object,
+    last_applied: &Object,
+    field_manager: &str,
+) -> Result<EndResult, Error> {
     let api = kube::Api::default_namespaced_with(client, &object.api_resource);
 
-    let patched = apply(&api, &object.dyn_object).await?;
+    let name = object.name().ok_or(Error::NeedName {
+        object_rep: format!("{:?}", object.dyn_object),
+    })?;
+
+    let kube_error = |kube_error| Error::Kube {
+        kube_error,
+        action: Action::Apply,
+        object_name: name.clone(),
+    };
+
+    let live = api.get(name.as_str()).await.map_err(kube_error)?;
+
+    let to_value = |object: &DynamicObject| {
+        serde_json::to_value(object).map_err(|error| kube_error(kube::Error::SerdeError(error)))
+    };
+
+    let patch = merge::three_way_patch(
+        &to_value(&last_applied.dyn_object)?,
+        &to_value(&object.dyn_object)?,
+        &to_value(&live)?,
+    );
+
+    let params = api::PatchParams {
+        field_manager: Some(field_manager.to_string()),
+        ..api::PatchParams::default()
+    };
+
+    let patched = api
+        .patch(name.as_str(), &params, &api::Patch::Merge(patch))
+        .await
+        .map_err(kube_error)?;
 
     Ok(EndResult {
         client: api.into_client(),
@@ -117,6 +396,28 @@ pub async fn apply_object(client: kube::Client, object: &Object) -> Result<EndRe
     })
 }
 
+/// Add an owner reference to the object. The reference is applied by a dedicated field manager,
+/// so later applies of the object itself leave it in place.
+pub async fn add_owner_reference(
+    client: kube::Client,
+    object: &Object,
+    owner: OwnerReference,
+    field_manager: &str,
+) -> Result<kube::Client, Error> {
+    let api = kube::Api::default_namespaced_with(client, &object.api_resource);
+
+    let name = object.name().ok_or(Error::NeedName {
+        object_rep: format!("{:?}", object.dyn_object),
+    })?;
+
+    let mut patch = DynamicObject::new(name.as_str(), &object.api_resource);
+    patch.metadata.owner_references = vec![owner];
+
+    apply_as(&api, &patch, format!("{}-owner", field_manager).as_str()).await?;
+
+    Ok(api.into_client())
+}
+
 pub async fn create<SomeResource>(
     api: &kube::Api<SomeResource>,
     object: &SomeResource,
@@ -151,6 +452,65 @@ pub async fn create_object(client: kube::Client, object: &Object) -> Result<EndR
     })
 }
 
+/// Send the action to the API server as a dry run, so it is validated and passes admission
+/// webhooks without being persisted. Returns the object as the server would have stored it, if the
+/// action results in one.
+pub async fn dry_run_object(
+    client: kube::Client,
+    action: Action,
+    object: &Object,
+    field_manager: &str,
+) -> Result<Option<DynamicObject>, Error> {
+    let api: kube::Api<DynamicObject> =
+        kube::Api::default_namespaced_with(client, &object.api_resource);
+
+    let name = object.name().ok_or(Error::NeedName {
+        object_rep: format!("{:?}", object.dyn_object),
+    })?;
+
+    let to_error = |kube_error| Error::Kube {
+        kube_error,
+        action,
+        object_name: name.clone(),
+    };
+
+    match action {
+        Action::Create => {
+            let params = api::PostParams {
+                dry_run: true,
+                field_manager: Some(field_manager.to_string()),
+            };
+
+            let result = api.create(&params, &object.dyn_object).await;
+            result.map(Some).map_err(to_error)
+        }
+
+        Action::Apply | Action::Recreate => {
+            let mut params = api::PatchParams::apply(field_manager).force();
+            params.dry_run = true;
+
+            let result = api
+                .patch(
+                    name.as_str(),
+                    &params,
+                    &api::Patch::Apply(object.dyn_object.clone()),
+                )
+                .await;
+            result.map(Some).map_err(to_error)
+        }
+
+        Action::Delete => {
+            let params = api::DeleteParams {
+                dry_run: true,
+                ..api::DeleteParams::default()
+            };
+
+            let result = api.delete(name.as_str(), &params).await;
+            result.map(|_| None).map_err(to_error)
+        }
+    }
+}
+
 pub async fn delete<SomeResource>(
     api: &kube::Api<SomeResource>,
     object: &SomeResource,
@@ -180,3 +540,188 @@ pub async fn delete_object(client: kube::Client, object: &Object) -> Result<kube
 
     Ok(api.into_client())
 }
+
+/// API for the resource type of the given object, as described by its type information
+fn dynamic_api(
+    client: kube::Client,
+    object: &DynamicObject,
+) -> Result<kube::Api<DynamicObject>, Error> {
+    let api_resource = object
+        .try_to_api_resource()
+        .ok_or_else(|| Error::NeedApiResource {
+            object: object.clone(),
+        })?;
+
+    Ok(kube::Api::default_namespaced_with(client, &api_resource))
+}
+
+pub async fn apply_dynamic(
+    client: kube::Client,
+    object: &DynamicObject,
+    field_manager: &str,
+) -> Result<EndResult, Error> {
+    let api = dynamic_api(client, object)?;
+
+    let patched = apply_as(&api, object, field_manager).await?;
+
+    Ok(EndResult {
+        client: api.into_client(),
+        result_object: patched,
+    })
+}
+
+pub async fn create_dynamic(
+    client: kube::Client,
+    object: &DynamicObject,
+) -> Result<EndResult, Error> {
+    let api = dynamic_api(client, object)?;
+
+    let result = create(&api, object).await?;
+
+    Ok(EndResult {
+        client: api.into_client(),
+        result_object: result,
+    })
+}
+
+pub async fn delete_dynamic(
+    client: kube::Client,
+    object: &DynamicObject,
+) -> Result<kube::Client, Error> {
+    let api = dynamic_api(client, object)?;
+
+    delete(&api, object).await?;
+
+    Ok(api.into_client())
+}
+
+async fn wait_for_removal<SomeResource>(
+    api: &kube::Api<SomeResource>,
+    name: &str,
+) -> Result<(), Error>
+where
+    SomeResource: Clone + fmt::Debug + DeserializeOwned,
+{
+    let started = Instant::now();
+
+    loop {
+        match api.get(name).await {
+            Err(kube::Error::Api(response)) if response.code == 404 => return Ok(()),
+
+            Err(kube_error) => {
+                return Err(Error::Kube {
+                    kube_error,
+                    action: Action::Delete,
+                    object_name: name.to_string(),
+                })
+            }
+
+            Ok(_) if started.elapsed() >= REMOVAL_TIMEOUT => {
+                return Err(Error::Timeout {
+                    action: Action::Delete,
+                    object_name: name.to_string(),
+                })
+            }
+
+            Ok(_) => tokio::time::sleep(REMOVAL_POLL_INTERVAL).await,
+        }
+    }
+}
+
+/// Delete the object, wait for it to disappear and create it anew. This is disruptive, but it is
+/// the only way to change immutable fields. An object which is gone already is only created, so a
+/// recreation that failed after the deletion can be rolled back by recreating the old object.
+pub async fn recreate_object(client: kube::Client, object: &Object) -> Result<EndResult, Error> {
+    let api = kube::Api::default_namespaced_with(client, &object.api_resource);
+
+    let name = object
+        .dyn_object
+        .metadata
+        .name
+        .as_ref()
+        .ok_or(Error::NeedName {
+            object_rep: format!("{:?}", object.dyn_object),
+        })?;
+
+    match delete(&api, &object.dyn_object).await {
+        Err(Error::Kube {
+            kube_error: kube::Error::Api(kube::error::ErrorResponse { code: 404, .. }),
+            ..
+        }) => {}
+        result => {
+            result?;
+            wait_for_removal(&api, name.as_str()).await?;
+        }
+    }
+    let result = create(&api, &object.dyn_object).await?;
+
+    Ok(EndResult {
+        client: api.into_client(),
+        result_object: result,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use std::convert::TryFrom;
+
+    /// Client for a server that doesn't exist, requests made with it can only fail.
+    fn unreachable_client() -> kube::Client {
+        let config = kube::Config::new("http://127.0.0.1:9".parse().unwrap());
+        kube::Client::try_from(config).expect("Client must be created")
+    }
+
+    /// Object without `apiVersion` and `kind`
+    fn untyped_object() -> DynamicObject {
+        DynamicObject {
+            types: None,
+            metadata: ObjectMeta {
+                name: Some("frontend".to_string()),
+                ..ObjectMeta::default()
+            },
+            data: serde_json::json!({ "data": { "key": "value" } }),
+        }
+    }
+
+    fn assert_needs_api_resource<T>(result: Result<T, Error>) {
+        match result {
+            Err(Error::NeedApiResource { object }) => {
+                assert_eq!(object.metadata.name.as_deref(), Some("frontend"))
+            }
+            Err(error) => panic!("Unexpected error: {}", error),
+            Ok(_) => panic!("Untyped object must be rejected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn untyped_objects_are_not_applied() {
+        let result = apply_dynamic(unreachable_client(), &untyped_object(), CRATE_NAME).await;
+        assert_needs_api_resource(result);
+    }
+
+    #[tokio::test]
+    async fn untyped_objects_are_not_created() {
+        let result = create_dynamic(unreachable_client(), &untyped_object()).await;
+        assert_needs_api_resource(result);
+    }
+
+    #[tokio::test]
+    async fn untyped_objects_are_not_deleted() {
+        let result = delete_dynamic(unreachable_client(), &untyped_object()).await;
+        assert_needs_api_resource(result);
+    }
+
+    #[tokio::test]
+    async fn typed_objects_have_an_api() {
+        let mut object = untyped_object();
+        object.types = Some(kube::core::TypeMeta {
+            api_version: "v1".to_string(),
+            kind: "ConfigMap".to_string(),
+        });
+
+        assert!(dynamic_api(unreachable_client(), &object).is_ok());
+        assert_needs_api_resource(dynamic_api(unreachable_client(), &untyped_object()));
+    }
+}
@@ -0,0 +1,8 @@
+pub mod identifier;
+pub mod k8s;
+pub mod manager;
+pub mod meta;
+pub mod metrics;
+pub mod objects;
+pub mod release;
+pub mod utils;
@@ -1,15 +1,27 @@
-mod identifier;
-mod k8s;
-mod manager;
-mod meta;
-mod objects;
-mod release;
-mod utils;
+mod audit;
+mod output;
+
+use able_seaman::identifier;
+use able_seaman::k8s;
+use able_seaman::manager;
+use able_seaman::meta;
+#[cfg(feature = "metrics")]
+use able_seaman::metrics;
+use able_seaman::objects;
+use able_seaman::release;
+use able_seaman::utils;
 
 use clap::Clap;
-use kube::Resource;
+use k8s::selector::Selector;
+use objects::overrides;
 use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use utils::git;
 
 #[derive(Clap, Clone, Debug)]
 enum Command {
@@ -19,74 +31,839 @@ enum Command {
         release_name: String,
 
         #[clap(
-            about = "Files or entire directories from which the Kubernetes objects should be read from (you can use '-' to read objects from stdin)"
+            about = "Files or entire directories from which the Kubernetes objects should be read from (you can use '-' to read objects from stdin or 'git+<repository>//<path>?ref=<ref>' to read them from a Git repository)"
         )]
         input_files: Vec<String>,
+
+        #[clap(flatten)]
+        input: ReleaseInput,
+
+        #[clap(flatten)]
+        deploy: DeployOptions,
+
+        #[clap(
+            long,
+            about = "Validate the objects against the cluster's OpenAPI schema before deploying"
+        )]
+        validate_schema: bool,
+
+        #[clap(
+            long,
+            about = "Warn about references to ConfigMaps, Secrets or ServiceAccounts that exist neither in the release nor in the cluster"
+        )]
+        check_references: bool,
+
+        #[clap(
+            long,
+            about = "Send the changes to the API server as a dry run and print the resulting objects"
+        )]
+        server_dry_run: bool,
+
+        #[clap(long, about = "Print the changed fields of upgraded objects")]
+        show_diff: bool,
+
+        #[clap(
+            long,
+            multiple_occurrences = true,
+            number_of_values = 1,
+            about = "After deploying, wait until an object reports a condition, e.g. 'Certificate/frontend:condition=Ready' (can be given multiple times)"
+        )]
+        wait_for: Vec<k8s::condition::WaitCondition>,
+
+        #[clap(
+            long,
+            default_value = "300",
+            about = "Seconds to wait for the conditions given by --wait-for"
+        )]
+        wait_timeout: u64,
+
+        #[clap(
+            long,
+            requires = "wait_for",
+            about = "Roll the release back if the conditions given by --wait-for are not met in time"
+        )]
+        rollback_on_failure: bool,
+
+        #[clap(
+            long,
+            about = "Ask for confirmation before applying creations, upgrades and deletions (requires a terminal)"
+        )]
+        step: bool,
     },
 
     #[clap(about = "Delete a release.")]
     Delete {
         #[clap(about = "Identifier of the release")]
         release_name: String,
+
+        #[clap(long, about = "Only show what would be deleted")]
+        dry_run: bool,
+
+        #[clap(short, long, about = "Delete without asking for confirmation")]
+        yes: bool,
+
+        #[clap(
+            long,
+            about = "Also delete the namespace, if it has been created by deploy --create-namespace"
+        )]
+        delete_namespace: bool,
+
+        #[clap(
+            long,
+            about = "Persist the rollback plan after every step, so an interrupted delete can be recovered"
+        )]
+        checkpoint: bool,
+
+        #[clap(
+            long,
+            about = "Look up the objects of the release by their labels, for releases deployed with --no-state"
+        )]
+        no_state: bool,
+
+        #[clap(
+            long,
+            about = "Keep the release state and its history, marking the release as deleted"
+        )]
+        keep_state: bool,
+    },
+
+    #[clap(about = "Periodically deploy a release and repair objects which have drifted.")]
+    Reconcile {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+
+        #[clap(
+            about = "Files or entire directories from which the Kubernetes objects should be read from"
+        )]
+        input_files: Vec<String>,
+
+        #[clap(flatten)]
+        input: ReleaseInput,
+
+        #[clap(flatten)]
+        deploy: DeployOptions,
+
+        #[clap(long, default_value = "60", about = "Seconds between reconciliations")]
+        interval: u64,
+    },
+
+    #[clap(about = "Bring existing objects under the management of a release.")]
+    Adopt {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+
+        #[clap(
+            about = "Files or entire directories from which the Kubernetes objects should be read from"
+        )]
+        input_files: Vec<String>,
+    },
+
+    #[clap(about = "Remove an object from a release without deleting it.")]
+    Orphan {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+
+        #[clap(about = "Kind of the object, e.g. Deployment")]
+        kind: String,
+
+        #[clap(about = "Name of the object")]
+        name: String,
+    },
+
+    #[clap(about = "Rename a release without touching its objects otherwise.")]
+    Rename {
+        #[clap(about = "Current identifier of the release")]
+        old_name: String,
+
+        #[clap(about = "New identifier of the release")]
+        new_name: String,
+    },
+
+    #[clap(about = "Remove a lock left behind by an interrupted operation.")]
+    Unlock {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+    },
+
+    #[clap(about = "Roll back an interrupted deploy or delete.")]
+    Recover {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+    },
+
+    #[clap(about = "Upgrade a release state written by an older version.")]
+    Migrate {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+    },
+
+    #[clap(about = "Print the state of a release as JSON, e.g. to back it up.")]
+    ExportState {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+    },
+
+    #[clap(about = "Restore the state of a release from JSON written by export-state.")]
+    ImportState {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+
+        #[clap(about = "File containing the state, defaults to standard input")]
+        input_file: Option<String>,
+    },
+
+    #[clap(about = "Check whether a release could be deployed and report any problems.")]
+    Doctor {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+
+        #[clap(
+            about = "Files or entire directories from which the Kubernetes objects should be read from"
+        )]
+        input_files: Vec<String>,
+    },
+
+    #[clap(about = "Show what deploying a release would change.")]
+    Diff {
+        #[clap(about = "Identifier of the release")]
+        release_name: String,
+
+        #[clap(
+            about = "Files or entire directories from which the Kubernetes objects should be read from"
+        )]
+        input_files: Vec<String>,
+
+        #[clap(flatten)]
+        input: ReleaseInput,
+
+        #[clap(
+            long,
+            about = "Compare with the objects found by their labels, for releases deployed with --no-state"
+        )]
+        no_state: bool,
+
+        #[clap(
+            long,
+            multiple_occurrences = true,
+            number_of_values = 1,
+            about = "Only show changes to the object 'Kind/name' (can be given multiple times)"
+        )]
+        only: Vec<identifier::Target>,
+
+        #[clap(
+            long,
+            about = "Exit with code 2 if deploying the release would change anything"
+        )]
+        exit_code: bool,
     },
 
     #[clap(about = "Verify a release.")]
     Verify {
         #[clap(about = "Identifier of the release")]
         release_name: String,
+
+        #[clap(
+            long,
+            about = "Only verify objects matching the label selector (e.g. 'team=payments,env=prod')"
+        )]
+        selector: Option<Selector>,
+
+        #[clap(
+            long,
+            about = "Fail if any API group cannot be discovered instead of skipping it"
+        )]
+        fail_on_discovery_error: bool,
+
+        #[clap(
+            long,
+            multiple_occurrences = true,
+            number_of_values = 1,
+            about = "Only verify the object 'Kind/name' (can be given multiple times)"
+        )]
+        only: Vec<identifier::Target>,
+
+        #[clap(
+            long = "list-key",
+            multiple_occurrences = true,
+            number_of_values = 1,
+            about = "Match the items of lists in fields named 'field' by their 'key' instead of their position, given as 'field=key' (can be given multiple times)"
+        )]
+        list_keys: Vec<String>,
+
+        #[clap(
+            long,
+            about = "Compare all lists by position, except for those given by --list-key"
+        )]
+        strict_lists: bool,
+
+        #[clap(
+            long,
+            about = "Record the time and outcome of the verification on the release state"
+        )]
+        record_verify: bool,
+
+        #[clap(
+            long,
+            default_value = "8",
+            about = "Number of resources to list concurrently when looking for objects of the release"
+        )]
+        verify_concurrency: usize,
+    },
+
+    #[clap(about = "List releases.")]
+    List {
+        #[clap(
+            long,
+            about = "Only list releases with objects matching the label selector (e.g. 'team=payments,env=prod')"
+        )]
+        selector: Option<Selector>,
     },
 }
 
+// Flags which decide how the objects of a release are read and transformed, shared by the commands
+// which deploy a release or show what deploying it would change. A doc comment would replace the
+// about of the commands it is flattened into.
+#[derive(Clap, Clone, Debug)]
+struct ReleaseInput {
+    #[clap(
+        long = "set",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Override a field of the objects using '[Kind/name:]path.to.field=value' (can be given multiple times)"
+    )]
+    overrides: Vec<overrides::Override>,
+
+    #[clap(
+        long = "values",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Merge a YAML file mapping 'Kind/name' to partial objects into the objects, applied before --set (can be given multiple times, later files win)"
+    )]
+    values_files: Vec<String>,
+
+    #[clap(
+        long = "base",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Files or directories with base objects onto which the input files are merged as an overlay (can be given multiple times)"
+    )]
+    base_files: Vec<String>,
+
+    #[clap(
+        long,
+        about = "Expand environment variables ($VAR, ${VAR} or ${VAR:-default}) in the input files"
+    )]
+    expand_env: bool,
+
+    #[clap(
+        long,
+        about = "Suffix the names of ConfigMaps and Secrets with a hash of their contents, make them immutable and update references to them"
+    )]
+    hash_config: bool,
+
+    #[clap(
+        long,
+        about = "Add the recommended labels app.kubernetes.io/managed-by and app.kubernetes.io/instance to every object"
+    )]
+    recommended_labels: bool,
+}
+
+impl ReleaseInput {
+    /// Read the objects of the release from the input files and transform them as the flags ask.
+    fn read(
+        &self,
+        output: &output::Output,
+        release_name: String,
+        input_files: Vec<String>,
+    ) -> Result<release::Release, GeneralError> {
+        let values = self
+            .values_files
+            .iter()
+            .map(|path| objects::values::Values::from_path(Path::new(path)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut builder = ingest_from_file_args(output, input_files, self.expand_env)?;
+
+        if !self.base_files.is_empty() {
+            let base = ingest_from_file_args(output, self.base_files.clone(), self.expand_env)?;
+            builder = builder.with_base(base, &release::verify::ListKeys::default())?;
+        }
+
+        let release = builder
+            .finish(release_name)
+            .with_values(&values)?
+            .with_overrides(&self.overrides)?;
+
+        let release = if self.hash_config {
+            release.with_hashed_config()?
+        } else {
+            release
+        };
+
+        let release = if self.recommended_labels {
+            release.with_recommended_labels()
+        } else {
+            release
+        };
+
+        Ok(release)
+    }
+}
+
+// Flags which configure how a release is deployed, shared by the commands which deploy a release.
+#[derive(Clap, Clone, Debug)]
+struct DeployOptions {
+    #[clap(
+        long,
+        default_value = "30",
+        about = "Seconds to wait for new CustomResourceDefinitions to become available"
+    )]
+    crd_timeout: u64,
+
+    #[clap(
+        long,
+        about = "Delete and recreate objects whose upgrade changes immutable fields (disruptive)"
+    )]
+    force_recreate: bool,
+
+    #[clap(
+        long = "allow-kind",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Only permit objects of this kind in the release (can be given multiple times)"
+    )]
+    allowed_kinds: Vec<String>,
+
+    #[clap(
+        long = "deny-kind",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Forbid objects of this kind in the release (can be given multiple times)"
+    )]
+    denied_kinds: Vec<String>,
+
+    #[clap(
+        long,
+        about = "Persist the rollback plan after every step, so an interrupted deploy can be recovered"
+    )]
+    checkpoint: bool,
+
+    #[clap(
+        long,
+        about = "Field manager used for server-side apply (defaults to able-seaman)"
+    )]
+    field_manager: Option<String>,
+
+    #[clap(
+        long,
+        about = "Seconds after which an action on a single object is considered failed"
+    )]
+    request_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        about = "Seconds after which the whole deploy is considered failed and rolled back"
+    )]
+    deploy_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "ssa",
+        possible_values = &["ssa", "three-way"],
+        about = "How upgraded objects are applied: server-side apply or a three-way merge which preserves manual changes"
+    )]
+    apply_strategy: String,
+
+    #[clap(
+        long,
+        about = "Make the release state the owner of namespaced objects, so they are garbage collected when the state is deleted"
+    )]
+    owner_references: bool,
+
+    #[clap(
+        long,
+        default_value = "10",
+        about = "Maximum number of objects an upgrade may delete without --confirm-prune"
+    )]
+    prune_limit: usize,
+
+    #[clap(
+        long,
+        about = "Allow upgrades to delete more objects than --prune-limit"
+    )]
+    confirm_prune: bool,
+
+    #[clap(
+        long = "prune-kind",
+        multiple_occurrences = true,
+        min_values = 0,
+        require_delimiter = true,
+        about = "Only delete removed objects of this kind, other removed objects are kept and reported; without a kind nothing is deleted (can be given multiple times)"
+    )]
+    prune_kinds: Option<Vec<String>>,
+
+    #[clap(
+        long,
+        about = "Limit the objects created, upgraded or deleted to this many per second on average"
+    )]
+    qps: Option<f64>,
+
+    #[clap(
+        long,
+        default_value = "10",
+        about = "Number of objects that may be changed at once despite --qps"
+    )]
+    burst: u32,
+
+    #[clap(long, about = "Create the namespace if it does not exist")]
+    create_namespace: bool,
+
+    #[clap(
+        long,
+        about = "Leave out CustomResourceDefinitions, e.g. when they are managed elsewhere"
+    )]
+    skip_crds: bool,
+
+    #[clap(
+        long,
+        about = "Keep the release locked when a failed deploy could not be rolled back"
+    )]
+    retain_lock_on_failure: bool,
+
+    #[clap(
+        long,
+        about = "Write every created or upgraded object to <dir>/<release>/<namespace>/<kind>.<group>-<name>.yaml"
+    )]
+    output_dir: Option<String>,
+
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Only deploy the object 'Kind/name' and leave all other objects of the release as they are (can be given multiple times)"
+    )]
+    only: Vec<identifier::Target>,
+
+    #[clap(
+        long,
+        about = "Don't keep a release state, but look up the objects of the release by their labels"
+    )]
+    no_state: bool,
+
+    #[clap(
+        long,
+        about = "Deploy objects even if they declare a namespace other than the target namespace"
+    )]
+    allow_namespace_override: bool,
+
+    #[clap(
+        long,
+        about = "Check that the current user may deploy every object before deploying anything"
+    )]
+    check_rbac: bool,
+
+    #[clap(
+        long = "ignore-annotation",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Disregard this annotation when deciding whether the release has changed (can be given multiple times)"
+    )]
+    ignored_annotations: Vec<String>,
+
+    #[clap(
+        long = "ignore-label",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        about = "Disregard this label when deciding whether the release has changed (can be given multiple times)"
+    )]
+    ignored_labels: Vec<String>,
+
+    #[clap(
+        long,
+        about = "Disregard the status of objects when deciding whether the release has changed"
+    )]
+    ignore_status: bool,
+}
+
+impl DeployOptions {
+    /// Configure the manager to deploy as the flags ask.
+    fn configure(self, manager: manager::Manager) -> manager::Manager {
+        manager
+            .with_crd_wait(
+                Duration::from_secs(1),
+                Duration::from_secs(self.crd_timeout),
+            )
+            .with_force_recreate(self.force_recreate)
+            .with_kind_policy(
+                release::policy::KindPolicy::new()
+                    .allow(self.allowed_kinds)
+                    .deny(self.denied_kinds),
+            )
+            .with_checkpoints(self.checkpoint)
+            .with_request_timeout(self.request_timeout.map(Duration::from_secs))
+            .with_deploy_timeout(self.deploy_timeout.map(Duration::from_secs))
+            .with_apply_strategy(match self.apply_strategy.as_str() {
+                "three-way" => release::plan::ApplyStrategy::ThreeWay,
+                _ => release::plan::ApplyStrategy::ServerSide,
+            })
+            .with_owner_references(self.owner_references)
+            .with_create_namespace(self.create_namespace)
+            .with_skip_crds(self.skip_crds)
+            .with_retain_lock_on_failure(self.retain_lock_on_failure)
+            .with_output_dir(self.output_dir.map(PathBuf::from))
+            .with_only(self.only)
+            .with_no_state(self.no_state)
+            .with_allow_namespace_override(self.allow_namespace_override)
+            .with_check_rbac(self.check_rbac)
+            .with_normalization(
+                release::normalize::Normalization::new()
+                    .ignore_injected_metadata()
+                    .ignore_annotations(self.ignored_annotations)
+                    .ignore_labels(self.ignored_labels)
+                    .ignore_status(self.ignore_status),
+            )
+            .with_prune_limit(if self.confirm_prune {
+                None
+            } else {
+                Some(self.prune_limit)
+            })
+            .with_prune_kinds(self.prune_kinds)
+            .with_rate_limit(
+                self.qps
+                    .map(|qps| utils::rate_limit::RateLimiter::new(qps, self.burst)),
+            )
+            .with_field_manager(
+                self.field_manager
+                    .unwrap_or_else(|| meta::CRATE_NAME.to_string()),
+            )
+    }
+}
+
 #[derive(Clap, Clone, Debug)]
 struct Options {
-    #[clap(short, long)]
+    #[clap(
+        short,
+        long,
+        about = "Namespace to operate in (defaults to the namespace of the current kubeconfig context)"
+    )]
     namespace: Option<String>,
 
+    #[clap(
+        long,
+        about = "Namespace in which to keep the state and lock of releases (defaults to --namespace)"
+    )]
+    release_namespace: Option<String>,
+
+    #[cfg(feature = "metrics")]
+    #[clap(
+        long,
+        about = "Push metrics to the Prometheus Pushgateway at the given URL"
+    )]
+    push_metrics: Option<String>,
+
+    #[clap(short, long, about = "Only print errors and warnings")]
+    quiet: bool,
+
+    #[clap(
+        long,
+        about = "Append a line of JSON describing every deploy, delete and recover to this file"
+    )]
+    audit_log: Option<String>,
+
+    #[clap(
+        long,
+        about = "Don't verify the certificate of the API server (insecure)"
+    )]
+    insecure_skip_tls_verify: bool,
+
+    #[clap(
+        long,
+        about = "PEM file with certificate authorities to trust in addition to those of the kubeconfig"
+    )]
+    certificate_authority: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "921600",
+        about = "Bytes of release state to store per ConfigMap, larger states are split across several"
+    )]
+    max_object_size: usize,
+
     #[clap(subcommand)]
     command: Command,
 }
 
 fn ingest_from_file_args<F: IntoIterator<Item = String>>(
+    output: &output::Output,
     files: F,
-) -> Result<objects::Objects, objects::BuilderError> {
-    let mut builder = objects::Builder::new();
+    expand_env: bool,
+) -> Result<release::Builder, GeneralError> {
+    let mut builder = release::Builder::new().with_env_expansion(expand_env);
 
     for ref file in files {
         if file == "-" {
-            builder.read_objects(io::stdin())?;
+            builder.add_objects(io::stdin())?;
+        } else if git::Source::is_source(file) {
+            let checkout = file.parse::<git::Source>()?.fetch()?;
+            builder.add_objects_from_path(checkout.path().as_path())?;
         } else {
-            builder.read_objects_from_path(Path::new(file))?;
+            builder.add_objects_from_path(Path::new(file))?;
         }
     }
 
-    Ok(builder.finish())
+    for skipped in builder.skipped() {
+        output.warning(format_args!(
+            "Skipped {}, which is not a manifest",
+            skipped.display()
+        ));
+    }
+
+    if builder.is_empty() {
+        output.warning("The input files do not contain any objects");
+    }
+
+    Ok(builder)
 }
 
-fn print_pretty_release_plan(plan: &release::plan::ReleasePlan) {
-    if !plan.creations.is_empty() {
-        println!("Creations: {}", plan.creations.len());
-        for creation in &plan.creations {
-            if let Some(name) = &creation.new.meta().name {
-                println!("+ {}", name)
+fn print_pretty_release_plan(output: &output::Output, plan: &release::plan::ReleasePlan) {
+    let summary = plan.summary();
+
+    for (title, symbol, identifiers) in [
+        ("Creations", '+', &summary.creations),
+        ("Upgrades", '~', &summary.upgrades),
+        ("Deletions", '-', &summary.deletions),
+    ] {
+        if !identifiers.is_empty() {
+            output.summary(format_args!("{}: {}", title, identifiers.len()));
+            for identifier in identifiers {
+                output.summary(format_args!("{} {}", symbol, identifier))
             }
         }
     }
 
-    if !plan.upgrades.is_empty() {
-        println!("Upgrades: {}", plan.upgrades.len());
-        for upgrade in &plan.upgrades {
-            if let Some(name) = &upgrade.new.meta().name {
-                println!("~ {}", name)
-            }
+    for cycle in plan.cycles() {
+        output.warning(format_args!(
+            "{}; they are handled in the order of their kinds",
+            cycle
+        ));
+    }
+}
+
+/// Ask the user a yes/no question on the terminal. Anything but yes counts as no.
+fn confirm(question: &str) -> Result<bool, GeneralError> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+/// Asks on the terminal before each phase of a plan is executed
+#[derive(Debug)]
+struct TerminalStep;
+
+impl release::plan::StepConfirmation for TerminalStep {
+    fn confirm(&self, phase: release::plan::Phase, identifiers: &[identifier::Identifier]) -> bool {
+        eprintln!("Next are {} of {} objects:", phase, identifiers.len());
+        for identifier in identifiers {
+            eprintln!("  {}", identifier);
         }
+
+        confirm("Continue?").unwrap_or(false)
     }
+}
 
-    if !plan.deletions.is_empty() {
-        println!("Deletions: {}", plan.deletions.len());
-        for deletion in &plan.deletions {
-            if let Some(name) = &deletion.old.meta().name {
-                println!("- {}", name)
-            }
+fn print_unchanged(output: &output::Output, reason: manager::UnchangedReason) {
+    match reason {
+        manager::UnchangedReason::Identical => output.summary("Release is unchanged."),
+        manager::UnchangedReason::Normalized => {
+            output.summary("Release is unchanged, apart from disregarded differences.")
+        }
+    }
+}
+
+/// Print the fields that change for each upgraded object.
+fn print_upgrade_diffs(output: &output::Output, plan: &release::plan::ReleasePlan) {
+    for upgrade in plan.upgrades() {
+        let changes = match (
+            serde_json::to_value(&upgrade.old_object().dyn_object),
+            serde_json::to_value(&upgrade.new_object().dyn_object),
+        ) {
+            (Ok(old), Ok(new)) => objects::diff::diff(&old, &new),
+            _ => continue,
+        };
+
+        if changes.is_empty() {
+            continue;
+        }
+
+        if let Some(identifier) = upgrade.new_object().identifier() {
+            output.summary(format_args!("~ {}", identifier));
+        }
+
+        for change in changes {
+            output.summary(format_args!("    {}", change));
+        }
+    }
+}
+
+fn print_verification_report(output: &output::Output, report: &manager::VerificationReport) {
+    if report.is_ok() {
+        output.summary("Release is in sync.");
+        return;
+    }
+
+    if !report.missing.is_empty() {
+        output.summary(format_args!("Missing: {}", report.missing.len()));
+        for identifier in &report.missing {
+            output.summary(format_args!("! {}", identifier));
+        }
+    }
+
+    if !report.mismatched_annotations.is_empty() {
+        output.summary(format_args!(
+            "Mismatching annotations: {}",
+            report.mismatched_annotations.len()
+        ));
+        for mismatch in &report.mismatched_annotations {
+            let identifier = &mismatch.identifier;
+            output.summary(format_args!("~ {}", identifier));
+        }
+    }
+
+    if !report.mismatched_labels.is_empty() {
+        output.summary(format_args!(
+            "Mismatching labels: {}",
+            report.mismatched_labels.len()
+        ));
+        for mismatch in &report.mismatched_labels {
+            let identifier = &mismatch.identifier;
+            output.summary(format_args!("~ {}", identifier));
+        }
+    }
+
+    if !report.mismatched_data.is_empty() {
+        output.summary(format_args!(
+            "Mismatching data: {}",
+            report.mismatched_data.len()
+        ));
+        for (identifier, mismatch) in &report.mismatched_data {
+            let path = mismatch
+                .path
+                .iter()
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(".");
+            let actual = mismatch
+                .actual
+                .as_ref()
+                .map_or_else(|| "nothing".to_string(), |actual| actual.to_string());
+            output.summary(format_args!(
+                "~ {} at {}: expected {}, found {}",
+                identifier, path, mismatch.expected, actual
+            ));
         }
     }
 }
@@ -94,60 +871,679 @@ fn print_pretty_release_plan(plan: &release::plan::ReleasePlan) {
 async fn inner_main() -> Result<(), GeneralError> {
     let options = Options::parse();
 
+    #[cfg(feature = "metrics")]
+    let push_metrics = options.push_metrics.clone();
+
+    let result = run_command(options).await;
+
+    #[cfg(feature = "metrics")]
+    if let Some(url) = push_metrics {
+        tokio::task::spawn_blocking(move || metrics::push(url.as_str()))
+            .await
+            .unwrap_or_else(|error| panic!("{:#?}", error))?;
+    }
+
+    result
+}
+
+/// Deploy the release and repair any objects which have drifted since.
+async fn reconcile(
+    output: &output::Output,
+    manager: &manager::Manager,
+    input: &ReleaseInput,
+    release_name: String,
+    input_files: Vec<String>,
+) -> Result<(), GeneralError> {
+    let release = input.read(output, release_name.clone(), input_files)?;
+
+    let result = manager.deploy(&release).await?;
+
+    match &result {
+        manager::DeployResult::Unchanged { .. } => {}
+
+        manager::DeployResult::Installed { plan, .. } => {
+            output.summary("Release was installed.");
+            print_pretty_release_plan(output, plan);
+        }
+
+        manager::DeployResult::Upgraded { plan, .. } => {
+            output.summary("Release was upgraded.");
+            print_pretty_release_plan(output, plan);
+        }
+    }
+
+    for warning in result.warnings() {
+        output.warning(warning);
+    }
+
+    for identifier in manager.repair(release_name).await? {
+        output.summary(format_args!("Repaired {}", identifier));
+    }
+
+    Ok(())
+}
+
+/// Create a manager and make sure the cluster can be reached before doing anything else.
+async fn connect(
+    namespace: Option<String>,
+    release_namespace: Option<String>,
+    connection: &manager::ConnectionOptions,
+    max_object_size: usize,
+) -> Result<manager::Manager, GeneralError> {
+    let manager =
+        manager::Manager::new_with_connection(manager::NamespaceMode::new(namespace), connection)
+            .await?
+            .with_release_namespace(release_namespace)
+            .with_max_object_size(max_object_size);
+    manager.ping().await?;
+    Ok(manager)
+}
+
+async fn run_command(options: Options) -> Result<(), GeneralError> {
+    let output = output::Output::new(options.quiet);
+    let audit_log = audit::AuditLog::new(options.audit_log.as_deref().map(Path::new));
+    let connection = manager::ConnectionOptions {
+        insecure_skip_tls_verify: options.insecure_skip_tls_verify,
+        certificate_authority: options.certificate_authority.map(PathBuf::from),
+        proxy_url: None,
+    };
+
     match options.command {
         Command::Deploy {
             release_name,
             input_files,
+            input,
+            deploy,
+            validate_schema,
+            check_references,
+            server_dry_run,
+            show_diff,
+            wait_for,
+            wait_timeout,
+            rollback_on_failure,
+            step,
         } => {
-            let release =
-                release::Release::from_objects(release_name, ingest_from_file_args(input_files)?);
+            // Waiting for an answer that nobody can give would hang forever.
+            if step && !io::stdin().is_terminal() {
+                return Err(GeneralError::NoTerminal);
+            }
+
+            let release = input.read(&output, release_name, input_files)?;
+
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            let manager = deploy.configure(manager).with_step(if step {
+                Some(Arc::new(TerminalStep))
+            } else {
+                None
+            });
 
-            let ns_mode = manager::NamespaceMode::new(options.namespace);
-            let manager = manager::Manager::new(ns_mode).await?;
-            let result = manager.deploy(&release).await?;
+            if validate_schema {
+                manager.validate_schema(&release).await?;
+            }
 
-            match result {
-                manager::DeployResult::Unchanged => {
-                    println!("Release is unchanged.");
+            if check_references {
+                for reference in manager.find_dangling_references(&release).await? {
+                    output.warning(reference);
                 }
+            }
 
-                manager::DeployResult::Installed { plan } => {
-                    println!("Release was installed.");
-                    print_pretty_release_plan(&plan);
+            if server_dry_run {
+                let (objects, warnings) = manager.server_dry_run(&release).await?;
+
+                // Each document starts with its own separator.
+                for object in objects {
+                    print!("{}", serde_yaml::to_string(&object)?);
                 }
 
-                manager::DeployResult::Upgraded { plan } => {
-                    println!("Release was upgraded.");
-                    print_pretty_release_plan(&plan);
+                for warning in warnings {
+                    output.warning(warning);
                 }
+
+                output.summary("Server dry run, nothing has been deployed.");
+                return Ok(());
+            }
+
+            let result = manager.deploy(&release).await;
+
+            let name = release.name().as_str();
+            audit_log.append(&match &result {
+                Ok(manager::DeployResult::Unchanged { .. }) => {
+                    audit::Record::new(name, "deploy", "unchanged")
+                }
+                Ok(manager::DeployResult::Installed { plan, .. }) => {
+                    audit::Record::new(name, "deploy", "installed").with_plan(plan.summary())
+                }
+                Ok(manager::DeployResult::Upgraded { plan, .. }) => {
+                    audit::Record::new(name, "deploy", "upgraded").with_plan(plan.summary())
+                }
+                Err(error) => audit::Record::new(name, "deploy", "failed").with_error(error),
+            });
+
+            let result = result?;
+
+            match &result {
+                manager::DeployResult::Unchanged { reason, .. } => {
+                    print_unchanged(&output, *reason);
+                }
+
+                manager::DeployResult::Installed { plan, .. } => {
+                    output.summary(format_args!(
+                        "Release was installed with {} objects.",
+                        release.objects().len()
+                    ));
+                    print_pretty_release_plan(&output, plan);
+                }
+
+                manager::DeployResult::Upgraded { plan, .. } => {
+                    output.summary(format_args!(
+                        "Release was upgraded to {} objects.",
+                        release.objects().len()
+                    ));
+                    print_pretty_release_plan(&output, plan);
+
+                    if show_diff {
+                        print_upgrade_diffs(&output, plan);
+                    }
+                }
+            }
+
+            for warning in result.warnings() {
+                output.warning(warning);
+            }
+
+            if !wait_for.is_empty() {
+                let waited = manager
+                    .wait_for_conditions(&release, &wait_for, Duration::from_secs(wait_timeout))
+                    .await;
+
+                match waited {
+                    Err(error @ manager::Error::ConditionTimeout(_)) if rollback_on_failure => {
+                        manager.undo_deploy(release.name(), &result, &error).await?;
+                        output.summary("Release was rolled back.");
+                        audit_log.append(
+                            &audit::Record::new(release.name(), "rollback", "rolled-back")
+                                .with_error(&error),
+                        );
+                        return Err(error.into());
+                    }
+
+                    waited => waited?,
+                }
+
+                output.summary("All conditions are met.");
             }
         }
 
-        Command::Delete { release_name } => {
-            let ns_mode = manager::NamespaceMode::new(options.namespace);
-            let manager = manager::Manager::new(ns_mode).await?;
-            let possible_plan = manager.delete(release_name).await?;
+        Command::Delete {
+            release_name,
+            dry_run,
+            yes,
+            delete_namespace,
+            checkpoint,
+            no_state,
+            keep_state,
+        } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?
+            .with_checkpoints(checkpoint)
+            .with_no_state(no_state)
+            .with_keep_state(keep_state);
+
+            // Prompting only makes sense when someone is there to answer.
+            if !dry_run && !yes && io::stdin().is_terminal() {
+                if let Some(plan) = manager.delete(release_name.clone(), true).await? {
+                    if !confirm(&format!(
+                        "This will delete {} objects from release {}. Continue?",
+                        plan.deletions().len(),
+                        release_name
+                    ))? {
+                        output.summary("Nothing has been deleted.");
+                        return Ok(());
+                    }
+                }
+            }
+
+            let result = manager.delete(release_name.clone(), dry_run).await;
+
+            if !dry_run {
+                let name = release_name.as_str();
+                audit_log.append(&match &result {
+                    Ok(Some(plan)) => {
+                        audit::Record::new(name, "delete", "deleted").with_plan(plan.summary())
+                    }
+                    Ok(None) => audit::Record::new(name, "delete", "not-deployed"),
+                    Err(error) => audit::Record::new(name, "delete", "failed").with_error(error),
+                });
+            }
+
+            let possible_plan = result?;
+
+            if dry_run {
+                output.summary("Dry run, nothing has been deleted.");
+            }
 
             if let Some(plan) = possible_plan {
-                print_pretty_release_plan(&plan);
+                print_pretty_release_plan(&output, &plan);
+            }
+
+            if delete_namespace && !dry_run {
+                if manager
+                    .delete_created_namespace(release_name.as_str())
+                    .await?
+                {
+                    output.summary(format_args!(
+                        "Namespace {} was deleted.",
+                        manager.namespace()
+                    ));
+                } else {
+                    output.summary(format_args!(
+                        "Namespace {} was not created by the release and is kept.",
+                        manager.namespace()
+                    ));
+                }
             }
         }
 
-        Command::Verify { release_name } => {
-            let ns_mode = manager::NamespaceMode::new(options.namespace);
-            let manager = manager::Manager::new(ns_mode).await?;
-            manager.verify(release_name).await?;
+        Command::Reconcile {
+            release_name,
+            input_files,
+            input,
+            deploy,
+            interval,
+        } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+            let manager = deploy.configure(manager);
+
+            loop {
+                let result = reconcile(
+                    &output,
+                    &manager,
+                    &input,
+                    release_name.clone(),
+                    input_files.clone(),
+                )
+                .await;
+
+                // Errors are likely transient, the next reconciliation tries again.
+                if let Err(error) = result {
+                    output.warning(format_args!("Reconciliation failed: {}", error));
+                }
+
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+            }
+        }
+
+        Command::Adopt {
+            release_name,
+            input_files,
+        } => {
+            let release = ingest_from_file_args(&output, input_files, false)?.finish(release_name);
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            for identifier in manager.adopt(&release).await? {
+                output.summary(format_args!("Adopted {}", identifier));
+            }
+        }
+
+        Command::Orphan {
+            release_name,
+            kind,
+            name,
+        } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            match manager
+                .orphan(release_name, kind.as_str(), name.as_str())
+                .await?
+            {
+                Some(identifier) => output.summary(format_args!(
+                    "{} is no longer part of the release.",
+                    identifier
+                )),
+                None => output.summary("Release contains no such object."),
+            }
+        }
+
+        Command::Rename { old_name, new_name } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+            let count = manager.rename(old_name, new_name.clone()).await?;
+
+            output.summary(format_args!(
+                "Release was renamed to {} and {} objects were relabelled.",
+                new_name, count
+            ));
+        }
+
+        Command::Unlock { release_name } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            if manager.unlock(release_name.as_str()).await? {
+                output.summary("Lock was removed.");
+            } else {
+                output.summary("Release is not locked.");
+            }
+        }
+
+        Command::Recover { release_name } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            let result = manager.recover(release_name.clone()).await;
+
+            let name = release_name.as_str();
+            audit_log.append(&match &result {
+                Ok(true) => audit::Record::new(name, "recover", "rolled-back"),
+                Ok(false) => audit::Record::new(name, "recover", "nothing-to-recover"),
+                Err(error) => audit::Record::new(name, "recover", "failed").with_error(error),
+            });
+
+            if result? {
+                output.summary("Release was rolled back.");
+            } else {
+                output.summary("Nothing to recover.");
+            }
+        }
+
+        Command::Migrate { release_name } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            if manager.migrate(release_name).await? {
+                output.summary("Release state was migrated.");
+            } else {
+                output.summary("Nothing to migrate.");
+            }
+        }
+
+        Command::ExportState { release_name } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            match manager.export_state(release_name.as_str()).await? {
+                Some(json) => println!("{}", json),
+                None => return Err(manager::Error::NoRelease(release_name).into()),
+            }
+        }
+
+        Command::ImportState {
+            release_name,
+            input_file,
+        } => {
+            let json = match input_file {
+                Some(path) => std::fs::read_to_string(path)?,
+                None => io::read_to_string(io::stdin())?,
+            };
+
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+            manager.import_state(release_name, json.as_str()).await?;
+            output.summary("Release state was imported.");
+        }
+
+        Command::Doctor {
+            release_name,
+            input_files,
+        } => {
+            let release = ingest_from_file_args(&output, input_files, false)?.finish(release_name);
+
+            // Unlike other commands, an unreachable cluster is one of the reported problems.
+            let manager = manager::Manager::new_with_connection(
+                manager::NamespaceMode::new(options.namespace),
+                &connection,
+            )
+            .await?;
+            let report = manager.doctor(&release).await;
+
+            for check in &report.checks {
+                match &check.problem {
+                    None => println!("ok   {}", check.description),
+                    Some(problem) => println!("FAIL {}: {}", check.description, problem),
+                }
+            }
+
+            if !report.is_ok() {
+                return Err(GeneralError::ChecksFailed);
+            }
+        }
+
+        Command::Diff {
+            release_name,
+            input_files,
+            input,
+            no_state,
+            only,
+            exit_code,
+        } => {
+            let release = input.read(&output, release_name, input_files)?;
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?
+            .with_no_state(no_state)
+            .with_only(only);
+
+            let result = manager.plan(&release).await?;
+
+            match &result {
+                manager::DeployResult::Unchanged { reason, .. } => {
+                    print_unchanged(&output, *reason);
+                }
+
+                manager::DeployResult::Installed { plan, .. } => {
+                    output.summary("Release would be installed.");
+                    print_pretty_release_plan(&output, plan);
+                }
+
+                manager::DeployResult::Upgraded { plan, .. } => {
+                    output.summary("Release would be upgraded.");
+                    print_pretty_release_plan(&output, plan);
+                    print_upgrade_diffs(&output, plan);
+                }
+            }
+
+            for warning in result.warnings() {
+                output.warning(warning);
+            }
+
+            let pending = !matches!(result, manager::DeployResult::Unchanged { .. });
+            if exit_code && pending {
+                return Err(GeneralError::PendingChanges);
+            }
+        }
+
+        Command::Verify {
+            release_name,
+            selector,
+            fail_on_discovery_error,
+            only,
+            list_keys,
+            strict_lists,
+            record_verify,
+            verify_concurrency,
+        } => {
+            let mut keyed_lists = if strict_lists {
+                release::verify::ListKeys::empty()
+            } else {
+                release::verify::ListKeys::default()
+            };
+
+            for list_key in list_keys {
+                let (field, key) = list_key
+                    .split_once('=')
+                    .ok_or(GeneralError::MalformedListKey(list_key.clone()))?;
+                keyed_lists = keyed_lists.with_key(field.to_string(), key.to_string());
+            }
+
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?
+            .with_strict_discovery(fail_on_discovery_error)
+            .with_only(only)
+            .with_list_keys(keyed_lists)
+            .with_record_verify(record_verify)
+            .with_verify_concurrency(verify_concurrency);
+            let report = manager
+                .verify_report(release_name, &selector.unwrap_or_default())
+                .await?;
+
+            for skipped in &report.skipped {
+                output.warning(skipped);
+            }
+
+            if let Some(reason) = &report.unrecorded {
+                output.warning(format_args!("Failed to record verification: {}", reason));
+            }
+
+            print_verification_report(&output, &report);
+            report.into_result()?;
+        }
+
+        Command::List { selector } => {
+            let manager = connect(
+                options.namespace,
+                options.release_namespace,
+                &connection,
+                options.max_object_size,
+            )
+            .await?;
+
+            for release in manager.list(&selector.unwrap_or_default()).await? {
+                let mut line = release.name;
+
+                if release.deleted_at.is_some() {
+                    line.push_str(" (deleted)");
+                }
+
+                if let Some(verification) = release.last_verification {
+                    line.push_str(
+                        format!(
+                            " (last verified at {}: {})",
+                            verification.verified_at,
+                            verification.result()
+                        )
+                        .as_str(),
+                    );
+                }
+
+                println!("{}", line);
+            }
         }
     }
 
     Ok(())
 }
 
+/// Exit code used when a release that is not deployed is verified
+const EXIT_NOT_DEPLOYED: i32 = 5;
+
+/// Exit code used by `diff --exit-code` when deploying the release would change something
+const EXIT_PENDING_CHANGES: i32 = 2;
+
 #[tokio::main]
 async fn main() {
-    inner_main()
-        .await
-        .unwrap_or_else(|error| panic!("{:#?}", error))
+    match inner_main().await {
+        Ok(()) => {}
+
+        Err(GeneralError::VerificationError(error)) => match *error {
+            manager::VerificationError::NoDeployedRelease { release_name } => {
+                eprintln!("Release {} is not deployed", release_name);
+                std::process::exit(EXIT_NOT_DEPLOYED);
+            }
+
+            error => panic!("{:#?}", GeneralError::VerificationError(Box::new(error))),
+        },
+
+        Err(GeneralError::ManagerError(manager::Error::Unreachable { server, error })) => {
+            eprintln!(
+                "Cannot reach cluster at {}: {}; check your kubeconfig and current context",
+                server, error
+            );
+            std::process::exit(1);
+        }
+
+        Err(GeneralError::ManagerError(manager::Error::NotFound(identifiers))) => {
+            for identifier in identifiers {
+                eprintln!("{} does not exist", identifier);
+            }
+            std::process::exit(1);
+        }
+
+        // The failed checks have been reported already.
+        Err(GeneralError::ChecksFailed) => std::process::exit(1),
+
+        Err(GeneralError::PendingChanges) => std::process::exit(EXIT_PENDING_CHANGES),
+
+        Err(error) => panic!("{:#?}", error),
+    }
 }
 
 #[derive(Debug)]
@@ -158,8 +1554,73 @@ pub enum GeneralError {
     JSONError(serde_json::Error),
     ReleaseError(Box<release::Error>),
     BuildError(objects::BuilderError),
+    OverrideError(overrides::Error),
+    ValuesError(objects::values::Error),
+    GitError(git::Error),
     ManagerError(manager::Error),
     VerificationError(Box<manager::VerificationError>),
+    MalformedListKey(String),
+    ChecksFailed,
+    NoTerminal,
+    PendingChanges,
+    #[cfg(feature = "metrics")]
+    MetricsError(prometheus::Error),
+}
+
+impl std::fmt::Display for GeneralError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            GeneralError::KubeError(error) => write!(formatter, "Kubernetes error: {}", error),
+            GeneralError::IOError(error) => write!(formatter, "I/O error: {}", error),
+            GeneralError::YAMLError(error) => write!(formatter, "YAML error: {}", error),
+            GeneralError::JSONError(error) => write!(formatter, "JSON error: {}", error),
+            GeneralError::ReleaseError(error) => write!(formatter, "{}", error),
+            GeneralError::BuildError(error) => write!(formatter, "{}", error),
+            GeneralError::OverrideError(error) => write!(formatter, "{}", error),
+            GeneralError::ValuesError(error) => write!(formatter, "{}", error),
+            GeneralError::GitError(error) => write!(formatter, "{}", error),
+            GeneralError::ManagerError(error) => write!(formatter, "{}", error),
+            GeneralError::VerificationError(error) => write!(formatter, "{}", error),
+            GeneralError::ChecksFailed => write!(formatter, "Some checks failed"),
+            GeneralError::NoTerminal => {
+                write!(formatter, "--step requires an interactive terminal")
+            }
+            GeneralError::PendingChanges => write!(formatter, "The release has pending changes"),
+            GeneralError::MalformedListKey(input) => {
+                write!(
+                    formatter,
+                    "Invalid list key {:?}: Expected field=key",
+                    input
+                )
+            }
+            #[cfg(feature = "metrics")]
+            GeneralError::MetricsError(error) => write!(formatter, "Metrics error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for GeneralError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GeneralError::KubeError(error) => Some(error),
+            GeneralError::IOError(error) => Some(error),
+            GeneralError::YAMLError(error) => Some(error),
+            GeneralError::JSONError(error) => Some(error),
+            GeneralError::ReleaseError(error) => Some(error.as_ref()),
+            GeneralError::BuildError(error) => Some(error),
+            GeneralError::OverrideError(error) => Some(error),
+            GeneralError::ValuesError(error) => Some(error),
+            GeneralError::GitError(error) => Some(error),
+            GeneralError::ManagerError(error) => Some(error),
+            GeneralError::VerificationError(error) => Some(error.as_ref()),
+            GeneralError::MalformedListKey(_) => None,
+            GeneralError::ChecksFailed => None,
+            GeneralError::NoTerminal => None,
+            GeneralError::PendingChanges => None,
+            #[cfg(feature = "metrics")]
+            GeneralError::MetricsError(error) => Some(error),
+        }
+    }
 }
 
 impl From<std::io::Error> for GeneralError {
@@ -198,6 +1659,24 @@ impl From<objects::BuilderError> for GeneralError {
     }
 }
 
+impl From<git::Error> for GeneralError {
+    fn from(error: git::Error) -> GeneralError {
+        GeneralError::GitError(error)
+    }
+}
+
+impl From<overrides::Error> for GeneralError {
+    fn from(error: overrides::Error) -> GeneralError {
+        GeneralError::OverrideError(error)
+    }
+}
+
+impl From<objects::values::Error> for GeneralError {
+    fn from(error: objects::values::Error) -> GeneralError {
+        GeneralError::ValuesError(error)
+    }
+}
+
 impl From<manager::Error> for GeneralError {
     fn from(error: manager::Error) -> GeneralError {
         GeneralError::ManagerError(error)
@@ -209,3 +1688,10 @@ impl From<manager::VerificationError> for GeneralError {
         GeneralError::VerificationError(Box::new(error))
     }
 }
+
+#[cfg(feature = "metrics")]
+impl From<prometheus::Error> for GeneralError {
+    fn from(error: prometheus::Error) -> GeneralError {
+        GeneralError::MetricsError(error)
+    }
+}
@@ -1,18 +1,54 @@
+use crate::identifier;
 use crate::identifier::Identifier;
 use crate::k8s;
+use crate::k8s::access;
+use crate::k8s::annotations;
 use crate::k8s::annotations::WithAnnotations;
+use crate::k8s::api_resource;
+use crate::k8s::api_resource::ToApiResource;
+use crate::k8s::condition;
+use crate::k8s::labels;
 use crate::k8s::labels::WithLabels;
+use crate::k8s::lock;
+use crate::k8s::lock::Lock;
+use crate::k8s::openapi;
+use crate::k8s::selector::Selector;
 use crate::k8s::transaction;
+use crate::meta;
+use crate::metrics;
 use crate::objects;
 use crate::release;
+use crate::release::checkpoint;
+use crate::release::normalize;
 use crate::release::plan;
+use crate::release::policy;
+use crate::release::rollback;
 use crate::release::verify;
+use crate::utils::backoff::Backoff;
+use crate::utils::rate_limit::RateLimiter;
 use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::core::DynamicObject;
 use kube::Resource;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
 use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
 use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Longest interval at which objects are polled while waiting for their conditions
+const CONDITION_POLL_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Debug)]
 pub enum Error {
@@ -24,6 +60,225 @@ pub enum Error {
         state: ReleaseState,
         error: Box<release::Error>,
     },
+
+    SchemaViolations(Vec<(Identifier, openapi::Violation)>),
+
+    ForbiddenKinds(Vec<Identifier>),
+
+    Timeout,
+
+    Unreachable {
+        server: String,
+        error: kube::Error,
+    },
+
+    Checkpoint(checkpoint::Error),
+
+    Verification(Box<VerificationError>),
+
+    Repair(transaction::Error),
+
+    Orphan(transaction::Error),
+
+    NotFound(Vec<Identifier>),
+
+    Adopt(transaction::Error),
+
+    DryRun(transaction::Error),
+
+    PruneLimit {
+        count: usize,
+        limit: usize,
+    },
+
+    UnknownWaitTarget(condition::WaitCondition),
+
+    MissingKinds(Vec<String>),
+
+    NoRelease(String),
+
+    ReleaseExists(String),
+
+    Relabel(transaction::Error),
+
+    ConditionTimeout(Vec<condition::WaitCondition>),
+
+    Rollback(rollback::Error),
+
+    UnmatchedTargets(Vec<identifier::Target>),
+
+    NamespaceMismatch {
+        namespace: String,
+        identifiers: Vec<Identifier>,
+    },
+
+    CertificateAuthority {
+        path: PathBuf,
+        reason: String,
+    },
+
+    /// The connection to the cluster cannot be configured as requested.
+    ConnectionOptions(String),
+
+    AccessDenied(Vec<access::Access>),
+
+    Lock(lock::Error),
+}
+
+impl Error {
+    /// Did the operation fail without its changes being rolled back completely?
+    pub fn is_unrecovered(&self) -> bool {
+        match self {
+            Error::Release { error, .. } => matches!(
+                error.as_ref(),
+                release::Error::RollbackError { .. }
+                    | release::Error::CheckpointError {
+                        rollback: Some(_),
+                        ..
+                    }
+                    | release::Error::Aborted {
+                        rollback: Some(_),
+                        ..
+                    }
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// List targets as `Kind/name`, separated by commas.
+fn join_targets(targets: &[identifier::Target]) -> String {
+    targets
+        .iter()
+        .map(|target| target.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// List identifiers as `Kind name`, separated by commas.
+fn join_identifiers(identifiers: &[Identifier]) -> String {
+    identifiers
+        .iter()
+        .map(Identifier::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::Kube(error) => write!(formatter, "Kubernetes error: {}", error),
+            Error::ReleaseState(error) => write!(formatter, "{}", error),
+            Error::Release { error, .. } => write!(formatter, "{}", error),
+            Error::SchemaViolations(violations) => {
+                write!(formatter, "{} schema violations", violations.len())?;
+                for (identifier, violation) in violations {
+                    write!(formatter, "; {}: {}", identifier, violation)?;
+                }
+                Ok(())
+            }
+            Error::ForbiddenKinds(identifiers) => write!(
+                formatter,
+                "Kinds not permitted by policy: {}",
+                join_identifiers(identifiers)
+            ),
+            Error::Timeout => write!(formatter, "Deploy timed out"),
+            Error::Unreachable { server, error } => {
+                write!(formatter, "Cannot reach cluster at {}: {}", server, error)
+            }
+            Error::Checkpoint(error) => write!(formatter, "{}", error),
+            Error::Verification(error) => write!(formatter, "{}", error),
+            Error::Repair(error) => write!(formatter, "Repair failed: {}", error),
+            Error::Orphan(error) => write!(formatter, "Orphaning failed: {}", error),
+            Error::NotFound(identifiers) => write!(
+                formatter,
+                "Objects do not exist: {}",
+                join_identifiers(identifiers)
+            ),
+            Error::Adopt(error) => write!(formatter, "Adoption failed: {}", error),
+            Error::DryRun(error) => write!(formatter, "Server dry run failed: {}", error),
+            Error::NoRelease(name) => write!(formatter, "Release {} does not exist", name),
+            Error::ReleaseExists(name) => write!(formatter, "Release {} already exists", name),
+            Error::Relabel(error) => write!(formatter, "Relabelling failed: {}", error),
+            Error::MissingKinds(kinds) => write!(
+                formatter,
+                "CustomResourceDefinitions were skipped, but these kinds are not available: {}",
+                kinds.join(", ")
+            ),
+            Error::UnknownWaitTarget(condition) => write!(
+                formatter,
+                "Release contains no object to wait for: {}",
+                condition
+            ),
+            Error::ConditionTimeout(conditions) => write!(
+                formatter,
+                "Timed out waiting for {}",
+                conditions
+                    .iter()
+                    .map(|condition| condition.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Error::PruneLimit { count, limit } => write!(
+                formatter,
+                "Upgrade would delete {} objects, which exceeds the limit of {}",
+                count, limit
+            ),
+            Error::Rollback(error) => write!(formatter, "{}", error),
+            Error::UnmatchedTargets(targets) => {
+                write!(formatter, "No object matches {}", join_targets(targets))
+            }
+            Error::NamespaceMismatch {
+                namespace,
+                identifiers,
+            } => write!(
+                formatter,
+                "Objects declare a namespace other than {}: {}",
+                namespace,
+                join_identifiers(identifiers)
+            ),
+            Error::CertificateAuthority { path, reason } => write!(
+                formatter,
+                "Cannot read certificate authority {}: {}",
+                path.display(),
+                reason
+            ),
+            Error::ConnectionOptions(reason) => {
+                write!(formatter, "Invalid connection options: {}", reason)
+            }
+            Error::AccessDenied(denied) => write!(
+                formatter,
+                "Missing permissions: {}",
+                denied
+                    .iter()
+                    .map(|access| access.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Error::Lock(error) => write!(formatter, "{}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Kube(error) => Some(error),
+            Error::ReleaseState(error) => Some(error.as_ref()),
+            Error::Release { error, .. } => Some(error.as_ref()),
+            Error::Unreachable { error, .. } => Some(error),
+            Error::Checkpoint(error) => Some(error),
+            Error::Verification(error) => Some(error.as_ref()),
+            Error::Repair(error) => Some(error),
+            Error::Orphan(error) => Some(error),
+            Error::Adopt(error) => Some(error),
+            Error::Relabel(error) => Some(error),
+            Error::DryRun(error) => Some(error),
+            Error::Rollback(error) => Some(error),
+            Error::Lock(error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 impl From<kube::Error> for Error {
@@ -32,6 +287,30 @@ impl From<kube::Error> for Error {
     }
 }
 
+impl From<lock::Error> for Error {
+    fn from(error: lock::Error) -> Self {
+        Error::Lock(error)
+    }
+}
+
+impl From<checkpoint::Error> for Error {
+    fn from(error: checkpoint::Error) -> Self {
+        Error::Checkpoint(error)
+    }
+}
+
+impl From<VerificationError> for Error {
+    fn from(error: VerificationError) -> Self {
+        Error::Verification(Box::new(error))
+    }
+}
+
+impl From<rollback::Error> for Error {
+    fn from(error: rollback::Error) -> Self {
+        Error::Rollback(error)
+    }
+}
+
 impl From<ReleaseStateError> for Error {
     fn from(error: ReleaseStateError) -> Self {
         Error::ReleaseState(Box::new(error))
@@ -40,14 +319,206 @@ impl From<ReleaseStateError> for Error {
 
 #[derive(Clone, Debug)]
 pub enum DeployResult {
-    Unchanged,
-    Installed { plan: plan::ReleasePlan },
-    Upgraded { plan: plan::ReleasePlan },
+    Unchanged {
+        reason: UnchangedReason,
+        warnings: Vec<DeployWarning>,
+    },
+    Installed {
+        plan: plan::ReleasePlan,
+        warnings: Vec<DeployWarning>,
+    },
+    Upgraded {
+        plan: plan::ReleasePlan,
+        warnings: Vec<DeployWarning>,
+    },
+}
+
+impl DeployResult {
+    /// Problems which did not fail the deploy
+    pub fn warnings(&self) -> &[DeployWarning] {
+        match self {
+            DeployResult::Unchanged { warnings, .. }
+            | DeployResult::Installed { warnings, .. }
+            | DeployResult::Upgraded { warnings, .. } => warnings.as_slice(),
+        }
+    }
+
+    fn with_warnings(mut self, more_warnings: Vec<DeployWarning>) -> Self {
+        match &mut self {
+            DeployResult::Unchanged { warnings, .. }
+            | DeployResult::Installed { warnings, .. }
+            | DeployResult::Upgraded { warnings, .. } => warnings.extend(more_warnings),
+        }
+
+        self
+    }
+}
+
+/// Problem which did not fail a deploy, but which should be looked into
+#[derive(Clone, Debug)]
+pub enum DeployWarning {
+    /// No owner references could be added, see `Manager::with_owner_references`.
+    OwnerReferencesUnavailable(String),
+
+    /// The object has not been given an owner reference.
+    NotOwned {
+        identifier: Identifier,
+        reason: String,
+    },
+
+    /// The start of the deploy could not be recorded in the release state.
+    AttemptNotRecorded(String),
+
+    /// The resources of an API group could not be discovered, see
+    /// `Manager::with_strict_discovery`.
+    DiscoverySkipped(api_resource::SkippedGroup),
+
+    /// The release has the same hash as the deployed one but differs from it, which is a bug in
+    /// hashing. It has been deployed as changed.
+    HashCollision,
+
+    /// The object has been removed from the release but is kept, since its kind is not among the
+    /// kinds to prune, see `Manager::with_prune_kinds`.
+    NotPruned(Identifier),
+
+    /// Executing the plan ran into a problem which did not fail it.
+    Execution(plan::ExecuteWarning),
+}
+
+impl fmt::Display for DeployWarning {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            DeployWarning::OwnerReferencesUnavailable(reason) => {
+                write!(formatter, "Cannot add owner references: {}", reason)
+            }
+            DeployWarning::NotOwned { identifier, reason } => write!(
+                formatter,
+                "Not adding owner reference to {}: {}",
+                identifier, reason
+            ),
+            DeployWarning::AttemptNotRecorded(reason) => {
+                write!(formatter, "Failed to record deploy attempt: {}", reason)
+            }
+            DeployWarning::DiscoverySkipped(skipped) => write!(formatter, "{}", skipped),
+            DeployWarning::HashCollision => write!(
+                formatter,
+                "The release has the same hash as the deployed one but differs from it; this is a bug in hashing"
+            ),
+            DeployWarning::NotPruned(identifier) => write!(
+                formatter,
+                "Not pruning {}, since its kind is not among the kinds to prune",
+                identifier
+            ),
+            DeployWarning::Execution(warning) => write!(formatter, "{}", warning),
+        }
+    }
+}
+
+/// Why a deploy left the release as it was
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnchangedReason {
+    /// The objects are identical to the deployed ones.
+    Identical,
+
+    /// The objects only differ from the deployed ones in parts which are disregarded, see
+    /// `Manager::with_normalization`.
+    Normalized,
+}
+
+/// Default size in bytes up to which a release state is kept in a single ConfigMap, which leaves
+/// room for metadata below the limit of 1 MiB
+pub const DEFAULT_MAX_OBJECT_SIZE: usize = 900 * 1024;
+
+/// Adjustments to the inferred client configuration, for clusters that cannot be reached with the
+/// kubeconfig alone
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionOptions {
+    /// Don't verify the certificate of the API server
+    pub insecure_skip_tls_verify: bool,
+
+    /// PEM file with certificate authorities to trust in addition to the configured ones
+    pub certificate_authority: Option<PathBuf>,
+
+    /// Proxy through which the API server is reached, defaults to the `HTTPS_PROXY` environment
+    /// variable
+    pub proxy_url: Option<http::Uri>,
+}
+
+impl ConnectionOptions {
+    fn apply_to(&self, config: &mut kube::Config) -> Result<(), Error> {
+        if self.insecure_skip_tls_verify {
+            config.accept_invalid_certs = true;
+        }
+
+        if let Some(path) = &self.certificate_authority {
+            let certificates = read_certificates(path)?;
+            config
+                .root_cert
+                .get_or_insert_with(Vec::new)
+                .extend(certificates);
+        }
+
+        let proxy_url = match &self.proxy_url {
+            Some(proxy_url) => Some(proxy_url.clone()),
+            None => proxy_from_env()?,
+        };
+        if let Some(proxy_url) = proxy_url {
+            config.proxy_url = Some(proxy_url);
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the DER encoded certificates from a PEM file.
+fn read_certificates(path: &std::path::Path) -> Result<Vec<Vec<u8>>, Error> {
+    let error = |reason: String| Error::CertificateAuthority {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let contents = std::fs::read(path).map_err(|io_error| error(io_error.to_string()))?;
+    let certificates: Vec<Vec<u8>> = pem::parse_many(contents)
+        .into_iter()
+        .filter(|block| block.tag == "CERTIFICATE")
+        .map(|block| block.contents)
+        .collect();
+
+    if certificates.is_empty() {
+        return Err(error("No certificates found".to_string()));
+    }
+
+    Ok(certificates)
 }
 
+/// Proxy given by the `HTTPS_PROXY` environment variable, if any
+fn proxy_from_env() -> Result<Option<http::Uri>, Error> {
+    let value = match ["HTTPS_PROXY", "https_proxy"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .filter(|value| !value.is_empty())
+    {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    value.parse().map(Some).map_err(|error| {
+        Error::ConnectionOptions(format!(
+            "HTTPS_PROXY {:?} is not a valid URL: {}",
+            value, error
+        ))
+    })
+}
+
+/// Namespace in which the manager operates
 #[derive(Clone, Debug)]
 pub enum NamespaceMode {
+    /// Namespace of the inferred configuration. That is the namespace of the current kubeconfig
+    /// context, the namespace of the service account when running inside a cluster or "default"
+    /// if neither specifies one.
     Default,
+
+    /// The given namespace
     Specific(String),
 }
 
@@ -62,12 +533,59 @@ impl NamespaceMode {
 #[derive(Clone)]
 pub struct Manager {
     client: kube::Client,
+    server: String,
+    namespace: String,
+    release_namespace: String,
     config_maps: kube::Api<ConfigMap>,
+    locking: bool,
+    execute_options: plan::ExecuteOptions,
+    checkpoints: bool,
+    deploy_timeout: Option<Duration>,
+    kind_policy: policy::KindPolicy,
+    owner_references: bool,
+    prune_limit: Option<usize>,
+    prune_kinds: Option<Vec<String>>,
+    create_namespace: bool,
+    skip_crds: bool,
+    retain_lock_on_failure: bool,
+    strict_discovery: bool,
+    output_dir: Option<PathBuf>,
+    only: Vec<identifier::Target>,
+    no_state: bool,
+    allow_namespace_override: bool,
+    list_keys: verify::ListKeys,
+    check_rbac: bool,
+    keep_state: bool,
+    normalization: normalize::Normalization,
+    step: Option<Arc<dyn plan::StepConfirmation>>,
+    max_object_size: usize,
+    record_verify: bool,
+    verify_concurrency: usize,
 }
 
 impl Manager {
     pub async fn new(ns_mode: NamespaceMode) -> Result<Self, Error> {
+        Self::new_with_connection(ns_mode, &ConnectionOptions::default()).await
+    }
+
+    /// Create a manager like `new`, adjusting the inferred configuration first.
+    pub async fn new_with_connection(
+        ns_mode: NamespaceMode,
+        connection: &ConnectionOptions,
+    ) -> Result<Self, Error> {
         let mut config = kube::Config::infer().await?;
+        connection.apply_to(&mut config)?;
+
+        Self::new_with_config(config, ns_mode)
+    }
+
+    /// Create a manager from the given configuration instead of the inferred one. With
+    /// `NamespaceMode::Default` the default namespace of the configuration is used, e.g. the
+    /// namespace of the current kubeconfig context.
+    pub fn new_with_config(
+        mut config: kube::Config,
+        ns_mode: NamespaceMode,
+    ) -> Result<Self, Error> {
         match ns_mode {
             NamespaceMode::Default => {}
             NamespaceMode::Specific(ns) => {
@@ -75,133 +593,2000 @@ impl Manager {
             }
         }
 
+        let server = config.cluster_url.to_string();
+        let namespace = config.default_namespace.clone();
         let client = kube::Client::try_from(config)?;
-        let config_maps = kube::Api::default_namespaced(client.clone());
 
-        Ok(Manager {
+        Ok(Self::new_with_client(client, namespace).with_server(server))
+    }
+
+    /// Create a manager that uses the given client instead of inferring the configuration. The
+    /// client does not expose its default namespace, so the namespace must be given explicitly.
+    /// Objects without a namespace are deployed into the default namespace of the client, which
+    /// should therefore match.
+    pub fn new_with_client(client: kube::Client, namespace: String) -> Self {
+        let config_maps = kube::Api::namespaced(client.clone(), namespace.as_str());
+
+        Manager {
             client,
+            server: "the configured cluster".to_string(),
+            release_namespace: namespace.clone(),
+            namespace,
             config_maps,
-        })
+            locking: true,
+            execute_options: plan::ExecuteOptions::default(),
+            checkpoints: false,
+            deploy_timeout: None,
+            kind_policy: policy::KindPolicy::new(),
+            owner_references: false,
+            prune_limit: None,
+            prune_kinds: None,
+            create_namespace: false,
+            skip_crds: false,
+            retain_lock_on_failure: false,
+            strict_discovery: false,
+            output_dir: None,
+            only: Vec::new(),
+            no_state: false,
+            allow_namespace_override: false,
+            list_keys: verify::ListKeys::default(),
+            check_rbac: false,
+            keep_state: false,
+            normalization: normalize::Normalization::new(),
+            step: None,
+            max_object_size: DEFAULT_MAX_OBJECT_SIZE,
+            record_verify: false,
+            verify_concurrency: verify::DEFAULT_CONCURRENCY,
+        }
     }
 
-    pub async fn deploy(&self, release: &release::Release) -> Result<DeployResult, Error> {
-        let name = release.name();
-        let lock = release.lock(&self.config_maps).await?;
-        let state = ReleaseState::get(&self.config_maps, name.as_str()).await?;
+    /// Address of the cluster, which is used in error messages
+    fn with_server(mut self, server: String) -> Self {
+        self.server = server;
+        self
+    }
 
-        let result = match state {
-            None => {
-                let state = ReleaseState {
-                    current: release.objects().clone(),
-                    history: Vec::new(),
-                };
+    /// Client used to talk to the cluster
+    pub fn client(&self) -> &kube::Client {
+        &self.client
+    }
 
-                let (_client, plan) =
-                    release
-                        .install(self.client.clone())
-                        .await
-                        .map_err(|error| Error::Release {
-                            error: Box::new(error),
-                            state: state.clone(),
-                        })?;
+    /// Namespace in which releases are managed
+    pub fn namespace(&self) -> &str {
+        self.namespace.as_str()
+    }
 
-                if let Err(err_cause) = state.apply(&self.config_maps, name.as_str()).await {
-                    plan.undo()
-                        .execute(self.client.clone())
-                        .await
-                        .map_err(|error| Error::Release {
-                            error: Box::new(error),
-                            state: state.clone(),
-                        })?;
-                    return Err(err_cause.into());
-                }
+    /// Namespace in which the states and locks of releases are kept
+    pub fn release_namespace(&self) -> &str {
+        self.release_namespace.as_str()
+    }
 
-                DeployResult::Installed { plan }
-            }
+    /// Keep the states and locks of releases in the given namespace instead of the one the
+    /// objects are deployed to. Owner references to the state are only added to objects in the
+    /// same namespace.
+    pub fn with_release_namespace(mut self, release_namespace: Option<String>) -> Self {
+        if let Some(release_namespace) = release_namespace {
+            self.config_maps =
+                kube::Api::namespaced(self.client.clone(), release_namespace.as_str());
+            self.release_namespace = release_namespace;
+        }
+        self
+    }
 
-            Some(mut state) => {
-                let old_release =
-                    release::Release::from_objects(name.clone(), state.current.clone());
+    /// Check that the cluster can be reached with the configured credentials.
+    pub async fn ping(&self) -> Result<(), Error> {
+        match self.client.list_core_api_versions().await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Error::Unreachable {
+                server: self.server.clone(),
+                error,
+            }),
+        }
+    }
 
-                if old_release.hash_value() == release.hash_value() {
-                    return Ok(DeployResult::Unchanged);
-                }
+    /// Enable or disable locking of releases during deploy and delete. Locking is enabled by
+    /// default.
+    ///
+    /// Disabling locking is only safe when nothing else operates on the same releases
+    /// concurrently, e.g. when the manager is embedded in a controller with a single replica.
+    pub fn with_locking(mut self, locking: bool) -> Self {
+        self.locking = locking;
+        self
+    }
 
-                let (_client, plan) = release
-                    .upgrade(&old_release, self.client.clone())
-                    .await
-                    .map_err(|error| Error::Release {
-                        error: Box::new(error),
-                        state: state.clone(),
-                    })?;
+    /// Configure how long to wait for the kinds of newly deployed CustomResourceDefinitions to
+    /// become available before deploying the remaining objects.
+    pub fn with_crd_wait(mut self, poll_interval: Duration, timeout: Duration) -> Self {
+        self.execute_options.crd_poll_interval = poll_interval;
+        self.execute_options.crd_timeout = timeout;
+        self
+    }
 
-                state.history.insert(0, state.current);
-                state.current = release.objects().clone();
+    /// Recreate objects whose upgrade changes immutable fields instead of failing the deployment.
+    pub fn with_force_recreate(mut self, force_recreate: bool) -> Self {
+        self.execute_options.force_recreate = force_recreate;
+        self
+    }
 
-                if let Err(err_cause) = state.apply(&self.config_maps, name.as_str()).await {
-                    plan.undo()
-                        .execute(self.client.clone())
-                        .await
-                        .map_err(|error| Error::Release {
-                            error: Box::new(error),
-                            state: state.clone(),
-                        })?;
-                    return Err(err_cause.into());
-                }
+    /// Apply objects on behalf of the given field manager instead of the default one.
+    pub fn with_field_manager(mut self, field_manager: String) -> Self {
+        self.execute_options.field_manager = field_manager;
+        self
+    }
+
+    /// Fail actions on individual objects which take longer than the given timeout. This
+    /// triggers the usual rollback.
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.execute_options.request_timeout = timeout;
+        self
+    }
+
+    /// Limit the time a deploy may take as a whole. When the time is up, the changes made so far
+    /// are rolled back.
+    pub fn with_deploy_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.deploy_timeout = timeout;
+        self
+    }
+
+    /// Limit the rate at which objects are created, upgraded and deleted, instead of relying on
+    /// retries once the API server starts throttling.
+    pub fn with_rate_limit(mut self, rate_limit: Option<RateLimiter>) -> Self {
+        self.execute_options.rate_limit = rate_limit;
+        self
+    }
+
+    /// Choose how upgraded objects are applied.
+    pub fn with_apply_strategy(mut self, apply_strategy: plan::ApplyStrategy) -> Self {
+        self.execute_options.apply_strategy = apply_strategy;
+        self
+    }
+
+    /// Persist the rollback plan after every step of a deploy or delete, so an interrupted
+    /// operation can be rolled back later using `recover`.
+    pub fn with_checkpoints(mut self, checkpoints: bool) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    /// Write every object created or upgraded by a deploy to `<directory>/<release>/`, as it was
+    /// returned by the server.
+    pub fn with_output_dir(mut self, output_dir: Option<PathBuf>) -> Self {
+        self.output_dir = output_dir;
+        self
+    }
+
+    /// Only deploy and verify the selected objects. All other objects are left as they are, both
+    /// in the cluster and in the release state.
+    pub fn with_only(mut self, only: Vec<identifier::Target>) -> Self {
+        self.only = only;
+        self
+    }
 
-                DeployResult::Upgraded { plan }
+    /// Don't keep a release state. The objects labelled as belonging to a release are looked up in
+    /// the cluster instead, which means there is no history and every deploy is an upgrade.
+    pub fn with_no_state(mut self, no_state: bool) -> Self {
+        self.no_state = no_state;
+        self
+    }
+
+    /// Allow objects to declare a namespace other than the one of the manager. Otherwise deploys
+    /// of such objects are refused, since they would be placed in the namespace of the manager.
+    pub fn with_allow_namespace_override(mut self, allow_namespace_override: bool) -> Self {
+        self.allow_namespace_override = allow_namespace_override;
+        self
+    }
+
+    /// Match the items of keyed lists by their key when verifying, see `verify::ListKeys`.
+    pub fn with_list_keys(mut self, list_keys: verify::ListKeys) -> Self {
+        self.list_keys = list_keys;
+        self
+    }
+
+    /// Ask the API server whether the current user may deploy every object before deploying, and
+    /// refuse to deploy if any permission is missing.
+    pub fn with_check_rbac(mut self, check_rbac: bool) -> Self {
+        self.check_rbac = check_rbac;
+        self
+    }
+
+    /// Keep the state of deleted releases, marking them as deleted. Their history remains
+    /// available and deploying them again continues it.
+    pub fn with_keep_state(mut self, keep_state: bool) -> Self {
+        self.keep_state = keep_state;
+        self
+    }
+
+    /// Record when a release has last been verified and whether it matched, as annotations on its
+    /// state. The outcome is shown by `list`.
+    pub fn with_record_verify(mut self, record_verify: bool) -> Self {
+        self.record_verify = record_verify;
+        self
+    }
+
+    /// Limit the number of resources which are listed concurrently when looking for the objects
+    /// of a release in the cluster, e.g. when verifying.
+    pub fn with_verify_concurrency(mut self, verify_concurrency: usize) -> Self {
+        self.verify_concurrency = verify_concurrency;
+        self
+    }
+
+    /// Why deploying the release over the deployed one would change nothing, unless it would. A
+    /// release which only differs despite having the same hash is added to the warnings.
+    fn unchanged_reason(
+        &self,
+        deployed: &release::Release,
+        release: &release::Release,
+        warnings: &mut Vec<DeployWarning>,
+    ) -> Option<UnchangedReason> {
+        match deployed.compare(release, &self.normalization) {
+            release::Comparison::Identical => Some(UnchangedReason::Identical),
+            release::Comparison::Equivalent => Some(UnchangedReason::Normalized),
+            release::Comparison::Different => None,
+            release::Comparison::HashCollision => {
+                warnings.push(DeployWarning::HashCollision);
+                None
             }
+        }
+    }
+
+    /// Split the deployed objects into those which are still part of the release or may be
+    /// pruned, and those which would be pruned but whose kind is not selected by
+    /// `with_prune_kinds`. The latter are added to the warnings.
+    fn split_prunable(
+        &self,
+        deployed: objects::Objects,
+        release: &release::Release,
+        warnings: &mut Vec<DeployWarning>,
+    ) -> (objects::Objects, objects::Objects) {
+        let prune_kinds = match &self.prune_kinds {
+            Some(prune_kinds) => prune_kinds,
+            None => return (deployed, objects::Objects::empty()),
         };
 
-        lock.release().await?;
-        Ok(result)
+        let (prunable, retained): (objects::Objects, objects::Objects) =
+            deployed.into_iter().partition(|(identifier, _)| {
+                release.objects().contains(identifier)
+                    || prune_kinds
+                        .iter()
+                        .any(|kind| kind.eq_ignore_ascii_case(identifier.kind()))
+            });
+
+        warnings.extend(
+            retained
+                .iter()
+                .map(|(identifier, _)| DeployWarning::NotPruned(identifier.clone())),
+        );
+
+        (prunable, retained)
     }
 
-    pub async fn delete(&self, name: String) -> Result<Option<plan::ReleasePlan>, Error> {
-        let state = ReleaseState::get(&self.config_maps, name.as_str()).await?;
+    /// Split the objects into those which are selected by `with_only` and the rest.
+    fn split_targeted(&self, objects: objects::Objects) -> (objects::Objects, objects::Objects) {
+        if self.only.is_empty() {
+            return (objects, objects::Objects::empty());
+        }
 
-        if let Some(state) = state {
-            let release = release::Release::from_objects(name, state.current.clone());
+        objects
+            .into_iter()
+            .partition(|(identifier, _)| self.only.iter().any(|target| target.matches(identifier)))
+    }
 
-            let (client, plan) = release
-                .uninstall(self.client.clone())
-                .await
-                .map_err(|error| Error::Release {
-                    error: Box::new(error),
-                    state,
-                })?;
+    /// Targets given to `with_only` which select none of the identified objects
+    fn unmatched_targets<'a>(
+        &self,
+        identifiers: impl Iterator<Item = &'a Identifier> + Clone,
+    ) -> Vec<identifier::Target> {
+        self.only
+            .iter()
+            .filter(|target| {
+                !identifiers
+                    .clone()
+                    .any(|identifier| target.matches(identifier))
+            })
+            .cloned()
+            .collect()
+    }
 
-            let api: kube::Api<ConfigMap> = kube::Api::default_namespaced(client);
+    fn execute_options(&self, release_name: &str) -> plan::ExecuteOptions {
+        let mut options = self.execute_options.clone();
+        options.output_dir = self
+            .output_dir
+            .as_ref()
+            .map(|directory| directory.join(release_name));
 
-            api.delete(release.name(), &kube::api::DeleteParams::default())
-                .await?;
+        if self.checkpoints {
+            options.checkpoint = Some(release::checkpoint_name(release_name));
+            options.checkpoint_namespace = Some(self.release_namespace.clone());
+        }
 
-            Ok(Some(plan))
-        } else {
-            Ok(None)
+        options
+    }
+
+    /// Restrict the kinds of objects that releases may contain. Deployments of releases that
+    /// violate the policy are rejected before anything is applied.
+    pub fn with_kind_policy(mut self, kind_policy: policy::KindPolicy) -> Self {
+        self.kind_policy = kind_policy;
+        self
+    }
+
+    /// Disregard the differences removed by the normalization when deciding whether a deploy
+    /// changes anything.
+    pub fn with_normalization(mut self, normalization: normalize::Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Ask for confirmation before each phase of a deploy. Undoing a deploy is never interrupted.
+    pub fn with_step(mut self, step: Option<Arc<dyn plan::StepConfirmation>>) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Split release states larger than the given number of bytes across several ConfigMaps.
+    pub fn with_max_object_size(mut self, max_object_size: usize) -> Self {
+        self.max_object_size = max_object_size;
+        self
+    }
+
+    /// Make the release state the owner of the objects it contains, so Kubernetes garbage
+    /// collects them when the state is deleted out-of-band. Cluster-scoped objects cannot be
+    /// owned by a namespaced object and are skipped.
+    pub fn with_owner_references(mut self, owner_references: bool) -> Self {
+        self.owner_references = owner_references;
+        self
+    }
+
+    /// Reject upgrades which would delete more than the given number of objects.
+    pub fn with_prune_limit(mut self, prune_limit: Option<usize>) -> Self {
+        self.prune_limit = prune_limit;
+        self
+    }
+
+    /// Only delete objects of the given kinds when they are removed from a release. Removed
+    /// objects of other kinds are kept, both in the cluster and in the release state, and returned
+    /// as warnings. With an empty list no objects are deleted, without a list objects of any kind
+    /// are deleted.
+    pub fn with_prune_kinds(mut self, prune_kinds: Option<Vec<String>>) -> Self {
+        self.prune_kinds = prune_kinds;
+        self
+    }
+
+    /// Create the namespace of the manager when deploying, if it does not exist yet.
+    pub fn with_create_namespace(mut self, create_namespace: bool) -> Self {
+        self.create_namespace = create_namespace;
+        self
+    }
+
+    /// Keep the lock of a release when a deploy fails and its changes could not be rolled back
+    /// completely. The lock is annotated with the failure and has to be removed using `unlock` once
+    /// the cluster has been inspected. Deploys that succeed or are rolled back cleanly release the
+    /// lock as usual.
+    pub fn with_retain_lock_on_failure(mut self, retain_lock_on_failure: bool) -> Self {
+        self.retain_lock_on_failure = retain_lock_on_failure;
+        self
+    }
+
+    /// Fail when any API group cannot be discovered instead of skipping it with a warning.
+    pub fn with_strict_discovery(mut self, strict_discovery: bool) -> Self {
+        self.strict_discovery = strict_discovery;
+        self
+    }
+
+    /// Leave CustomResourceDefinitions out of deployed releases, e.g. because they are managed
+    /// separately. The kinds they define have to be available already.
+    pub fn with_skip_crds(mut self, skip_crds: bool) -> Self {
+        self.skip_crds = skip_crds;
+        self
+    }
+
+    /// Remove the CustomResourceDefinitions from the release and make sure the kinds they define
+    /// are already available in the cluster. Groups skipped during discovery are returned along
+    /// with the release.
+    async fn strip_crds(
+        &self,
+        release: &release::Release,
+    ) -> Result<(release::Release, Vec<api_resource::SkippedGroup>), Error> {
+        let (release, crds) = release.clone().without_crds();
+
+        if crds.is_empty() {
+            return Ok((release, Vec::new()));
+        }
+
+        let (resources, skipped) =
+            api_resource::find_api_resources(&self.client, self.strict_discovery).await?;
+
+        let missing: Vec<String> = crds
+            .iter()
+            .flat_map(plan::defined_kinds)
+            .filter(|kind| {
+                !resources.iter().any(|resource| {
+                    resource.group == kind.group
+                        && resource.version == kind.version
+                        && resource.kind == kind.kind
+                })
+            })
+            .map(|kind| format!("{}.{}/{}", kind.kind, kind.group, kind.version))
+            .collect();
+
+        if !missing.is_empty() {
+            return Err(Error::MissingKinds(missing));
         }
+
+        Ok((release, skipped))
     }
 
-    pub async fn verify(&self, release_name: String) -> Result<(), VerificationError> {
+    /// Make sure the namespace of the manager exists. A namespace that has to be created is
+    /// labelled as belonging to the release, so it can be told apart from pre-existing ones later.
+    /// Returns whether the namespace has been created.
+    async fn ensure_namespace(&self, release_name: &str) -> Result<bool, Error> {
+        let namespaces: kube::Api<Namespace> = kube::Api::all(self.client.clone());
+
+        match namespaces.get(self.namespace()).await {
+            Ok(_) => return Ok(false),
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+            Err(error) => return Err(Error::Kube(error)),
+        }
+
+        let mut namespace =
+            plan::ReleasePlan::tag_object(release_name.to_string(), Namespace::default());
+        namespace.metadata.name = Some(self.namespace.clone());
+
+        namespaces
+            .create(&kube::api::PostParams::default(), &namespace)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Delete the namespace of the manager, but only if it has been created for the given
+    /// release. Returns whether the namespace has been deleted.
+    pub async fn delete_created_namespace(&self, release_name: &str) -> Result<bool, Error> {
+        let namespaces: kube::Api<Namespace> = kube::Api::all(self.client.clone());
+
+        let namespace = match namespaces.get(self.namespace()).await {
+            Ok(namespace) => namespace,
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                return Ok(false)
+            }
+            Err(error) => return Err(Error::Kube(error)),
+        };
+
+        let created_by_release = namespace.has_label(&k8s::ObjectType::Managed)
+            && namespace.has_label(&k8s::ReleaseName(release_name.to_string()));
+
+        if !created_by_release {
+            return Ok(false);
+        }
+
+        namespaces
+            .delete(self.namespace(), &kube::api::DeleteParams::default())
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn lock(&self, release_name: &str) -> Result<Option<Lock<'_, ConfigMap>>, Error> {
+        if self.locking {
+            let lock_value =
+                ConfigMap::default().with_label(&k8s::ReleaseName(release_name.to_string()));
+
+            let lock = Lock::new_with(
+                &self.config_maps,
+                release::lock_name(release_name),
+                lock_value,
+            )
+            .await?;
+            Ok(Some(lock))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Check whether the release could be deployed: the cluster is reachable, the namespace
+    /// exists, the release is not locked and the current user may deploy every kind of object.
+    /// Checks which depend on the cluster are skipped if it cannot be reached.
+    pub async fn doctor(&self, release: &release::Release) -> DoctorReport {
+        let mut report = DoctorReport::default();
+
+        let reachable = self.ping().await.err();
+        let unreachable = reachable.is_some();
+        report.record(
+            format!("Cluster at {} is reachable", self.server),
+            reachable,
+        );
+        if unreachable {
+            return report;
+        }
+
+        let namespaces: kube::Api<Namespace> = kube::Api::all(self.client.clone());
+        let namespace_problem = match namespaces.get(self.namespace()).await {
+            Ok(_) => None,
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                Some("It does not exist; deploy with --create-namespace".to_string())
+            }
+            Err(error) => Some(error.to_string()),
+        };
+        report.record(
+            format!("Namespace {} exists", self.namespace),
+            namespace_problem,
+        );
+
+        let lock_name = release::lock_name(release.name());
+        let lock_problem = match self.config_maps.get(lock_name.as_str()).await {
+            Ok(_) => Some(format!(
+                "Lock {} exists; remove it with unlock unless another operation is running",
+                lock_name
+            )),
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => None,
+            Err(error) => Some(error.to_string()),
+        };
+        report.record(
+            format!("Release {} is not locked", release.name()),
+            lock_problem,
+        );
+
+        let (access_problem, skipped) = match self.find_denied_access(release.objects()).await {
+            Ok((denied, skipped)) if denied.is_empty() => (None, skipped),
+            Ok((denied, skipped)) => (Some(Error::AccessDenied(denied).to_string()), skipped),
+            Err(error) => (Some(error.to_string()), Vec::new()),
+        };
+        report.record(
+            "Permissions to deploy all kinds of objects".to_string(),
+            access_problem,
+        );
+
+        for group in skipped {
+            report.record(
+                format!("API group {} can be discovered", group.group_version),
+                Some(group.reason),
+            );
+        }
+
+        report
+    }
+
+    /// Find the permissions the current user lacks to deploy and delete the objects. Groups
+    /// skipped during discovery are returned along with them.
+    async fn find_denied_access(
+        &self,
+        objects: &objects::Objects,
+    ) -> Result<(Vec<access::Access>, Vec<api_resource::SkippedGroup>), kube::Error> {
+        let (cluster_scoped, skipped) =
+            api_resource::find_cluster_scoped_kinds(&self.client, self.strict_discovery).await?;
+        let required = access::required_access(objects, self.namespace(), &cluster_scoped);
+        Ok((access::find_denied(&self.client, &required).await?, skipped))
+    }
+
+    /// Validate the objects of a release against the OpenAPI schema published by the cluster.
+    pub async fn validate_schema(&self, release: &release::Release) -> Result<(), Error> {
+        let schema = openapi::Schema::fetch(&self.client).await?;
+
+        let violations: Vec<(Identifier, openapi::Violation)> = release
+            .objects()
+            .iter()
+            .flat_map(|(identifier, object)| {
+                schema
+                    .validate(object)
+                    .into_iter()
+                    .map(move |violation| (identifier.clone(), violation))
+            })
+            .collect();
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::SchemaViolations(violations))
+        }
+    }
+
+    /// Find references between objects of a release which point neither at another object of the
+    /// release nor at an existing object in the cluster.
+    pub async fn find_dangling_references(
+        &self,
+        release: &release::Release,
+    ) -> Result<Vec<objects::Reference>, kube::Error> {
+        let mut dangling = Vec::new();
+
+        for reference in release.objects().validate_references() {
+            let api: kube::Api<DynamicObject> = kube::Api::default_namespaced_with(
+                self.client.clone(),
+                &reference.target.to_api_resource(),
+            );
+
+            match api.get(reference.target.name()).await {
+                Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                    dangling.push(reference);
+                }
+
+                Err(error) => return Err(error),
+
+                Ok(_) => {}
+            }
+        }
+
+        Ok(dangling)
+    }
+
+    pub async fn deploy(&self, release: &release::Release) -> Result<DeployResult, Error> {
+        let (release, skipped) = self.preflight(release).await?;
+        let release = release.as_ref();
+
+        // The namespace has to exist before the lock can be placed in it.
+        if self.create_namespace {
+            self.ensure_namespace(release.name()).await?;
+        }
+
+        let deadline = self.deploy_timeout.map(|timeout| Instant::now() + timeout);
+
+        let lock = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), self.lock(release.name()))
+                .await
+                .map_err(|_elapsed| Error::Timeout)??,
+            None => self.lock(release.name()).await?,
+        };
+
+        let result = self.inner_deploy(release, deadline).await;
+
+        metrics::record_deploy(match &result {
+            Ok(DeployResult::Unchanged { .. }) => "unchanged",
+            Ok(DeployResult::Installed { .. }) => "installed",
+            Ok(DeployResult::Upgraded { .. }) => "upgraded",
+            Err(_) => "failed",
+        });
+
+        if let Some(lock) = lock {
+            match &result {
+                Err(error) if self.retain_lock_on_failure && error.is_unrecovered() => {
+                    lock.retain(error.to_string()).await?
+                }
+                _ => lock.release().await?,
+            }
+        }
+
+        result.map(|result| {
+            result.with_warnings(
+                skipped
+                    .into_iter()
+                    .map(DeployWarning::DiscoverySkipped)
+                    .collect(),
+            )
+        })
+    }
+
+    /// Checks which a release has to pass before it is deployed: CustomResourceDefinitions are
+    /// stripped if they are to be skipped, and the kind policy, the namespaces of the objects and
+    /// the permissions are checked. Returns the release to deploy along with the API groups which
+    /// could not be discovered.
+    async fn preflight<'a>(
+        &self,
+        release: &'a release::Release,
+    ) -> Result<(Cow<'a, release::Release>, Vec<api_resource::SkippedGroup>), Error> {
+        let mut skipped = Vec::new();
+
+        let release = if self.skip_crds {
+            let (release, skipped_groups) = self.strip_crds(release).await?;
+            skipped.extend(skipped_groups);
+            Cow::Owned(release)
+        } else {
+            Cow::Borrowed(release)
+        };
+
+        let forbidden = self.kind_policy.violations(release.objects());
+        if !forbidden.is_empty() {
+            return Err(Error::ForbiddenKinds(forbidden));
+        }
+
+        if !self.allow_namespace_override {
+            let mismatched: Vec<Identifier> = release
+                .objects()
+                .iter()
+                .map(|(identifier, _)| identifier)
+                .filter(|identifier| {
+                    identifier
+                        .namespace()
+                        .map_or(false, |namespace| namespace != self.namespace())
+                })
+                .cloned()
+                .collect();
+
+            if !mismatched.is_empty() {
+                return Err(Error::NamespaceMismatch {
+                    namespace: self.namespace.clone(),
+                    identifiers: mismatched,
+                });
+            }
+        }
+
+        if self.check_rbac {
+            let (denied, skipped_groups) = self.find_denied_access(release.objects()).await?;
+            if !denied.is_empty() {
+                return Err(Error::AccessDenied(denied));
+            }
+            skipped.extend(skipped_groups);
+        }
+
+        Ok((release, skipped))
+    }
+
+    async fn inner_deploy(
+        &self,
+        release: &release::Release,
+        deadline: Option<Instant>,
+    ) -> Result<DeployResult, Error> {
+        if self.no_state {
+            return self.inner_deploy_without_state(release, deadline).await;
+        }
+
+        let name = release.name();
+        // Undoing a deploy is not subject to its deadline.
+        let undo_options = self.execute_options(name.as_str());
+        let mut options = undo_options.clone();
+        options.deadline = deadline;
+        options.step = self.step.clone();
+        let state = ReleaseState::get(&self.config_maps, name.as_str()).await?;
+
+        let deployed = state.iter().flat_map(|state| state.current.iter());
+        let unmatched = self.unmatched_targets(
+            release
+                .objects()
+                .iter()
+                .chain(deployed)
+                .map(|(identifier, _)| identifier),
+        );
+
+        if !unmatched.is_empty() {
+            return Err(Error::UnmatchedTargets(unmatched));
+        }
+
+        let (targeted, _) = self.split_targeted(release.objects().clone());
+        let release = &release::Release::from_objects(name.clone(), targeted);
+
+        let result = match state {
+            None => {
+                let state = ReleaseState::new(release.objects().clone());
+
+                let (_client, plan, applied) = release
+                    .install(self.client.clone(), &options)
+                    .await
+                    .map_err(|error| Error::Release {
+                        error: Box::new(error),
+                        state: state.clone(),
+                    })?;
+
+                if let Err(err_cause) = state
+                    .apply(&self.config_maps, name.as_str(), self.max_object_size)
+                    .await
+                {
+                    plan.undo()
+                        .execute(self.client.clone(), &undo_options)
+                        .await
+                        .map_err(|error| Error::Release {
+                            error: Box::new(error),
+                            state: state.clone(),
+                        })?;
+                    return Err(err_cause.into());
+                }
+
+                DeployResult::Installed {
+                    plan,
+                    warnings: applied
+                        .warnings
+                        .into_iter()
+                        .map(DeployWarning::Execution)
+                        .collect(),
+                }
+            }
+
+            Some(mut state) => {
+                let mut warnings = Vec::new();
+                let (old_objects, untouched) = self.split_targeted(state.current.clone());
+                let (old_objects, retained) =
+                    self.split_prunable(old_objects, release, &mut warnings);
+                let untouched = untouched.union(retained);
+                let old_release = release::Release::from_objects(name.clone(), old_objects);
+
+                if let Some(reason) = self.unchanged_reason(&old_release, release, &mut warnings) {
+                    return Ok(DeployResult::Unchanged { reason, warnings });
+                }
+
+                // The pruned objects are the deletions of the returned plan.
+                let pruned = old_release
+                    .objects()
+                    .iter()
+                    .filter(|(identifier, _)| !release.objects().contains(identifier))
+                    .count();
+
+                if let Some(limit) = self.prune_limit {
+                    if pruned > limit {
+                        return Err(Error::PruneLimit {
+                            count: pruned,
+                            limit,
+                        });
+                    }
+                }
+
+                if let Err(error) = state
+                    .record_attempt(
+                        &self.config_maps,
+                        name.as_str(),
+                        self.max_object_size,
+                        AttemptStatus::InProgress,
+                        None,
+                    )
+                    .await
+                {
+                    warnings.push(DeployWarning::AttemptNotRecorded(error.to_string()));
+                }
+
+                let plan = match release
+                    .upgrade(&old_release, self.client.clone(), &options)
+                    .await
+                {
+                    Ok((_client, plan, applied)) => {
+                        warnings.extend(applied.warnings.into_iter().map(DeployWarning::Execution));
+                        plan
+                    }
+
+                    Err(error) => {
+                        let status = match error {
+                            release::Error::ReleaseError { .. }
+                            | release::Error::Aborted { rollback: None, .. } => {
+                                AttemptStatus::RolledBack
+                            }
+                            _ => AttemptStatus::Failed,
+                        };
+
+                        // The failed deploy is what gets reported. The attempt is still part of
+                        // the state returned with the error, even if it could not be stored.
+                        let _ = state
+                            .record_attempt(
+                                &self.config_maps,
+                                name.as_str(),
+                                self.max_object_size,
+                                status,
+                                Some(error.to_string()),
+                            )
+                            .await;
+
+                        return Err(Error::Release {
+                            error: Box::new(error),
+                            state,
+                        });
+                    }
+                };
+
+                state.history.insert(0, state.current);
+                state.current = untouched.union(release.objects().clone());
+                state.last_attempt_status = Some(AttemptStatus::Succeeded);
+                state.last_error = None;
+                state.deleted_at = None;
+
+                if let Err(err_cause) = state
+                    .apply(&self.config_maps, name.as_str(), self.max_object_size)
+                    .await
+                {
+                    plan.undo()
+                        .execute(self.client.clone(), &undo_options)
+                        .await
+                        .map_err(|error| Error::Release {
+                            error: Box::new(error),
+                            state: state.clone(),
+                        })?;
+                    return Err(err_cause.into());
+                }
+
+                DeployResult::Upgraded { plan, warnings }
+            }
+        };
+
+        if self.owner_references {
+            return Ok(result.with_warnings(self.adopt_objects(release).await));
+        }
+
+        Ok(result)
+    }
+
+    async fn inner_deploy_without_state(
+        &self,
+        release: &release::Release,
+        deadline: Option<Instant>,
+    ) -> Result<DeployResult, Error> {
+        let name = release.name();
+        let mut options = self.execute_options(name.as_str());
+        options.deadline = deadline;
+        options.step = self.step.clone();
+
+        let (old_objects, skipped) = self.find_live_objects(name.as_str()).await?;
+        let mut warnings: Vec<DeployWarning> = skipped
+            .into_iter()
+            .map(DeployWarning::DiscoverySkipped)
+            .collect();
+
+        let installing = old_objects.is_empty();
+        let (old_objects, _retained) = self.split_prunable(old_objects, release, &mut warnings);
+        let old_release = release::Release::from_objects(name.clone(), old_objects);
+
+        let (_client, plan, applied) = release
+            .upgrade(&old_release, self.client.clone(), &options)
+            .await
+            .map_err(|error| Error::Release {
+                error: Box::new(error),
+                state: ReleaseState::new(old_release.objects().clone()),
+            })?;
+        warnings.extend(applied.warnings.into_iter().map(DeployWarning::Execution));
+
+        if installing {
+            Ok(DeployResult::Installed { plan, warnings })
+        } else {
+            Ok(DeployResult::Upgraded { plan, warnings })
+        }
+    }
+
+    /// Undo a deploy after it has finished, e.g. because its objects did not become ready. The
+    /// objects are restored by undoing the plan and the release state is reset to what it was
+    /// before the deploy. The given error is recorded as the reason.
+    pub async fn undo_deploy(
+        &self,
+        name: &str,
+        result: &DeployResult,
+        reason: &Error,
+    ) -> Result<(), Error> {
+        let (plan, installed) = match result {
+            DeployResult::Unchanged { .. } => return Ok(()),
+            DeployResult::Installed { plan, .. } => (plan, true),
+            DeployResult::Upgraded { plan, .. } => (plan, false),
+        };
+
+        let lock = self.lock(name).await?;
+        let result = self.inner_undo_deploy(name, plan, installed, reason).await;
+
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_undo_deploy(
+        &self,
+        name: &str,
+        plan: &plan::ReleasePlan,
+        installed: bool,
+        reason: &Error,
+    ) -> Result<(), Error> {
+        let state = ReleaseState::get(&self.config_maps, name).await?;
+
+        plan.undo()
+            .execute(self.client.clone(), &self.execute_options(name))
+            .await
+            .map_err(|error| Error::Release {
+                error: Box::new(error),
+                state: state
+                    .clone()
+                    .unwrap_or_else(|| ReleaseState::new(objects::Objects::empty())),
+            })?;
+
+        let mut state = match state {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        if installed {
+            ReleaseState::delete(&self.config_maps, name).await?;
+            return Ok(());
+        }
+
+        if !state.history.is_empty() {
+            state.current = state.history.remove(0);
+        }
+
+        state.last_attempt_status = Some(AttemptStatus::RolledBack);
+        state.last_error = Some(reason.to_string());
+        state
+            .apply(&self.config_maps, name, self.max_object_size)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Find the objects in the cluster which are labelled as belonging to the release. Only the
+    /// fields that could have been declared are kept, so they can take the place of the objects
+    /// in a release state. Objects in the namespace of the manager lose their namespace, like
+    /// objects which have been declared without one. Groups skipped during discovery are returned
+    /// along with the objects.
+    async fn find_live_objects(
+        &self,
+        name: &str,
+    ) -> Result<(objects::Objects, Vec<api_resource::SkippedGroup>), Error> {
+        let (live, skipped) = verify::find_release_objects(
+            self.client.clone(),
+            name.to_string(),
+            &Selector::default(),
+            self.strict_discovery,
+            self.verify_concurrency,
+        )
+        .await?;
+
+        let objects = live
+            .into_iter()
+            .map(|(identifier, object)| {
+                let namespace = identifier
+                    .namespace()
+                    .filter(|namespace| *namespace != self.namespace())
+                    .map(str::to_string);
+
+                let mut data = object.dyn_object.data;
+                if let Some(fields) = data.as_object_mut() {
+                    fields.remove("status");
+                }
+
+                let dyn_object = DynamicObject {
+                    types: object.dyn_object.types,
+                    metadata: ObjectMeta {
+                        name: object.dyn_object.metadata.name,
+                        namespace: namespace.clone(),
+                        labels: object.dyn_object.metadata.labels,
+                        annotations: object.dyn_object.metadata.annotations,
+                        ..ObjectMeta::default()
+                    },
+                    data,
+                };
+
+                let object = objects::Object {
+                    api_resource: object.api_resource,
+                    dyn_object,
+                };
+
+                (identifier.with_namespace(namespace), object)
+            })
+            .collect();
+
+        Ok((objects, skipped))
+    }
+
+    /// Add an owner reference pointing at the release state to every namespaced object of the
+    /// release. The deploy has already succeeded at this point, hence failures are only returned
+    /// as warnings.
+    async fn adopt_objects(&self, release: &release::Release) -> Vec<DeployWarning> {
+        let name = release.name();
+
+        let state_config_map = match self.config_maps.get(name.as_str()).await {
+            Ok(config_map) => config_map,
+            Err(error) => {
+                return vec![DeployWarning::OwnerReferencesUnavailable(format!(
+                    "Failed to look up release state {}: {}",
+                    name, error
+                ))];
+            }
+        };
+
+        let owner = OwnerReference {
+            api_version: "v1".to_string(),
+            kind: "ConfigMap".to_string(),
+            name: name.clone(),
+            uid: state_config_map.meta().uid.clone().unwrap_or_default(),
+            ..OwnerReference::default()
+        };
+
+        let (cluster_scoped, skipped) = match api_resource::find_cluster_scoped_kinds(
+            &self.client,
+            self.strict_discovery,
+        )
+        .await
+        {
+            Ok(discovered) => discovered,
+            Err(error) => {
+                return vec![DeployWarning::OwnerReferencesUnavailable(format!(
+                    "Failed to discover cluster-scoped kinds: {}",
+                    error
+                ))];
+            }
+        };
+
+        let mut warnings: Vec<DeployWarning> = skipped
+            .into_iter()
+            .map(DeployWarning::DiscoverySkipped)
+            .collect();
+
+        for (ident, object) in release.objects().iter() {
+            let not_owned = |reason: String| DeployWarning::NotOwned {
+                identifier: ident.clone(),
+                reason,
+            };
+            let scope = (
+                object.api_resource.group.clone(),
+                object.api_resource.kind.clone(),
+            );
+
+            if cluster_scoped.contains(&scope) {
+                warnings.push(not_owned("It is cluster-scoped".to_string()));
+                continue;
+            }
+
+            // Owner references must not cross namespaces.
+            if ident.namespace().unwrap_or(self.namespace()) != self.release_namespace() {
+                warnings.push(not_owned(
+                    "It is not in the namespace of the release state".to_string(),
+                ));
+                continue;
+            }
+
+            if let Err(error) = transaction::add_owner_reference(
+                self.client.clone(),
+                object,
+                owner.clone(),
+                self.execute_options.field_manager.as_str(),
+            )
+            .await
+            {
+                warnings.push(not_owned(error.to_string()));
+            }
+        }
+
+        warnings
+    }
+
+    /// Wait until the objects of the release satisfy the given conditions. Fails if a condition
+    /// refers to an object that is not part of the release or if the timeout elapses.
+    pub async fn wait_for_conditions(
+        &self,
+        release: &release::Release,
+        conditions: &[condition::WaitCondition],
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let mut pending = Vec::new();
+
+        for condition in conditions {
+            let object = release
+                .objects()
+                .iter()
+                .find(|(identifier, _)| {
+                    identifier.kind() == condition.kind && identifier.name() == condition.name
+                })
+                .map(|(_, object)| object)
+                .ok_or_else(|| Error::UnknownWaitTarget(condition.clone()))?;
+
+            pending.push((condition, object));
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Backoff::new(Duration::from_secs(1), 1.5)
+            .with_max(CONDITION_POLL_INTERVAL)
+            .with_jitter(0.2);
+
+        loop {
+            let mut still_pending = Vec::new();
+
+            for (condition, object) in pending {
+                match transaction::get_object(self.client.clone(), object).await? {
+                    Some(live) if condition.is_met(&live) => {}
+                    _ => still_pending.push((condition, object)),
+                }
+            }
+
+            pending = still_pending;
+
+            if pending.is_empty() {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                let error = Error::ConditionTimeout(
+                    pending
+                        .into_iter()
+                        .map(|(condition, _)| condition.clone())
+                        .collect(),
+                );
+
+                let name = release.name();
+                if let Ok(Some(mut state)) = ReleaseState::get(&self.config_maps, name).await {
+                    // Not being ready is what gets reported, recording it is only a courtesy.
+                    let _ = state
+                        .record_attempt(
+                            &self.config_maps,
+                            name,
+                            self.max_object_size,
+                            AttemptStatus::NotReady,
+                            Some(error.to_string()),
+                        )
+                        .await;
+                }
+
+                return Err(error);
+            }
+
+            backoff.sleep().await;
+        }
+    }
+
+    /// Find out what a deploy of the release would do without changing anything. The release is
+    /// compared with its state, not with the objects in the cluster.
+    pub async fn plan(&self, release: &release::Release) -> Result<DeployResult, Error> {
+        let name = release.name();
+        let (targeted, _) = self.split_targeted(release.objects().clone());
+        let release = release::Release::from_objects(name.clone(), targeted);
+
+        let mut warnings = Vec::new();
+
+        let deployed = if self.no_state {
+            let (objects, skipped) = self.find_live_objects(name.as_str()).await?;
+            warnings.extend(skipped.into_iter().map(DeployWarning::DiscoverySkipped));
+            Some(objects)
+        } else {
+            ReleaseState::get(&self.config_maps, name.as_str())
+                .await?
+                .filter(|state| state.deleted_at.is_none())
+                .map(|state| state.current)
+        };
+
+        let old_objects = match deployed {
+            Some(old_objects) => old_objects,
+            None => {
+                let plan = plan::ReleasePlan::new(
+                    name.as_str(),
+                    release.objects(),
+                    &objects::Objects::empty(),
+                );
+                return Ok(DeployResult::Installed { plan, warnings });
+            }
+        };
+
+        let (old_objects, _) = self.split_targeted(old_objects);
+        let (old_objects, _retained) = self.split_prunable(old_objects, &release, &mut warnings);
+        let old_release = release::Release::from_objects(name.clone(), old_objects);
+
+        if let Some(reason) = self.unchanged_reason(&old_release, &release, &mut warnings) {
+            return Ok(DeployResult::Unchanged { reason, warnings });
+        }
+
+        let plan = plan::ReleasePlan::new(name.as_str(), release.objects(), old_release.objects());
+        Ok(DeployResult::Upgraded { plan, warnings })
+    }
+
+    /// Send the changes a deploy would make to the API server as a dry run. This runs validation,
+    /// defaulting and admission webhooks without persisting anything. The changes are those of
+    /// `plan` after the checks of a deploy. Returns the objects as the server would have stored
+    /// them, along with problems which did not fail the dry run.
+    pub async fn server_dry_run(
+        &self,
+        release: &release::Release,
+    ) -> Result<(Vec<DynamicObject>, Vec<DeployWarning>), Error> {
+        let (release, skipped) = self.preflight(release).await?;
+        let options = self.execute_options(release.name().as_str());
+
+        let result = self.plan(release.as_ref()).await?;
+        let mut warnings: Vec<DeployWarning> = skipped
+            .into_iter()
+            .map(DeployWarning::DiscoverySkipped)
+            .chain(result.warnings().iter().cloned())
+            .collect();
+
+        let plan = match result {
+            DeployResult::Unchanged { .. } => return Ok((Vec::new(), warnings)),
+            DeployResult::Installed { plan, .. } | DeployResult::Upgraded { plan, .. } => plan,
+        };
+
+        let steps = plan
+            .creations()
+            .iter()
+            .map(|creation| (transaction::Action::Create, creation.object()))
+            .chain(
+                plan.upgrades()
+                    .iter()
+                    .map(|upgrade| (transaction::Action::Apply, upgrade.new_object())),
+            )
+            .chain(
+                plan.deletions()
+                    .iter()
+                    .map(|deletion| (transaction::Action::Delete, deletion.object())),
+            );
+
+        let mut results = Vec::new();
+
+        for (action, object) in steps {
+            let result = transaction::dry_run_object(
+                self.client.clone(),
+                action,
+                object,
+                self.execute_options.field_manager.as_str(),
+            )
+            .await
+            .map_err(Error::DryRun)?;
+
+            if let (Some(directory), Some(object)) = (&options.output_dir, &result) {
+                if let Err(error) = plan::write_manifest(directory, object) {
+                    warnings.push(DeployWarning::Execution(
+                        plan::ExecuteWarning::ManifestNotWritten {
+                            name: object.metadata.name.clone().unwrap_or_default(),
+                            directory: directory.clone(),
+                            reason: error.to_string(),
+                        },
+                    ));
+                }
+            }
+
+            results.extend(result);
+        }
+
+        Ok((results, warnings))
+    }
+
+    /// Delete a release. When doing a dry run, the plan for the deletion is computed but nothing is
+    /// deleted. A lock that has been retained after a failure (see `with_retain_lock_on_failure`)
+    /// is removed along with the release.
+    pub async fn delete(
+        &self,
+        name: String,
+        dry_run: bool,
+    ) -> Result<Option<plan::ReleasePlan>, Error> {
+        if dry_run && self.no_state {
+            // Objects in skipped groups are left alone, see `with_strict_discovery`.
+            let (objects, _skipped) = self.find_live_objects(name.as_str()).await?;
+
+            if objects.is_empty() {
+                return Ok(None);
+            }
+
+            return Ok(Some(
+                release::Release::from_objects(name, objects).uninstall_plan(),
+            ));
+        }
+
+        if dry_run {
+            let state = ReleaseState::get(&self.config_maps, name.as_str()).await?;
+
+            return Ok(state
+                .filter(|state| state.deleted_at.is_none())
+                .map(|state| {
+                    release::Release::from_objects(name, state.current).uninstall_plan()
+                }));
+        }
+
+        // A lock retained after a failure is held by no one and would keep the deletion waiting
+        // forever, while deleting the release is what resolves the failure.
+        if self.locking {
+            self.remove_lock(name.as_str(), |lock| k8s::LockFailure::of(lock).is_some())
+                .await?;
+        }
+
+        let lock = self.lock(name.as_str()).await?;
+        let result = self.inner_delete(name.clone()).await;
+
+        match lock {
+            Some(lock) => lock.release().await?,
+
+            // Without locking, a lock left behind by an earlier operation is cleaned up as well.
+            None if result.is_ok() => {
+                self.unlock(name.as_str()).await?;
+            }
+
+            None => {}
+        }
+
+        result
+    }
+
+    /// Remove the lock of a release, e.g. after the process holding it has been killed. Only
+    /// ConfigMaps labelled as locks are removed. Returns whether a lock has been removed.
+    pub async fn unlock(&self, name: &str) -> Result<bool, Error> {
+        self.remove_lock(name, |_| true).await
+    }
+
+    /// Remove the lock of a release if it is labelled as a lock and satisfies the predicate.
+    /// Returns whether a lock has been removed.
+    async fn remove_lock<P>(&self, name: &str, predicate: P) -> Result<bool, Error>
+    where
+        P: Fn(&ConfigMap) -> bool,
+    {
+        let lock_name = release::lock_name(name);
+
+        let lock = match self.config_maps.get(lock_name.as_str()).await {
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                return Ok(false)
+            }
+            Err(error) => return Err(Error::Kube(error)),
+            Ok(lock) => lock,
+        };
+
+        if !lock.has_label(&k8s::ObjectType::Lock) || !predicate(&lock) {
+            return Ok(false);
+        }
+
+        self.config_maps
+            .delete(lock_name.as_str(), &kube::api::DeleteParams::default())
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Rewrite the release state in the format of this version. Only states written by older
+    /// versions whose format can still be read are migrated. Returns whether the state has been
+    /// rewritten.
+    pub async fn migrate(&self, name: String) -> Result<bool, Error> {
+        let lock = self.lock(name.as_str()).await?;
+        let result = self.inner_migrate(name).await;
+
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_migrate(&self, name: String) -> Result<bool, Error> {
+        let config_map = match self.config_maps.get(name.as_str()).await {
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                return Ok(false)
+            }
+            Err(error) => return Err(Error::Kube(error)),
+            Ok(config_map) => config_map,
+        };
+
+        if !config_map.has_label(&k8s::ObjectType::ReleaseState) {
+            return Err(ReleaseStateError::NameCollision { name }.into());
+        }
+
+        if let Some(version) = k8s::CrateVersion::of(&config_map) {
+            match k8s::CrateVersion::compare(version.as_str()) {
+                Some(Ordering::Equal) => return Ok(false),
+                Some(Ordering::Less) => {}
+                _ => return Err(ReleaseStateError::IncompatibleVersion { name, version }.into()),
+            }
+        }
+
+        let state = ReleaseState::parse_config_map(&self.config_maps, &config_map).await?;
+        state
+            .apply(&self.config_maps, name.as_str(), self.max_object_size)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Serialize the state of a release as JSON, e.g. to back it up. The state is exported
+    /// regardless of the version that wrote it.
+    pub async fn export_state(&self, name: &str) -> Result<Option<String>, Error> {
+        let config_map = match self.config_maps.get(name).await {
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => return Ok(None),
+            Err(error) => return Err(Error::Kube(error)),
+            Ok(config_map) => config_map,
+        };
+
+        if !config_map.has_label(&k8s::ObjectType::ReleaseState) {
+            return Err(ReleaseStateError::NameCollision {
+                name: name.to_string(),
+            }
+            .into());
+        }
+
+        let state = ReleaseState::parse_config_map(&self.config_maps, &config_map).await?;
+        let json = serde_json::to_string_pretty(&state).map_err(ReleaseStateError::from)?;
+
+        Ok(Some(json))
+    }
+
+    /// Write a state exported by `export_state` for a release which has no state, e.g. because
+    /// its state has been deleted by accident. The state is validated before anything is written.
+    pub async fn import_state(&self, name: String, json: &str) -> Result<(), Error> {
+        let state = ReleaseState::from_json(json)?;
+
+        let lock = self.lock(name.as_str()).await?;
+        let result = self.inner_import_state(name, state).await;
+
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_import_state(&self, name: String, state: ReleaseState) -> Result<(), Error> {
+        if ReleaseState::get(&self.config_maps, name.as_str())
+            .await?
+            .is_some()
+        {
+            return Err(Error::ReleaseExists(name));
+        }
+
+        state
+            .apply(&self.config_maps, name.as_str(), self.max_object_size)
+            .await?;
+        Ok(())
+    }
+
+    async fn inner_delete(&self, name: String) -> Result<Option<plan::ReleasePlan>, Error> {
+        let options = self.execute_options(name.as_str());
+
+        if self.no_state {
+            // Objects in skipped groups are left alone, see `with_strict_discovery`.
+            let (objects, _skipped) = self.find_live_objects(name.as_str()).await?;
+
+            if objects.is_empty() {
+                return Ok(None);
+            }
+
+            let release = release::Release::from_objects(name, objects);
+            let (_client, plan, _applied) = release
+                .uninstall(self.client.clone(), &options)
+                .await
+                .map_err(|error| Error::Release {
+                    error: Box::new(error),
+                    state: ReleaseState::new(release.objects().clone()),
+                })?;
+
+            return Ok(Some(plan));
+        }
+
+        let state = ReleaseState::get(&self.config_maps, name.as_str())
+            .await?
+            .filter(|state| state.deleted_at.is_none());
+
+        if let Some(mut state) = state {
+            let release = release::Release::from_objects(name, state.current.clone());
+
+            let (_client, plan, _applied) = release
+                .uninstall(self.client.clone(), &options)
+                .await
+                .map_err(|error| Error::Release {
+                    error: Box::new(error),
+                    state: state.clone(),
+                })?;
+
+            if self.keep_state {
+                state.history.insert(0, state.current);
+                state.current = objects::Objects::empty();
+                state.deleted_at = Some(
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_secs()),
+                );
+                state
+                    .apply(&self.config_maps, release.name(), self.max_object_size)
+                    .await?;
+            } else {
+                ReleaseState::delete(&self.config_maps, release.name()).await?;
+            }
+
+            Ok(Some(plan))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Complete the rollback of an interrupted deploy or delete using the checkpoint it left
+    /// behind. Returns whether there was anything to recover.
+    pub async fn recover(&self, name: String) -> Result<bool, Error> {
+        let lock = self.lock(name.as_str()).await?;
+        let result = self.inner_recover(name).await;
+
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_recover(&self, name: String) -> Result<bool, Error> {
+        let checkpoint_name = release::checkpoint_name(name.as_str());
+
+        let checkpoint = match checkpoint::Checkpoint::load(
+            &self.config_maps,
+            checkpoint_name.as_str(),
+        )
+        .await?
+        {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(false),
+        };
+
+        checkpoint
+            .to_plan()
+            .execute(self.client.clone(), &self.execute_options)
+            .await?;
+        checkpoint::Checkpoint::discard(&self.config_maps, checkpoint_name.as_str()).await?;
+
+        Ok(true)
+    }
+
+    /// Re-apply the objects of a deployed release which are missing or have drifted from the
+    /// release state. Returns the identifiers of the re-applied objects.
+    pub async fn repair(&self, name: String) -> Result<Vec<Identifier>, Error> {
+        let lock = self.lock(name.as_str()).await?;
+        let result = self.inner_repair(name).await;
+
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_repair(&self, name: String) -> Result<Vec<Identifier>, Error> {
+        let state = match ReleaseState::get(&self.config_maps, name.as_str()).await? {
+            Some(state) => state,
+            None => return Ok(Vec::new()),
+        };
+
+        let report = self.verify_report(name.clone(), &Selector::new()).await?;
+        let drifted = report.drifted();
+        let mut repaired = Vec::new();
+
+        for (identifier, object) in state.current {
+            let identifier = identifier.with_default_namespace(self.namespace());
+
+            if !drifted.contains(&&identifier) {
+                continue;
+            }
+
+            let object = plan::ReleasePlan::tag_object(name.clone(), object);
+            transaction::apply_object(
+                self.client.clone(),
+                &object,
+                self.execute_options.field_manager.as_str(),
+            )
+            .await
+            .map_err(Error::Repair)?;
+
+            repaired.push(identifier);
+        }
+
+        Ok(repaired)
+    }
+
+    /// Bring objects which already exist in the cluster under the management of a release. The
+    /// objects are applied in place and recorded in the release state, nothing is recreated.
+    /// Fails without changing anything if one of the objects does not exist.
+    pub async fn adopt(&self, release: &release::Release) -> Result<Vec<Identifier>, Error> {
+        let lock = self.lock(release.name()).await?;
+        let result = self.inner_adopt(release).await;
+
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_adopt(&self, release: &release::Release) -> Result<Vec<Identifier>, Error> {
+        let name = release.name();
+        let mut missing = Vec::new();
+
+        for (identifier, object) in release.objects().iter() {
+            let api: kube::Api<DynamicObject> =
+                kube::Api::default_namespaced_with(self.client.clone(), &object.api_resource);
+
+            match api.get(identifier.name()).await {
+                Ok(_) => {}
+                Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                    missing.push(identifier.clone());
+                }
+                Err(error) => return Err(Error::Kube(error)),
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::NotFound(missing));
+        }
+
+        let mut adopted = Vec::new();
+
+        for (identifier, object) in release.objects().iter() {
+            let object = plan::ReleasePlan::tag_object(name.clone(), object.clone());
+
+            transaction::apply_object(
+                self.client.clone(),
+                &object,
+                self.execute_options.field_manager.as_str(),
+            )
+            .await
+            .map_err(Error::Adopt)?;
+
+            adopted.push(identifier.clone());
+        }
+
+        let state = match ReleaseState::get(&self.config_maps, name.as_str()).await? {
+            Some(mut state) => {
+                let current = state.current.clone().union(release.objects().clone());
+                state.history.insert(0, state.current);
+                state.current = current;
+                state
+            }
+
+            None => ReleaseState::new(release.objects().clone()),
+        };
+
+        state
+            .apply(&self.config_maps, name.as_str(), self.max_object_size)
+            .await?;
+
+        Ok(adopted)
+    }
+
+    /// Rename a release. The objects of the release are relabelled, including the recommended
+    /// instance label if they carry it, and the release state is moved to the new name along with
+    /// the ownership of owned objects. Both names are locked meanwhile. Returns the number of
+    /// relabelled objects.
+    pub async fn rename(&self, old_name: String, new_name: String) -> Result<usize, Error> {
+        let old_lock = self.lock(old_name.as_str()).await?;
+        let new_lock = self.lock(new_name.as_str()).await?;
+        let result = self.inner_rename(old_name, new_name).await;
+
+        if let Some(lock) = new_lock {
+            lock.release().await?;
+        }
+
+        if let Some(lock) = old_lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_rename(&self, old_name: String, new_name: String) -> Result<usize, Error> {
+        let mut state = ReleaseState::get(&self.config_maps, old_name.as_str())
+            .await?
+            .ok_or_else(|| Error::NoRelease(old_name.clone()))?;
+
+        if ReleaseState::get(&self.config_maps, new_name.as_str())
+            .await?
+            .is_some()
+        {
+            return Err(Error::ReleaseExists(new_name));
+        }
+
+        state.rename_instance(old_name.as_str(), new_name.as_str());
+        let instance = k8s::Instance(new_name.clone());
+        let release_labels = labels::Labels::from(k8s::ReleaseName(new_name.clone()));
+
+        for (_, object) in state.current.iter() {
+            let labels = if object.has_label(&instance) {
+                release_labels.clone().add(instance.clone())
+            } else {
+                release_labels.clone()
+            };
+
+            transaction::set_labels(self.client.clone(), object, &labels)
+                .await
+                .map_err(Error::Relabel)?;
+        }
+
+        // The new state is in place before the old one goes, so the release is never lost.
+        state
+            .apply(&self.config_maps, new_name.as_str(), self.max_object_size)
+            .await?;
+        self.transfer_ownership(&state.current, old_name.as_str(), new_name.as_str())
+            .await?;
+        ReleaseState::delete(&self.config_maps, old_name.as_str()).await?;
+
+        Ok(state.current.len())
+    }
+
+    /// Make the state of the new name the owner of the objects owned by the state of the old name,
+    /// which would otherwise be garbage collected along with it, see `with_owner_references`.
+    async fn transfer_ownership(
+        &self,
+        objects: &objects::Objects,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), Error> {
+        let old_uid = self.config_maps.get(old_name).await?.meta().uid.clone();
+        let new_state_config_map = self.config_maps.get(new_name).await?;
+
+        let owner = OwnerReference {
+            api_version: "v1".to_string(),
+            kind: "ConfigMap".to_string(),
+            name: new_name.to_string(),
+            uid: new_state_config_map.meta().uid.clone().unwrap_or_default(),
+            ..OwnerReference::default()
+        };
+
+        for (_, object) in objects.iter() {
+            let owned = transaction::get_object(self.client.clone(), object)
+                .await?
+                .map_or(false, |live| {
+                    live.metadata
+                        .owner_references
+                        .iter()
+                        .any(|reference| Some(&reference.uid) == old_uid.as_ref())
+                });
+
+            // The reference is applied by the same field manager as the old one, which it
+            // therefore replaces.
+            if owned {
+                transaction::add_owner_reference(
+                    self.client.clone(),
+                    object,
+                    owner.clone(),
+                    self.execute_options.field_manager.as_str(),
+                )
+                .await
+                .map_err(Error::Relabel)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove an object from a release without deleting it. The object loses the labels that mark
+    /// it as managed and is no longer part of the release state, so it survives later upgrades and
+    /// the deletion of the release. Returns the identifier of the orphaned object, if the release
+    /// contains an object of the given kind and name.
+    pub async fn orphan(
+        &self,
+        name: String,
+        kind: &str,
+        object_name: &str,
+    ) -> Result<Option<Identifier>, Error> {
+        let lock = self.lock(name.as_str()).await?;
+        let result = self.inner_orphan(name, kind, object_name).await;
+
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+
+        result
+    }
+
+    async fn inner_orphan(
+        &self,
+        name: String,
+        kind: &str,
+        object_name: &str,
+    ) -> Result<Option<Identifier>, Error> {
+        let mut state = match ReleaseState::get(&self.config_maps, name.as_str()).await? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+
+        let identifier = state
+            .current
+            .iter()
+            .map(|(identifier, _)| identifier)
+            .find(|identifier| {
+                identifier.kind().eq_ignore_ascii_case(kind) && identifier.name() == object_name
+            })
+            .cloned();
+
+        let identifier = match identifier {
+            Some(identifier) => identifier,
+            None => return Ok(None),
+        };
+
+        if let Some(object) = state.current.remove(&identifier) {
+            let labels = labels::Labels::new()
+                .add(k8s::ObjectType::Managed)
+                .add(k8s::ReleaseName(name.clone()));
+            let annotations = annotations::Annotations::from(k8s::CrateVersion);
+
+            transaction::strip_metadata(self.client.clone(), &object, &labels, &annotations)
+                .await
+                .map_err(Error::Orphan)?;
+        }
+
+        state
+            .apply(&self.config_maps, name.as_str(), self.max_object_size)
+            .await?;
+
+        Ok(Some(identifier))
+    }
+
+    /// List all releases, including deleted ones whose state has been kept. If the selector is not
+    /// empty, only releases with at least one object matching the selector are listed.
+    pub async fn list(&self, selector: &Selector) -> Result<Vec<ListedRelease>, Error> {
+        let config_maps = self
+            .config_maps
+            .list(&labels::Labels::from(k8s::ObjectType::ReleaseState).to_listparams())
+            .await?;
+
+        let mut releases = Vec::new();
+
+        for config_map in config_maps.items {
+            let name = match &config_map.metadata.name {
+                Some(name) => name.clone(),
+                None => continue,
+            };
+
+            if !selector.is_empty() {
+                let state = ReleaseState::from_config_map(&self.config_maps, &config_map).await?;
+                let matches = state
+                    .current
+                    .iter()
+                    .any(|(_, object)| selector.matches(&object.meta().labels));
+
+                if !matches {
+                    continue;
+                }
+            }
+
+            // States written by other versions are listed, even if they cannot be used.
+            let deleted_at = ReleaseState::parse_config_map(&self.config_maps, &config_map)
+                .await
+                .ok()
+                .and_then(|state| state.deleted_at);
+
+            releases.push(ListedRelease {
+                last_verification: k8s::LastVerification::of(&config_map),
+                name,
+                deleted_at,
+            });
+        }
+
+        releases.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+        Ok(releases)
+    }
+
+    /// Verify that the objects of a release match the objects in the cluster. If the selector is
+    /// not empty, only objects matching the selector are verified. This fails with the first
+    /// discrepancy, use `verify_report` to find all of them.
+    pub async fn verify(
+        &self,
+        release_name: String,
+        selector: &Selector,
+    ) -> Result<(), VerificationError> {
+        self.verify_report(release_name, selector)
+            .await?
+            .into_result()
+    }
+
+    /// Compare the objects of a release with the objects in the cluster and report all
+    /// discrepancies. If the selector is not empty, only objects matching the selector are
+    /// compared.
+    pub async fn verify_report(
+        &self,
+        release_name: String,
+        selector: &Selector,
+    ) -> Result<VerificationReport, VerificationError> {
         let state = ReleaseState::get(&self.config_maps, release_name.as_str())
             .await?
-            .ok_or(VerificationError::NoDeployedRelease)?;
+            .filter(|state| state.deleted_at.is_none())
+            .ok_or_else(|| VerificationError::NoDeployedRelease {
+                release_name: release_name.clone(),
+            })?;
+
+        let (real_objects, skipped) = verify::find_release_objects(
+            self.client.clone(),
+            release_name.clone(),
+            selector,
+            self.strict_discovery,
+            self.verify_concurrency,
+        )
+        .await?;
+
+        let unmatched =
+            self.unmatched_targets(state.current.iter().map(|(identifier, _)| identifier));
+
+        if !unmatched.is_empty() {
+            return Err(VerificationError::UnmatchedTargets(unmatched));
+        }
+
+        let (targeted, _) = self.split_targeted(state.current);
+        let desired_objects = targeted
+            .into_iter()
+            .filter(|(_, desired)| selector.matches(&desired.meta().labels));
 
-        let real_objects =
-            verify::find_release_objects(self.client.clone(), release_name.clone()).await?;
+        let mut report = VerificationReport {
+            skipped,
+            ..VerificationReport::default()
+        };
 
-        for (identifier, desired) in state.current {
+        for (identifier, desired) in desired_objects {
             let desired = plan::ReleasePlan::tag_object(release_name.clone(), desired);
 
-            let reality = real_objects
-                .get(&identifier)
-                .ok_or_else(|| VerificationError::MissingObject(identifier.clone()))?;
+            // Objects without a namespace have been deployed to the namespace of the manager.
+            let identifier = identifier.with_default_namespace(self.namespace());
+
+            let reality = match real_objects.get(&identifier) {
+                Some(reality) => reality,
+                None => {
+                    report.missing.push(identifier);
+                    continue;
+                }
+            };
 
             let desired_meta = desired.meta();
             let reality_meta = reality.meta();
 
             if !verify::check_mapping(&desired_meta.annotations, &reality_meta.annotations) {
-                return Err(VerificationError::MismatchingAnnotations {
+                report.mismatched_annotations.push(MappingMismatch {
                     identifier: identifier.clone(),
                     desired: desired_meta.annotations.clone(),
                     reality: reality_meta.annotations.clone(),
@@ -209,19 +2594,186 @@ impl Manager {
             }
 
             if !verify::check_mapping(&desired_meta.labels, &reality_meta.labels) {
-                return Err(VerificationError::MismatchingLabels {
+                report.mismatched_labels.push(MappingMismatch {
                     identifier: identifier.clone(),
                     desired: desired_meta.labels.clone(),
                     reality: reality_meta.labels.clone(),
                 });
             }
 
-            verify::check_value(
+            if let Err(mismatch) = verify::check_value(
                 &desired.dyn_object.data,
                 &reality.dyn_object.data,
                 VecDeque::new(),
+                &self.list_keys,
+            ) {
+                report.mismatched_data.push((identifier, mismatch));
+            }
+        }
+
+        if self.record_verify {
+            report.unrecorded = self
+                .record_verification(release_name.as_str(), report.is_ok())
+                .await
+                .err()
+                .map(|error| error.to_string());
+        }
+
+        Ok(report)
+    }
+
+    /// Annotate the state of the release with the outcome of a verification.
+    async fn record_verification(
+        &self,
+        release_name: &str,
+        in_sync: bool,
+    ) -> Result<(), kube::Error> {
+        let verification = k8s::LastVerification {
+            verified_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            in_sync,
+        };
+
+        let annotated = ConfigMap::default().with_annotations(&verification.to_annotations());
+        let patch = serde_json::json!({
+            "metadata": { "annotations": annotated.metadata.annotations }
+        });
+
+        self.config_maps
+            .patch(
+                release_name,
+                &kube::api::PatchParams::default(),
+                &kube::api::Patch::Merge(patch),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Outcome of a single check of `Manager::doctor`
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub description: String,
+
+    /// Reason why the check failed, unless it passed
+    pub problem: Option<String>,
+}
+
+/// Outcome of all checks of `Manager::doctor`
+#[derive(Clone, Debug, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    /// Did all checks pass?
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.problem.is_none())
+    }
+
+    fn record<D: fmt::Display>(&mut self, description: String, problem: Option<D>) {
+        self.checks.push(Check {
+            description,
+            problem: problem.map(|problem| problem.to_string()),
+        });
+    }
+}
+
+/// Mismatch between the desired and the real labels or annotations of an object
+#[derive(Clone, Debug)]
+pub struct MappingMismatch {
+    pub identifier: Identifier,
+    pub desired: BTreeMap<String, String>,
+    pub reality: BTreeMap<String, String>,
+}
+
+/// All discrepancies between a release and the objects in the cluster
+#[derive(Clone, Debug, Default)]
+pub struct VerificationReport {
+    pub missing: Vec<Identifier>,
+    pub mismatched_labels: Vec<MappingMismatch>,
+    pub mismatched_annotations: Vec<MappingMismatch>,
+    pub mismatched_data: Vec<(Identifier, verify::Mismatch)>,
+
+    /// API groups whose objects could not be listed, see `Manager::with_strict_discovery`
+    pub skipped: Vec<api_resource::SkippedGroup>,
+
+    /// Why the outcome could not be recorded on the release state, see
+    /// `Manager::with_record_verify`. The verification has happened regardless.
+    pub unrecorded: Option<String>,
+}
+
+impl VerificationReport {
+    /// Were no discrepancies found?
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+            && self.mismatched_labels.is_empty()
+            && self.mismatched_annotations.is_empty()
+            && self.mismatched_data.is_empty()
+    }
+
+    /// Identifiers of all objects which are missing or don't match the release
+    pub fn drifted(&self) -> Vec<&Identifier> {
+        let mut drifted: Vec<&Identifier> = Vec::new();
+
+        let identifiers = self
+            .missing
+            .iter()
+            .chain(
+                self.mismatched_annotations
+                    .iter()
+                    .map(|mismatch| &mismatch.identifier),
+            )
+            .chain(
+                self.mismatched_labels
+                    .iter()
+                    .map(|mismatch| &mismatch.identifier),
             )
-            .map_err(|path| VerificationError::MismatchingData { path })?;
+            .chain(
+                self.mismatched_data
+                    .iter()
+                    .map(|(identifier, _)| identifier),
+            );
+
+        for identifier in identifiers {
+            if !drifted.contains(&identifier) {
+                drifted.push(identifier);
+            }
+        }
+
+        drifted
+    }
+
+    /// Turn the first discrepancy into an error.
+    pub fn into_result(self) -> Result<(), VerificationError> {
+        if let Some(identifier) = self.missing.into_iter().next() {
+            return Err(VerificationError::MissingObject(identifier));
+        }
+
+        if let Some(mismatch) = self.mismatched_annotations.into_iter().next() {
+            return Err(VerificationError::MismatchingAnnotations {
+                identifier: mismatch.identifier,
+                desired: mismatch.desired,
+                reality: mismatch.reality,
+            });
+        }
+
+        if let Some(mismatch) = self.mismatched_labels.into_iter().next() {
+            return Err(VerificationError::MismatchingLabels {
+                identifier: mismatch.identifier,
+                desired: mismatch.desired,
+                reality: mismatch.reality,
+            });
+        }
+
+        if let Some((_identifier, mismatch)) = self.mismatched_data.into_iter().next() {
+            return Err(VerificationError::MismatchingData {
+                path: mismatch.path,
+                expected: mismatch.expected,
+                actual: mismatch.actual,
+            });
         }
 
         Ok(())
@@ -232,7 +2784,9 @@ impl Manager {
 pub enum VerificationError {
     ReleaseStateError(ReleaseStateError),
     KubeError(kube::Error),
-    NoDeployedRelease,
+    NoDeployedRelease {
+        release_name: String,
+    },
     MissingObject(Identifier),
     MismatchingLabels {
         identifier: Identifier,
@@ -246,7 +2800,57 @@ pub enum VerificationError {
     },
     MismatchingData {
         path: VecDeque<String>,
+        expected: serde_json::Value,
+        actual: Option<serde_json::Value>,
     },
+    UnmatchedTargets(Vec<identifier::Target>),
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            VerificationError::ReleaseStateError(error) => write!(formatter, "{}", error),
+            VerificationError::KubeError(error) => write!(formatter, "Kubernetes error: {}", error),
+            VerificationError::NoDeployedRelease { release_name } => {
+                write!(formatter, "Release {} is not deployed", release_name)
+            }
+            VerificationError::MissingObject(identifier) => {
+                write!(formatter, "{} is missing", identifier)
+            }
+            VerificationError::MismatchingLabels { identifier, .. } => {
+                write!(formatter, "Labels of {} do not match", identifier)
+            }
+            VerificationError::MismatchingAnnotations { identifier, .. } => {
+                write!(formatter, "Annotations of {} do not match", identifier)
+            }
+            VerificationError::MismatchingData {
+                path,
+                expected,
+                actual,
+            } => write!(
+                formatter,
+                "Data at {} does not match: expected {}, found {}",
+                path.iter().cloned().collect::<Vec<_>>().join("."),
+                expected,
+                actual
+                    .as_ref()
+                    .map_or_else(|| "nothing".to_string(), |actual| actual.to_string())
+            ),
+            VerificationError::UnmatchedTargets(targets) => {
+                write!(formatter, "No object matches {}", join_targets(targets))
+            }
+        }
+    }
+}
+
+impl error::Error for VerificationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            VerificationError::ReleaseStateError(error) => Some(error),
+            VerificationError::KubeError(error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 impl From<kube::Error> for VerificationError {
@@ -267,6 +2871,71 @@ pub enum ReleaseStateError {
     JSONError(serde_json::Error),
     UpdateError(transaction::Error),
     KubeError(kube::Error),
+
+    /// A ConfigMap with the name of the release exists, but it wasn't created by us
+    NameCollision {
+        name: String,
+    },
+
+    /// The release state has been written by an incompatible version of this crate
+    IncompatibleVersion {
+        name: String,
+        version: String,
+    },
+
+    /// An object of an imported state is stored under an identifier that doesn't match it
+    MismatchedIdentifier {
+        identifier: Identifier,
+    },
+}
+
+impl fmt::Display for ReleaseStateError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            ReleaseStateError::CorruptReleaseState(config_map) => write!(
+                formatter,
+                "Release state {} is corrupt",
+                config_map.metadata.name.as_deref().unwrap_or("")
+            ),
+            ReleaseStateError::JSONError(error) => {
+                write!(formatter, "Malformed release state: {}", error)
+            }
+            ReleaseStateError::UpdateError(error) => {
+                write!(formatter, "Failed to update release state: {}", error)
+            }
+            ReleaseStateError::KubeError(error) => write!(formatter, "Kubernetes error: {}", error),
+            ReleaseStateError::NameCollision { name } => write!(
+                formatter,
+                "Name collision: a ConfigMap named {} exists which is not managed by {}; use a different release name",
+                name,
+                meta::CRATE_NAME
+            ),
+            ReleaseStateError::IncompatibleVersion { name, version } => write!(
+                formatter,
+                "Release state {} was written by {} {}, which is incompatible with {}; states of older versions can be upgraded with the migrate command",
+                name,
+                meta::CRATE_NAME,
+                version,
+                meta::CRATE_VERSION
+            ),
+            ReleaseStateError::MismatchedIdentifier { identifier } => write!(
+                formatter,
+                "Release state contains an object under {} which doesn't match its identifier",
+                identifier
+            ),
+        }
+    }
+}
+
+impl error::Error for ReleaseStateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ReleaseStateError::JSONError(error) => Some(error),
+            ReleaseStateError::UpdateError(error) => Some(error),
+            ReleaseStateError::KubeError(error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 impl From<serde_json::Error> for ReleaseStateError {
@@ -275,32 +2944,195 @@ impl From<serde_json::Error> for ReleaseStateError {
     }
 }
 
+/// Release found by `Manager::list`
+#[derive(Clone, Debug)]
+pub struct ListedRelease {
+    pub name: String,
+
+    /// Time at which the release has been deleted, see `Manager::with_keep_state`
+    pub deleted_at: Option<u64>,
+
+    /// Outcome of the last recorded verification, see `Manager::with_record_verify`
+    pub last_verification: Option<k8s::LastVerification>,
+}
+
+/// Outcome of a deploy attempt
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AttemptStatus {
+    /// The deploy has started but not finished, e.g. because the process was killed
+    InProgress,
+
+    /// The deploy succeeded
+    Succeeded,
+
+    /// The deploy failed and its changes have been rolled back
+    RolledBack,
+
+    /// The deploy failed and rolling back its changes failed as well
+    Failed,
+
+    /// The deploy succeeded, but its objects did not reach the awaited conditions in time
+    NotReady,
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct ReleaseState {
     current: objects::Objects,
     history: Vec<objects::Objects>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_attempt_status: Option<AttemptStatus>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+
+    /// Seconds since the Unix epoch at which the release has been deleted, if its state has been
+    /// kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<u64>,
 }
 
 impl ReleaseState {
-    fn from_config_map(config_map: &ConfigMap) -> Result<Self, ReleaseStateError> {
-        let data = config_map
+    fn new(current: objects::Objects) -> Self {
+        ReleaseState {
+            current,
+            history: Vec::new(),
+            last_attempt_status: Some(AttemptStatus::Succeeded),
+            last_error: None,
+            deleted_at: None,
+        }
+    }
+
+    /// Outcome of the most recent deploy attempt, if known
+    pub fn last_attempt_status(&self) -> Option<AttemptStatus> {
+        self.last_attempt_status
+    }
+
+    /// Error of the most recent deploy attempt, if it failed
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Time at which the release has been deleted, in seconds since the Unix epoch
+    pub fn deleted_at(&self) -> Option<u64> {
+        self.deleted_at
+    }
+
+    /// Replace the old name of the release with the new one in the recommended instance label of
+    /// all objects which carry it, see `Release::with_recommended_labels`.
+    fn rename_instance(&mut self, old_name: &str, new_name: &str) {
+        let old_instance = k8s::Instance(old_name.to_string());
+        let new_instance = k8s::Instance(new_name.to_string());
+
+        let rename = |objects: objects::Objects| -> objects::Objects {
+            objects
+                .into_iter()
+                .map(|(identifier, object)| {
+                    if object.has_label(&old_instance) {
+                        (identifier, object.with_label(&new_instance))
+                    } else {
+                        (identifier, object)
+                    }
+                })
+                .collect()
+        };
+
+        self.current = rename(std::mem::take(&mut self.current));
+        self.history = self.history.drain(..).map(rename).collect();
+    }
+
+    /// Remember the outcome of a deploy attempt. Failing to do so does not affect the deploy.
+    async fn record_attempt(
+        &mut self,
+        api: &kube::Api<ConfigMap>,
+        name: &str,
+        max_size: usize,
+        status: AttemptStatus,
+        error: Option<String>,
+    ) -> Result<(), ReleaseStateError> {
+        self.last_attempt_status = Some(status);
+        self.last_error = error;
+
+        self.apply(api, name, max_size).await
+    }
+
+    async fn from_config_map(
+        api: &kube::Api<ConfigMap>,
+        config_map: &ConfigMap,
+    ) -> Result<Self, ReleaseStateError> {
+        if let Some(version) = k8s::CrateVersion::of(config_map) {
+            if k8s::CrateVersion::compare(version.as_str()) != Some(Ordering::Equal) {
+                return Err(ReleaseStateError::IncompatibleVersion {
+                    name: config_map.metadata.name.clone().unwrap_or_default(),
+                    version,
+                });
+            }
+        }
+
+        Self::parse_config_map(api, config_map).await
+    }
+
+    /// Read the state regardless of the version that wrote it.
+    async fn parse_config_map(
+        api: &kube::Api<ConfigMap>,
+        config_map: &ConfigMap,
+    ) -> Result<Self, ReleaseStateError> {
+        if let Some(data) = config_map.data.get(STATE_KEY) {
+            return Ok(serde_json::from_str(data.as_str())?);
+        }
+
+        // States that don't fit into a single ConfigMap are split into chunks, see `apply`.
+        let count: usize = config_map
             .data
-            .get("release_state")
+            .get(CHUNKS_KEY)
+            .and_then(|count| count.parse().ok())
             .ok_or_else(|| ReleaseStateError::CorruptReleaseState(config_map.clone()))?;
+        let name = config_map.metadata.name.as_deref().unwrap_or_default();
+
+        let mut data = String::new();
+
+        for index in 0..count {
+            let chunk = api
+                .get(chunk_name(name, index).as_str())
+                .await
+                .map_err(ReleaseStateError::KubeError)?;
+            let part = chunk
+                .data
+                .get(STATE_KEY)
+                .ok_or_else(|| ReleaseStateError::CorruptReleaseState(chunk.clone()))?;
+
+            data.push_str(part);
+        }
 
         Ok(serde_json::from_str(data.as_str())?)
     }
 
-    fn to_config_map(&self) -> Result<ConfigMap, ReleaseStateError> {
+    /// Read a state from JSON as written by `Manager::export_state`, making sure every object is
+    /// stored under its own identifier.
+    fn from_json(json: &str) -> Result<Self, ReleaseStateError> {
+        let state: Self = serde_json::from_str(json)?;
+
+        for objects in std::iter::once(&state.current).chain(state.history.iter()) {
+            for (identifier, object) in objects {
+                if object.identifier().as_ref() != Some(identifier) {
+                    return Err(ReleaseStateError::MismatchedIdentifier {
+                        identifier: identifier.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(state)
+    }
+
+    fn new_config_map(object_type: k8s::ObjectType, name: String, release_name: &str) -> ConfigMap {
         let mut config_map = ConfigMap::default()
-            .with_label(&k8s::ObjectType::ReleaseState)
+            .with_label(&object_type)
+            .with_label(&k8s::ReleaseName(release_name.to_string()))
             .with_annotation(&k8s::CrateVersion);
 
+        config_map.metadata.name = Some(name);
         config_map
-            .data
-            .insert("release_state".to_string(), serde_json::to_string(&self)?);
-
-        Ok(config_map)
     }
 
     pub async fn get(
@@ -314,18 +3146,463 @@ impl ReleaseState {
 
             Err(err) => Err(ReleaseStateError::KubeError(err)),
 
-            Ok(value) => Ok(Some(ReleaseState::from_config_map(&value)?)),
+            Ok(value) if !value.has_label(&k8s::ObjectType::ReleaseState) => {
+                Err(ReleaseStateError::NameCollision {
+                    name: name.to_string(),
+                })
+            }
+
+            Ok(value) => Ok(Some(ReleaseState::from_config_map(api, &value).await?)),
         }
     }
 
-    async fn apply(&self, api: &kube::Api<ConfigMap>, name: &str) -> Result<(), ReleaseStateError> {
-        let mut config_map = self.to_config_map()?;
-        config_map.metadata.name = Some(name.to_string());
+    /// Write the state. States larger than `max_size` bytes are split into chunks which are stored
+    /// in ConfigMaps of their own, since a single ConfigMap must not exceed 1 MiB.
+    async fn apply(
+        &self,
+        api: &kube::Api<ConfigMap>,
+        name: &str,
+        max_size: usize,
+    ) -> Result<(), ReleaseStateError> {
+        let data = serde_json::to_string(&self)?;
+        let mut config_map =
+            Self::new_config_map(k8s::ObjectType::ReleaseState, name.to_string(), name);
+
+        let chunks = split_chunks(data.as_str(), max_size);
+        let chunk_count = if chunks.len() > 1 { chunks.len() } else { 0 };
+
+        if chunk_count == 0 {
+            config_map.data.insert(STATE_KEY.to_string(), data.clone());
+        } else {
+            // The chunks are in place before the index that refers to them.
+            for (index, chunk) in chunks.into_iter().enumerate() {
+                let chunk_name = chunk_name(name, index);
+                Self::check_chunk_name(api, chunk_name.as_str(), name).await?;
+
+                let mut chunk_map =
+                    Self::new_config_map(k8s::ObjectType::ReleaseStateChunk, chunk_name, name);
+                chunk_map
+                    .data
+                    .insert(STATE_KEY.to_string(), chunk.to_string());
+
+                transaction::apply(api, &chunk_map)
+                    .await
+                    .map_err(ReleaseStateError::UpdateError)?;
+            }
+
+            config_map
+                .data
+                .insert(CHUNKS_KEY.to_string(), chunk_count.to_string());
+        }
 
         transaction::apply(api, &config_map)
             .await
             .map_err(ReleaseStateError::UpdateError)?;
 
+        Self::delete_chunks(api, name, chunk_count).await
+    }
+
+    /// Make sure a ConfigMap with the name of the chunk is either absent or a chunk of the state of
+    /// the same release, so writing the chunk does not overwrite anything else.
+    async fn check_chunk_name(
+        api: &kube::Api<ConfigMap>,
+        chunk_name: &str,
+        release_name: &str,
+    ) -> Result<(), ReleaseStateError> {
+        match api.get(chunk_name).await {
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(()),
+
+            Err(error) => Err(ReleaseStateError::KubeError(error)),
+
+            Ok(config_map)
+                if config_map.has_label(&k8s::ObjectType::ReleaseStateChunk)
+                    && config_map.has_label(&k8s::ReleaseName(release_name.to_string())) =>
+            {
+                Ok(())
+            }
+
+            Ok(_) => Err(ReleaseStateError::NameCollision {
+                name: chunk_name.to_string(),
+            }),
+        }
+    }
+
+    /// Delete the state and its chunks.
+    async fn delete(api: &kube::Api<ConfigMap>, name: &str) -> Result<(), ReleaseStateError> {
+        api.delete(name, &kube::api::DeleteParams::default())
+            .await
+            .map_err(ReleaseStateError::KubeError)?;
+
+        Self::delete_chunks(api, name, 0).await
+    }
+
+    /// Delete the chunks of the state except for the first `keep` ones.
+    async fn delete_chunks(
+        api: &kube::Api<ConfigMap>,
+        name: &str,
+        keep: usize,
+    ) -> Result<(), ReleaseStateError> {
+        let labels = labels::Labels::from(k8s::ObjectType::ReleaseStateChunk)
+            .add(k8s::ReleaseName(name.to_string()));
+
+        let chunks = api
+            .list(&labels.to_listparams())
+            .await
+            .map_err(ReleaseStateError::KubeError)?;
+        let kept: Vec<String> = (0..keep).map(|index| chunk_name(name, index)).collect();
+
+        for chunk in chunks.items {
+            let chunk_name = chunk.metadata.name.unwrap_or_default();
+
+            if !kept.contains(&chunk_name) {
+                api.delete(chunk_name.as_str(), &kube::api::DeleteParams::default())
+                    .await
+                    .map_err(ReleaseStateError::KubeError)?;
+            }
+        }
+
         Ok(())
     }
 }
+
+/// Key under which the serialized release state, or a chunk of it, is stored
+const STATE_KEY: &str = "release_state";
+
+/// Key under which the number of chunks of a release state is stored, if it has been split up
+const CHUNKS_KEY: &str = "release_state_chunks";
+
+/// Name of the ConfigMap holding a chunk of the state of the given release
+fn chunk_name(release_name: &str, index: usize) -> String {
+    format!("{}-state-{}", release_name, index)
+}
+
+/// Split the data into chunks of at most `max_size` bytes, without splitting characters. Chunks
+/// hold at least 4 bytes, so every character fits into one.
+fn split_chunks(mut data: &str, max_size: usize) -> Vec<&str> {
+    let max_size = max_size.max(4);
+    let mut chunks = Vec::new();
+
+    while data.len() > max_size {
+        let mut end = max_size;
+        while !data.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (chunk, rest) = data.split_at(end);
+        chunks.push(chunk);
+        data = rest;
+    }
+
+    chunks.push(data);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::config::KubeConfigOptions;
+    use kube::config::Kubeconfig;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::net::TcpStream;
+
+    /// Answer the requests with the given status codes and bodies in order and return the request
+    /// lines of the requests. Fails if fewer requests arrive within a few seconds.
+    async fn serve(listener: TcpListener, responses: Vec<(u16, serde_json::Value)>) -> Vec<String> {
+        tokio::time::timeout(Duration::from_secs(10), answer(listener, responses))
+            .await
+            .expect("Expected more requests")
+    }
+
+    async fn answer(
+        listener: TcpListener,
+        responses: Vec<(u16, serde_json::Value)>,
+    ) -> Vec<String> {
+        let mut request_lines = Vec::new();
+        let mut connection = None;
+
+        for (status, body) in responses {
+            // Clients may close idle connections and open new ones at any time.
+            let request = loop {
+                if connection.is_none() {
+                    connection = Some(listener.accept().await.unwrap().0);
+                }
+
+                match read_request(connection.as_mut().unwrap()).await {
+                    Some(request) => break request,
+                    None => connection = None,
+                }
+            };
+
+            let body = body.to_string();
+            let response = format!(
+                "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let stream = connection.as_mut().unwrap();
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            request_lines.push(request.lines().next().unwrap_or_default().to_string());
+        }
+
+        request_lines
+    }
+
+    /// Read a request including its body. Returns `None` if the connection has been closed.
+    async fn read_request(stream: &mut TcpStream) -> Option<String> {
+        let mut request: Vec<u8> = Vec::new();
+        let mut buffer = [0; 1024];
+
+        let header_end = loop {
+            if let Some(index) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                break index + 4;
+            }
+
+            let length = stream.read(&mut buffer).await.unwrap();
+            if length == 0 {
+                return None;
+            }
+            request.extend_from_slice(&buffer[..length]);
+        };
+
+        let head = String::from_utf8_lossy(&request[..header_end]).to_lowercase();
+        let content_length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .map_or(0, |length| length.trim().parse().unwrap());
+
+        while request.len() < header_end + content_length {
+            let length = stream.read(&mut buffer).await.unwrap();
+            assert!(
+                length > 0,
+                "Connection closed before the request was complete"
+            );
+            request.extend_from_slice(&buffer[..length]);
+        }
+
+        Some(String::from_utf8_lossy(&request).into_owned())
+    }
+
+    fn not_found() -> (u16, serde_json::Value) {
+        (
+            404,
+            serde_json::json!({
+                "kind": "Status",
+                "apiVersion": "v1",
+                "status": "Failure",
+                "reason": "NotFound",
+                "code": 404,
+                "message": "not found",
+            }),
+        )
+    }
+
+    /// Manager for the namespace "payments" on the given server
+    async fn manager_for(server: String) -> Manager {
+        let kubeconfig: Kubeconfig = serde_yaml::from_str(
+            format!(
+                r#"
+apiVersion: v1
+kind: Config
+current-context: payments
+clusters:
+  - name: local
+    cluster:
+      server: {}
+contexts:
+  - name: payments
+    context:
+      cluster: local
+      user: anonymous
+      namespace: payments
+users:
+  - name: anonymous
+    user: {{}}
+"#,
+                server
+            )
+            .as_str(),
+        )
+        .unwrap();
+        let config =
+            kube::Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default())
+                .await
+                .unwrap();
+
+        Manager::new_with_config(config, NamespaceMode::Default).unwrap()
+    }
+
+    fn objects_from(document: &str) -> objects::Objects {
+        let mut builder = objects::Builder::new();
+        builder.read_objects(document.as_bytes()).unwrap();
+        builder.finish()
+    }
+
+    async fn bind() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        (listener, server)
+    }
+
+    #[tokio::test]
+    async fn default_namespace_is_the_namespace_of_the_kubeconfig_context() {
+        let (listener, server) = bind().await;
+        let manager = manager_for(server).await;
+        assert_eq!(manager.namespace(), "payments");
+        assert_eq!(manager.release_namespace(), "payments");
+
+        let (request_lines, result) = futures::join!(
+            serve(listener, vec![not_found()]),
+            manager.config_maps.get("release")
+        );
+        assert!(result.is_err());
+        assert!(
+            request_lines[0].starts_with("GET /api/v1/namespaces/payments/configmaps/release"),
+            "Unexpected request: {}",
+            request_lines[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_removes_locks_retained_after_failures() {
+        let (listener, server) = bind().await;
+        let manager = manager_for(server).await;
+
+        let mut lock = ConfigMap::default()
+            .with_label(&k8s::ObjectType::Lock)
+            .with_annotation(&k8s::LockFailure("Rollback failed".to_string()));
+        lock.metadata.name = Some(release::lock_name("web"));
+        let lock = serde_json::to_value(&lock).unwrap();
+
+        let (request_lines, result) = futures::join!(
+            serve(
+                listener,
+                vec![
+                    (200, lock.clone()),
+                    (200, lock.clone()),
+                    (201, lock.clone()),
+                    not_found(),
+                    (200, lock),
+                ]
+            ),
+            manager.delete("web".to_string(), false)
+        );
+        assert!(matches!(result, Ok(None)));
+
+        let lock_path = "/api/v1/namespaces/payments/configmaps/able-seaman-lock-web";
+        let expected = vec![
+            format!("GET {}", lock_path),
+            format!("DELETE {}", lock_path),
+            "POST /api/v1/namespaces/payments/configmaps".to_string(),
+            "GET /api/v1/namespaces/payments/configmaps/web".to_string(),
+            format!("DELETE {}", lock_path),
+        ];
+        for (request_line, expected) in request_lines.iter().zip(expected) {
+            assert!(
+                request_line.starts_with(expected.as_str()),
+                "Expected {}, got {}",
+                expected,
+                request_line
+            );
+        }
+    }
+
+    #[test]
+    fn rename_updates_the_instance_label() {
+        let mut state = ReleaseState::new(objects_from(
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: settings, labels: {app.kubernetes.io/instance: web}}}\n---\n{apiVersion: v1, kind: Secret, metadata: {name: password, labels: {app.kubernetes.io/instance: other}}}",
+        ));
+        state.history.push(state.current.clone());
+
+        state.rename_instance("web", "shop");
+
+        for objects in std::iter::once(&state.current).chain(&state.history) {
+            for (identifier, object) in objects.iter() {
+                let expected = match identifier.name() {
+                    "settings" => "shop",
+                    _ => "other",
+                };
+                assert!(
+                    object.has_label(&k8s::Instance(expected.to_string())),
+                    "{} should be an instance of {}",
+                    identifier,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_split_at_character_boundaries() {
+        assert_eq!(split_chunks("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(split_chunks("abcd", 4), vec!["abcd"]);
+        assert_eq!(split_chunks("", 4), vec![""]);
+
+        // "ä" takes two bytes and "€" three, neither may be cut in half.
+        let data = "aääb€€c";
+        let chunks = split_chunks(data, 4);
+        assert_eq!(chunks, vec!["aä", "äb", "€", "€c"]);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 4));
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[tokio::test]
+    async fn chunks_do_not_overwrite_foreign_config_maps() {
+        let (listener, server) = bind().await;
+        let manager = manager_for(server).await;
+        let state = ReleaseState::new(objects::Objects::empty());
+
+        let foreign = serde_json::to_value(&ConfigMap::default()).unwrap();
+
+        let (request_lines, result) = futures::join!(
+            serve(listener, vec![(200, foreign)]),
+            state.apply(&manager.config_maps, "web", 4)
+        );
+        assert!(
+            matches!(&result, Err(ReleaseStateError::NameCollision { name }) if name == &chunk_name("web", 0)),
+            "Unexpected result: {:?}",
+            result
+        );
+        assert_eq!(request_lines.len(), 1);
+        assert!(request_lines[0].starts_with(
+            format!(
+                "GET /api/v1/namespaces/payments/configmaps/{}",
+                chunk_name("web", 0)
+            )
+            .as_str()
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_removed_objects_of_the_kinds_to_prune_are_pruned() {
+        let deployed = objects_from(
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: settings}}\n---\n{apiVersion: v1, kind: Secret, metadata: {name: password}}\n---\n{apiVersion: v1, kind: Service, metadata: {name: frontend}}",
+        );
+        let release = release::Release::from_objects(
+            "web".to_string(),
+            objects_from("{apiVersion: v1, kind: Service, metadata: {name: frontend}}"),
+        );
+        let manager = manager_for("http://127.0.0.1:9".to_string()).await;
+
+        let split = |manager: Manager| {
+            let mut warnings = Vec::new();
+            let (prunable, retained) =
+                manager.split_prunable(deployed.clone(), &release, &mut warnings);
+            (prunable.len(), retained.len(), warnings.len())
+        };
+
+        assert_eq!(split(manager.clone()), (3, 0, 0));
+        assert_eq!(
+            split(
+                manager
+                    .clone()
+                    .with_prune_kinds(Some(vec!["configmap".to_string()]))
+            ),
+            (2, 1, 1)
+        );
+        assert_eq!(split(manager.with_prune_kinds(Some(Vec::new()))), (1, 2, 2));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::k8s::transaction;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use once_cell::sync::Lazy;
+
+#[cfg(feature = "metrics")]
+use prometheus::Encoder;
+
+#[cfg(feature = "metrics")]
+static DEPLOYS: Lazy<prometheus::IntCounterVec> = Lazy::new(|| {
+    prometheus::register_int_counter_vec!(
+        "able_seaman_deploys_total",
+        "Number of deploy operations by result",
+        &["result"]
+    )
+    .expect("Failed to register deploy counter")
+});
+
+#[cfg(feature = "metrics")]
+static OBJECT_LATENCY: Lazy<prometheus::HistogramVec> = Lazy::new(|| {
+    prometheus::register_histogram_vec!(
+        "able_seaman_object_action_seconds",
+        "Latency of actions performed on individual objects",
+        &["action"]
+    )
+    .expect("Failed to register object action histogram")
+});
+
+#[cfg(feature = "metrics")]
+static ROLLBACKS: Lazy<prometheus::IntCounter> = Lazy::new(|| {
+    prometheus::register_int_counter!(
+        "able_seaman_rollbacks_total",
+        "Number of rollbacks that have been triggered"
+    )
+    .expect("Failed to register rollback counter")
+});
+
+#[cfg(feature = "metrics")]
+static LOCK_WAIT: Lazy<prometheus::Histogram> = Lazy::new(|| {
+    prometheus::register_histogram!(
+        "able_seaman_lock_wait_seconds",
+        "Time spent waiting to acquire a lock"
+    )
+    .expect("Failed to register lock wait histogram")
+});
+
+/// Record the outcome of a deploy operation. Recording metrics does nothing unless the `metrics`
+/// feature is enabled.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn record_deploy(result: &str) {
+    #[cfg(feature = "metrics")]
+    DEPLOYS.with_label_values(&[result]).inc();
+}
+
+/// Record how long an action on a single object took.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn observe_object_action(action: &transaction::Action, duration: Duration) {
+    #[cfg(feature = "metrics")]
+    OBJECT_LATENCY
+        .with_label_values(&[action.to_string().as_str()])
+        .observe(duration.as_secs_f64());
+}
+
+/// Record that a rollback has been triggered.
+pub fn record_rollback() {
+    #[cfg(feature = "metrics")]
+    ROLLBACKS.inc();
+}
+
+/// Record how long it took to acquire a lock.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn observe_lock_wait(duration: Duration) {
+    #[cfg(feature = "metrics")]
+    LOCK_WAIT.observe(duration.as_secs_f64());
+}
+
+/// Render all metrics in the Prometheus text format, e.g. to serve them via a `/metrics` endpoint.
+#[cfg(feature = "metrics")]
+pub fn render() -> Result<String, prometheus::Error> {
+    let mut buffer = Vec::new();
+    prometheus::TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    String::from_utf8(buffer).map_err(|error| prometheus::Error::Msg(error.to_string()))
+}
+
+/// Push all metrics to a Prometheus Pushgateway. This blocks the current thread.
+#[cfg(feature = "metrics")]
+pub fn push(url: &str) -> Result<(), prometheus::Error> {
+    prometheus::push_metrics(
+        crate::meta::CRATE_NAME,
+        std::collections::HashMap::new(),
+        url,
+        prometheus::gather(),
+        None,
+    )
+}
@@ -1,20 +1,47 @@
+pub mod config_hash;
+pub mod diff;
+pub mod overlay;
+pub mod overrides;
+pub mod values;
+
 use crate::identifier::Identifier;
 use crate::k8s::api_resource::ToApiResource;
 use crate::k8s::api_resource::TryToApiResource;
+use crate::utils::env;
+use crate::utils::fs::is_kustomization;
+use crate::utils::fs::is_sops_config;
+use crate::utils::fs::is_sops_encrypted;
+use crate::utils::fs::is_sops_file_name;
 use crate::utils::fs::list_files;
+use flate2::read::GzDecoder;
 use kube::core::ApiResource;
 use kube::core::DynamicObject;
+use kube::core::GroupVersionKind;
 use serde::Deserialize;
 use serde::Deserializer;
 use serde::Serialize;
 use serde::Serializer;
+use serde_json::Value;
 use std::borrow::Cow;
 use std::collections::hash_map;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::error;
+use std::fmt;
 use std::fs::File;
 use std::io;
+#[cfg(feature = "sops")]
+use std::io::Write;
+use std::iter::FromIterator;
 use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+#[cfg(feature = "sops")]
+use std::process::Output;
+#[cfg(feature = "sops")]
+use std::process::Stdio;
 
 /// Clone of ApiResource that supports Serialize and Deserialize
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -47,6 +74,16 @@ impl Object {
     pub fn name(&self) -> Option<&String> {
         self.dyn_object.metadata.name.as_ref()
     }
+
+    /// Identifier of the object, if it has a name. Objects are told apart by kind and namespace,
+    /// not just by name.
+    pub fn identifier(&self) -> Option<Identifier> {
+        let name = self.name()?.clone();
+        Some(
+            Identifier::from_api_resource(name, &self.api_resource)
+                .with_namespace(self.dyn_object.metadata.namespace.clone()),
+        )
+    }
 }
 
 impl ToApiResource for Object {
@@ -111,6 +148,51 @@ impl<'de> Deserialize<'de> for Object {
     }
 }
 
+/// Kinds in the order in which they should be applied, so objects exist before others refer to
+/// them. Kinds not listed here are applied after these, but before workloads that run to
+/// completion.
+const KIND_ORDER: &[&str] = &[
+    "Namespace",
+    "CustomResourceDefinition",
+    "PriorityClass",
+    "ResourceQuota",
+    "LimitRange",
+    "PodSecurityPolicy",
+    "PodDisruptionBudget",
+    "ServiceAccount",
+    "Secret",
+    "ConfigMap",
+    "StorageClass",
+    "PersistentVolume",
+    "PersistentVolumeClaim",
+    "ClusterRole",
+    "ClusterRoleBinding",
+    "Role",
+    "RoleBinding",
+    "Service",
+    "DaemonSet",
+    "ReplicaSet",
+    "Deployment",
+    "HorizontalPodAutoscaler",
+    "StatefulSet",
+    "Ingress",
+    "APIService",
+];
+
+/// Kinds which are applied last
+const LAST_KINDS: &[&str] = &["Pod", "Job", "CronJob"];
+
+/// Position of the kind in the apply order
+pub fn kind_priority(kind: &str) -> usize {
+    match KIND_ORDER.iter().position(|candidate| *candidate == kind) {
+        Some(position) => position,
+        None => match LAST_KINDS.iter().position(|candidate| *candidate == kind) {
+            Some(position) => KIND_ORDER.len() + 1 + position,
+            None => KIND_ORDER.len(),
+        },
+    }
+}
+
 /// Deployable collection of objects
 #[derive(Debug, Clone)]
 pub struct Objects {
@@ -125,6 +207,27 @@ impl Objects {
         }
     }
 
+    /// Construct a collection from the given objects. Objects are checked the same way as when
+    /// they are added to a `Builder`.
+    pub fn from_iter_checked<I>(objects: I) -> Result<Self, BuilderError>
+    where
+        I: IntoIterator<Item = DynamicObject>,
+    {
+        let mut builder = Builder::new();
+        builder.extend(objects)?;
+        Ok(builder.finish())
+    }
+
+    /// Number of objects in the collection
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Is the collection empty?
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     /// Is there an object associated with the given identifier?
     pub fn contains(&self, ident: &Identifier) -> bool {
         self.inner.contains_key(ident)
@@ -135,10 +238,316 @@ impl Objects {
         self.inner.iter()
     }
 
+    /// Identifiers of all objects in the collection
+    pub fn identifiers(&self) -> HashSet<Identifier> {
+        self.inner.keys().cloned().collect()
+    }
+
+    /// Copy the objects of the given kind into a new collection.
+    pub fn filter_by_kind(&self, kind: &str) -> Objects {
+        self.filter(|identifier| identifier.kind() == kind)
+    }
+
+    /// Copy the objects in the given namespace into a new collection. `None` selects the objects
+    /// which don't declare a namespace.
+    pub fn filter_by_namespace(&self, namespace: Option<&str>) -> Objects {
+        self.filter(|identifier| identifier.namespace() == namespace)
+    }
+
+    fn filter<F>(&self, predicate: F) -> Objects
+    where
+        F: Fn(&Identifier) -> bool,
+    {
+        self.inner
+            .iter()
+            .filter(|(identifier, _)| predicate(identifier))
+            .map(|(identifier, object)| (identifier.clone(), object.clone()))
+            .collect()
+    }
+
+    /// Combine both collections. Objects of the other collection take precedence.
+    pub fn union(mut self, other: Objects) -> Self {
+        self.inner.extend(other.inner);
+        self
+    }
+
+    /// Provide a borrowing iterator which yields the objects in the order in which they should be
+    /// applied, see `kind_priority`. Objects of the same kind are ordered by namespace and name.
+    pub fn iter_ordered(&self) -> std::vec::IntoIter<(&Identifier, &Object)> {
+        let mut objects: Vec<(&Identifier, &Object)> = self.inner.iter().collect();
+        objects.sort_by(|(lhs, _), (rhs, _)| {
+            (
+                kind_priority(lhs.kind()),
+                lhs.kind(),
+                lhs.namespace(),
+                lhs.name(),
+            )
+                .cmp(&(
+                    kind_priority(rhs.kind()),
+                    rhs.kind(),
+                    rhs.namespace(),
+                    rhs.name(),
+                ))
+        });
+        objects.into_iter()
+    }
+
+    /// Identifiers of the objects in an order in which every object follows the objects it
+    /// depends on, see `dependencies`. Objects which don't depend on each other are ordered like
+    /// `iter_ordered`. Fails if objects depend on each other in a cycle.
+    pub fn topo_order(&self) -> Result<Vec<Identifier>, CycleError> {
+        let ordered: Vec<&Identifier> = self
+            .iter_ordered()
+            .map(|(identifier, _)| identifier)
+            .collect();
+        let positions: HashMap<&Identifier, usize> = ordered
+            .iter()
+            .enumerate()
+            .map(|(position, identifier)| (*identifier, position))
+            .collect();
+
+        // Number of dependencies that have not been ordered yet, and the reverse edges
+        let mut pending = vec![0usize; ordered.len()];
+        let mut dependents = vec![Vec::new(); ordered.len()];
+
+        for (position, identifier) in ordered.iter().enumerate() {
+            for dependency in dependencies(identifier, &self.inner[*identifier]) {
+                match positions.get(&dependency) {
+                    Some(&dependency_position) if dependency_position != position => {
+                        pending[position] += 1;
+                        dependents[dependency_position].push(position);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Positions in `ordered` break ties, which keeps the result deterministic.
+        let mut ready: BTreeSet<usize> = (0..ordered.len())
+            .filter(|position| pending[*position] == 0)
+            .collect();
+        let mut result = Vec::with_capacity(ordered.len());
+
+        while let Some(position) = ready.iter().next().copied() {
+            ready.remove(&position);
+            result.push(ordered[position].clone());
+
+            for dependent in &dependents[position] {
+                pending[*dependent] -= 1;
+                if pending[*dependent] == 0 {
+                    ready.insert(*dependent);
+                }
+            }
+        }
+
+        if result.len() < ordered.len() {
+            return Err(CycleError {
+                identifiers: (0..ordered.len())
+                    .filter(|position| pending[*position] > 0)
+                    .map(|position| ordered[position].clone())
+                    .collect(),
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Construct a collection from the given objects, deriving their identifiers. Fails if an
+    /// object has no name or two objects share the same identifier.
+    pub fn try_from_objects<I>(objects: I) -> Result<Self, BuilderError>
+    where
+        I: IntoIterator<Item = Object>,
+    {
+        let mut collection = Objects::empty();
+        collection.try_extend(objects)?;
+        Ok(collection)
+    }
+
+    /// Add the given objects, deriving their identifiers. Fails if an object has no name or its
+    /// identifier is taken already. Objects preceding the failing one remain added.
+    pub fn try_extend<I>(&mut self, objects: I) -> Result<(), BuilderError>
+    where
+        I: IntoIterator<Item = Object>,
+    {
+        for object in objects {
+            let identifier =
+                object
+                    .identifier()
+                    .ok_or_else(|| BuilderError::ObjectWithoutName {
+                        object: Box::new(object.clone()),
+                    })?;
+
+            if self.inner.contains_key(&identifier) {
+                return Err(BuilderError::DuplicateObject { identifier });
+            }
+
+            self.inner.insert(identifier, object);
+        }
+
+        Ok(())
+    }
+
+    /// Remove the object associated with the given identifier from the collection.
+    pub fn remove(&mut self, key: &Identifier) -> Option<Object> {
+        self.inner.remove(key)
+    }
+
     /// Find an object associated with the given identifier.
     pub fn get(&self, key: &Identifier) -> Option<&Object> {
         self.inner.get(key)
     }
+
+    /// Find references to ConfigMaps, Secrets and ServiceAccounts (e.g. in volumes, `envFrom` or
+    /// `valueFrom`) which point at objects that are not part of the collection. References marked
+    /// as optional are ignored.
+    pub fn validate_references(&self) -> Vec<Reference> {
+        let mut dangling = Vec::new();
+
+        for (identifier, object) in &self.inner {
+            let mut references = BTreeSet::new();
+            collect_references(&object.dyn_object.data, &mut references);
+
+            for (kind, name) in references {
+                let gvk = GroupVersionKind::gvk("", "v1", kind);
+                let target = Identifier::from_api_resource(name, &ApiResource::from_gvk(&gvk))
+                    .with_namespace(identifier.namespace().map(str::to_string));
+
+                if !self.contains(&target) {
+                    dangling.push(Reference {
+                        referrer: identifier.clone(),
+                        target,
+                    });
+                }
+            }
+        }
+
+        dangling
+    }
+}
+
+/// Reference from one object to another
+#[derive(Clone, Debug)]
+pub struct Reference {
+    pub referrer: Identifier,
+    pub target: Identifier,
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "{} references {} which does not exist",
+            self.referrer, self.target
+        )
+    }
+}
+
+/// Objects which depend on each other in a cycle, so none of them can be applied first
+#[derive(Clone, Debug)]
+pub struct CycleError {
+    /// Objects on the cycle and those depending on them
+    pub identifiers: Vec<Identifier>,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(formatter, "Objects depend on each other in a cycle: ")?;
+
+        for (index, identifier) in self.identifiers.iter().enumerate() {
+            if index > 0 {
+                write!(formatter, ", ")?;
+            }
+            write!(formatter, "{}", identifier)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for CycleError {}
+
+/// Objects which have to exist before the given object is applied: the ConfigMaps, Secrets and
+/// ServiceAccounts it references, see `collect_references`, and its owners.
+fn dependencies(identifier: &Identifier, object: &Object) -> HashSet<Identifier> {
+    let namespace = identifier.namespace().map(str::to_string);
+    let mut references = BTreeSet::new();
+    collect_references(&object.dyn_object.data, &mut references);
+
+    let referenced = references.into_iter().map(|(kind, name)| {
+        let gvk = GroupVersionKind::gvk("", "v1", kind);
+        Identifier::from_api_resource(name, &ApiResource::from_gvk(&gvk))
+            .with_namespace(namespace.clone())
+    });
+
+    let owners = object
+        .dyn_object
+        .metadata
+        .owner_references
+        .iter()
+        .map(|owner| {
+            let (group, version) = match owner.api_version.split_once('/') {
+                Some((group, version)) => (group, version),
+                None => ("", owner.api_version.as_str()),
+            };
+            let gvk = GroupVersionKind::gvk(group, version, owner.kind.as_str());
+            Identifier::from_api_resource(owner.name.clone(), &ApiResource::from_gvk(&gvk))
+                .with_namespace(namespace.clone())
+        });
+
+    referenced.chain(owners).collect()
+}
+
+fn collect_references(value: &Value, references: &mut BTreeSet<(&'static str, String)>) {
+    let named = |reference: &Value, key: &str| -> Option<String> {
+        if reference.get("optional") == Some(&Value::Bool(true)) {
+            return None;
+        }
+
+        reference
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                let reference = match key.as_str() {
+                    "configMapRef" | "configMapKeyRef" | "configMap" => {
+                        named(field, "name").map(|name| ("ConfigMap", name))
+                    }
+
+                    "secretRef" | "secretKeyRef" => {
+                        named(field, "name").map(|name| ("Secret", name))
+                    }
+
+                    // Secret volumes use "secretName", projected Secret volumes use "name".
+                    "secret" => named(field, "secretName")
+                        .or_else(|| named(field, "name"))
+                        .map(|name| ("Secret", name)),
+
+                    // The default ServiceAccount is always present.
+                    "serviceAccountName" => field
+                        .as_str()
+                        .filter(|name| *name != "default")
+                        .map(|name| ("ServiceAccount", name.to_string())),
+
+                    _ => None,
+                };
+
+                references.extend(reference);
+                collect_references(field, references);
+            }
+        }
+
+        Value::Array(items) => {
+            for item in items {
+                collect_references(item, references);
+            }
+        }
+
+        _ => {}
+    }
 }
 
 impl Default for Objects {
@@ -167,6 +576,20 @@ impl<'a> IntoIterator for &'a Objects {
     }
 }
 
+impl FromIterator<(Identifier, Object)> for Objects {
+    fn from_iter<I: IntoIterator<Item = (Identifier, Object)>>(iter: I) -> Self {
+        Objects {
+            inner: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<(Identifier, Object)> for Objects {
+    fn extend<I: IntoIterator<Item = (Identifier, Object)>>(&mut self, iter: I) {
+        self.inner.extend(iter)
+    }
+}
+
 impl From<HashMap<Identifier, Object>> for Objects {
     fn from(inner: HashMap<Identifier, Object>) -> Self {
         Objects { inner }
@@ -191,14 +614,17 @@ impl Serialize for Objects {
     where
         S: Serializer,
     {
-        self.inner
+        // Entries are sorted, so the same objects always serialize the same way.
+        let mut entries = self
+            .inner
             .iter()
             .map(|(identifier, object)| SerDeObjectsEntry {
                 identifier: identifier.clone(),
                 object: object.clone(),
             })
-            .collect::<Vec<SerDeObjectsEntry>>()
-            .serialize(serializer)
+            .collect::<Vec<SerDeObjectsEntry>>();
+        entries.sort_by(|lhs, rhs| lhs.identifier.cmp(&rhs.identifier));
+        entries.serialize(serializer)
     }
 }
 
@@ -216,6 +642,16 @@ impl<'de> Deserialize<'de> for Objects {
     }
 }
 
+/// Is the object a list of other objects, as opposed to an object which merely has a kind ending
+/// in `List`?
+fn is_list(object: &DynamicObject) -> bool {
+    let kind = object
+        .types
+        .as_ref()
+        .map_or("", |types| types.kind.as_str());
+    kind.ends_with("List") && object.metadata.name.is_none() && object.data["items"].is_array()
+}
+
 /// An error that may occur while building a collection of deployable objects
 #[derive(Debug)]
 pub enum BuilderError {
@@ -236,6 +672,84 @@ pub enum BuilderError {
 
     /// Object made of faulty YAML
     DeserializeError { error: serde_yaml::Error },
+
+    /// Archive could not be read
+    ArchiveError { path: Box<Path>, error: io::Error },
+
+    /// Input could not be read
+    ReadError { error: io::Error },
+
+    /// Environment variables could not be expanded
+    ExpandError { error: env::Error },
+
+    /// Kustomization could not be built
+    KustomizeError { path: Box<Path>, reason: String },
+
+    /// SOPS-encrypted file could not be decrypted
+    DecryptError { path: Box<Path>, reason: String },
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            BuilderError::DuplicateObject { identifier } => {
+                write!(formatter, "Duplicate object {}", identifier)
+            }
+            BuilderError::ObjectWithoutName { object } => write!(
+                formatter,
+                "Object of kind {} has no name",
+                object.api_resource.kind
+            ),
+            BuilderError::BadDynamicObject { error } => write!(formatter, "Bad object: {}", error),
+            BuilderError::ListFilesError { path, error } => {
+                write!(formatter, "Failed to list {}: {}", path.display(), error)
+            }
+            BuilderError::OpenFileError { path, error } => {
+                write!(formatter, "Failed to open {}: {}", path.display(), error)
+            }
+            BuilderError::DeserializeError { error } => {
+                write!(formatter, "Malformed object: {}", error)
+            }
+            BuilderError::ArchiveError { path, error } => write!(
+                formatter,
+                "Failed to read archive {}: {}",
+                path.display(),
+                error
+            ),
+            BuilderError::ReadError { error } => {
+                write!(formatter, "Failed to read input: {}", error)
+            }
+            BuilderError::ExpandError { error } => write!(formatter, "{}", error),
+            BuilderError::KustomizeError { path, reason } => write!(
+                formatter,
+                "Failed to build kustomization {}: {}",
+                path.display(),
+                reason
+            ),
+            BuilderError::DecryptError { path, reason } => {
+                write!(
+                    formatter,
+                    "Failed to decrypt {}: {}",
+                    path.display(),
+                    reason
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for BuilderError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BuilderError::ListFilesError { error, .. } => Some(error),
+            BuilderError::OpenFileError { error, .. } => Some(error),
+            BuilderError::DeserializeError { error } => Some(error),
+            BuilderError::ArchiveError { error, .. } => Some(error),
+            BuilderError::ReadError { error } => Some(error),
+            BuilderError::ExpandError { error } => Some(error),
+            _ => None,
+        }
+    }
 }
 
 impl From<serde_yaml::Error> for BuilderError {
@@ -248,6 +762,8 @@ impl From<serde_yaml::Error> for BuilderError {
 #[derive(Debug)]
 pub struct Builder {
     objects: HashMap<Identifier, Object>,
+    expand_env: bool,
+    skipped: Vec<PathBuf>,
 }
 
 impl Builder {
@@ -255,22 +771,66 @@ impl Builder {
     pub fn new() -> Self {
         Builder {
             objects: HashMap::new(),
+            expand_env: false,
+            skipped: Vec::new(),
         }
     }
 
-    /// Add a DynamicObject.
-    pub fn add_dynamic_object(&mut self, dyn_object: DynamicObject) -> Result<(), BuilderError> {
+    /// Expand environment variables (`$VAR`, `${VAR}` or `${VAR:-default}`) in the input before
+    /// parsing it. Undefined variables without a default are an error.
+    pub fn with_env_expansion(mut self, expand_env: bool) -> Self {
+        self.expand_env = expand_env;
+        self
+    }
+
+    /// Number of objects added so far
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Have no objects been added so far?
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Members of archives which have been skipped because they are not manifests, each given as
+    /// the path of the archive joined with the path of the member
+    pub fn skipped(&self) -> &[PathBuf] {
+        self.skipped.as_slice()
+    }
+
+    /// Add a DynamicObject. Lists like `kind: List` or `kind: ConfigMapList` are expanded into
+    /// their items, which are added individually.
+    pub fn add_dynamic_object(
+        &mut self,
+        mut dyn_object: DynamicObject,
+    ) -> Result<(), BuilderError> {
+        if is_list(&dyn_object) {
+            let items = match dyn_object.data["items"].take() {
+                Value::Array(items) => items,
+                _ => Vec::new(),
+            };
+
+            for item in items {
+                let item = serde_json::from_value(item).map_err(|error| {
+                    BuilderError::BadDynamicObject {
+                        error: format!("Bad list item: {}", error),
+                    }
+                })?;
+                self.add_dynamic_object(item)?;
+            }
+
+            return Ok(());
+        }
+
         let object = Object::try_from(dyn_object)
             .map_err(|error| BuilderError::BadDynamicObject { error })?;
 
-        let name = object
-            .name()
+        let identifier = object
+            .identifier()
             .ok_or_else(|| BuilderError::ObjectWithoutName {
                 object: Box::new(object.clone()),
-            })?
-            .clone();
-
-        let identifier = Identifier::from_api_resource(name, &object.api_resource);
+            })?;
 
         if self.objects.insert(identifier.clone(), object).is_some() {
             return Err(BuilderError::DuplicateObject { identifier });
@@ -279,12 +839,64 @@ impl Builder {
         Ok(())
     }
 
+    /// Add an object given as JSON value, e.g. one that has been generated programmatically.
+    pub fn add_value(&mut self, value: Value) -> Result<(), BuilderError> {
+        let dyn_object =
+            serde_json::from_value(value).map_err(|error| BuilderError::BadDynamicObject {
+                error: error.to_string(),
+            })?;
+        self.add_dynamic_object(dyn_object)
+    }
+
+    /// Add many objects given as JSON values.
+    pub fn add_values<I>(&mut self, values: I) -> Result<(), BuilderError>
+    where
+        I: IntoIterator<Item = Value>,
+    {
+        for value in values {
+            self.add_value(value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add many DynamicObjects at once. Space for the objects is reserved up front.
+    pub fn extend<I>(&mut self, objects: I) -> Result<(), BuilderError>
+    where
+        I: IntoIterator<Item = DynamicObject>,
+    {
+        let objects = objects.into_iter();
+        self.objects.reserve(objects.size_hint().0);
+
+        for dyn_object in objects {
+            self.add_dynamic_object(dyn_object)?;
+        }
+
+        Ok(())
+    }
+
     /// Read objects from a YAML document.
-    pub fn read_objects<SomeRead>(&mut self, input: SomeRead) -> Result<(), BuilderError>
+    pub fn read_objects<SomeRead>(&mut self, mut input: SomeRead) -> Result<(), BuilderError>
     where
         SomeRead: io::Read,
     {
-        for document in serde_yaml::Deserializer::from_reader(input) {
+        if self.expand_env {
+            let mut raw = String::new();
+            input
+                .read_to_string(&mut raw)
+                .map_err(|error| BuilderError::ReadError { error })?;
+
+            let expanded = env::expand(raw.as_str(), |name| std::env::var(name).ok())
+                .map_err(|error| BuilderError::ExpandError { error })?;
+
+            return self.read_documents(serde_yaml::Deserializer::from_str(expanded.as_str()));
+        }
+
+        self.read_documents(serde_yaml::Deserializer::from_reader(input))
+    }
+
+    fn read_documents(&mut self, documents: serde_yaml::Deserializer) -> Result<(), BuilderError> {
+        for document in documents {
             let object = DynamicObject::deserialize(document)?;
             self.add_dynamic_object(object)?;
         }
@@ -294,19 +906,213 @@ impl Builder {
 
     /// Read objects from a file or files. If the given path is a directory, it will be traversed
     /// and all files, including in any subdirectories will be read.
+    /// Directories containing a kustomization are built instead, SOPS-encrypted files are
+    /// decrypted first.
     pub fn read_objects_from_path(&mut self, input: &Path) -> Result<(), BuilderError> {
         let files = list_files(input).map_err(|error| BuilderError::ListFilesError {
             path: input.to_owned().into_boxed_path(),
             error,
         })?;
 
-        for file in files {
-            let file = File::open(file.as_path()).map_err(|error| BuilderError::OpenFileError {
-                path: file.into_boxed_path(),
+        for path in files {
+            if is_kustomization(path.as_path()) {
+                self.read_objects_from_kustomization(path.as_path())?;
+                continue;
+            }
+
+            if is_sops_encrypted(path.as_path()) {
+                self.read_objects_from_sops(path.as_path())?;
+                continue;
+            }
+
+            if is_sops_config(path.as_path()) {
+                self.skipped.push(path);
+                continue;
+            }
+
+            let file = File::open(path.as_path()).map_err(|error| BuilderError::OpenFileError {
+                path: path.clone().into_boxed_path(),
                 error,
             })?;
 
-            self.read_objects(file)?;
+            match ArchiveFormat::detect(path.as_path()) {
+                Some(ArchiveFormat::Tar) => self.read_objects_from_archive(path.as_path(), file)?,
+
+                Some(ArchiveFormat::TarGz) => {
+                    self.read_objects_from_archive(path.as_path(), GzDecoder::new(file))?
+                }
+
+                None => self.read_objects(file)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the objects rendered from a kustomization using `kustomize build`, or
+    /// `kubectl kustomize` if kustomize is not installed.
+    pub fn read_objects_from_kustomization(&mut self, path: &Path) -> Result<(), BuilderError> {
+        let kustomize_error = |reason: String| BuilderError::KustomizeError {
+            path: path.to_owned().into_boxed_path(),
+            reason,
+        };
+
+        let output = match Command::new("kustomize").arg("build").arg(path).output() {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                Command::new("kubectl").arg("kustomize").arg(path).output()
+            }
+            result => result,
+        }
+        .map_err(|error| kustomize_error(error.to_string()))?;
+
+        if !output.status.success() {
+            return Err(kustomize_error(
+                String::from_utf8_lossy(output.stderr.as_slice())
+                    .trim()
+                    .to_string(),
+            ));
+        }
+
+        self.read_objects(output.stdout.as_slice())
+    }
+
+    /// Read the objects from a SOPS-encrypted file, decrypted using `sops --decrypt`.
+    #[cfg(feature = "sops")]
+    pub fn read_objects_from_sops(&mut self, path: &Path) -> Result<(), BuilderError> {
+        let output = Command::new("sops")
+            .arg("--decrypt")
+            .arg(path)
+            .output()
+            .map_err(|error| decrypt_error(path, error.to_string()))?;
+
+        self.read_decrypted_objects(path, output)
+    }
+
+    /// Read the objects from a SOPS-encrypted archive member, whose contents are decrypted by
+    /// passing them to `sops --decrypt` on its standard input.
+    #[cfg(feature = "sops")]
+    fn read_objects_from_sops_member(
+        &mut self,
+        path: &Path,
+        mut member: impl io::Read,
+    ) -> Result<(), BuilderError> {
+        let mut contents = Vec::new();
+        member
+            .read_to_end(&mut contents)
+            .map_err(|error| decrypt_error(path, error.to_string()))?;
+
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => "json",
+            _ => "yaml",
+        };
+
+        let mut child = Command::new("sops")
+            .args(&["--decrypt", "--input-type", format, "--output-type", format])
+            .arg("/dev/stdin")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| decrypt_error(path, error.to_string()))?;
+
+        // SOPS reads all of its input before writing anything, so the input can be written in one
+        // go. Dropping the handle closes the input.
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(contents.as_slice())
+                .map_err(|error| decrypt_error(path, error.to_string()))?;
+        }
+
+        let output = child
+            .wait_with_output()
+            .map_err(|error| decrypt_error(path, error.to_string()))?;
+
+        self.read_decrypted_objects(path, output)
+    }
+
+    #[cfg(feature = "sops")]
+    fn read_decrypted_objects(&mut self, path: &Path, output: Output) -> Result<(), BuilderError> {
+        if !output.status.success() {
+            return Err(decrypt_error(
+                path,
+                String::from_utf8_lossy(output.stderr.as_slice())
+                    .trim()
+                    .to_string(),
+            ));
+        }
+
+        self.read_objects(output.stdout.as_slice())
+    }
+
+    /// Refuse SOPS-encrypted files, as their encrypted contents must not be deployed.
+    #[cfg(not(feature = "sops"))]
+    pub fn read_objects_from_sops(&mut self, path: &Path) -> Result<(), BuilderError> {
+        Err(decrypt_error(
+            path,
+            "Support for SOPS requires the sops feature".to_string(),
+        ))
+    }
+
+    /// Refuse SOPS-encrypted archive members, as their encrypted contents must not be deployed.
+    #[cfg(not(feature = "sops"))]
+    fn read_objects_from_sops_member(
+        &mut self,
+        path: &Path,
+        _member: impl io::Read,
+    ) -> Result<(), BuilderError> {
+        self.read_objects_from_sops(path)
+    }
+
+    /// Read objects from the YAML and JSON files contained in a tar archive, SOPS-encrypted ones
+    /// are decrypted first. Other members of the archive are skipped, see `skipped`.
+    pub fn read_objects_from_archive<SomeRead>(
+        &mut self,
+        path: &Path,
+        input: SomeRead,
+    ) -> Result<(), BuilderError>
+    where
+        SomeRead: io::Read,
+    {
+        let archive_error = |error: io::Error| BuilderError::ArchiveError {
+            path: path.to_owned().into_boxed_path(),
+            error,
+        };
+
+        let mut archive = tar::Archive::new(input);
+
+        for entry in archive.entries().map_err(archive_error)? {
+            let mut entry = entry.map_err(archive_error)?;
+
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let member = entry.path().map_err(archive_error)?.into_owned();
+            let is_manifest = member
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map_or(false, |extension| {
+                    matches!(extension, "yaml" | "yml" | "json")
+                });
+
+            let member = path.join(member);
+
+            if !is_manifest || is_sops_config(member.as_path()) {
+                self.skipped.push(member);
+                continue;
+            }
+
+            let encrypted = member
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, is_sops_file_name);
+
+            if encrypted {
+                self.read_objects_from_sops_member(member.as_path(), &mut entry)?;
+                continue;
+            }
+
+            self.read_objects(&mut entry)?;
         }
 
         Ok(())
@@ -318,8 +1124,232 @@ impl Builder {
     }
 }
 
+fn decrypt_error(path: &Path, reason: String) -> BuilderError {
+    BuilderError::DecryptError {
+        path: path.to_owned().into_boxed_path(),
+        reason,
+    }
+}
+
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+
+        if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
 impl Default for Builder {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENTS: [&str; 3] = [
+        "{apiVersion: v1, kind: ConfigMap, metadata: {name: a}, data: {x: '1'}}",
+        "{apiVersion: v1, kind: Secret, metadata: {name: b}}",
+        "{apiVersion: apps/v1, kind: Deployment, metadata: {name: c, namespace: web}}",
+    ];
+
+    fn objects_from(order: &[usize]) -> Objects {
+        let mut builder = Builder::new();
+        for index in order {
+            builder
+                .read_objects(DOCUMENTS[*index].as_bytes())
+                .expect("Fixture must be valid");
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn serialization_is_independent_of_insertion_order() {
+        let forward = serde_json::to_string(&objects_from(&[0, 1, 2])).unwrap();
+        let backward = serde_json::to_string(&objects_from(&[2, 1, 0])).unwrap();
+        let shuffled = serde_json::to_string(&objects_from(&[1, 2, 0])).unwrap();
+
+        assert_eq!(forward, backward);
+        assert_eq!(forward, shuffled);
+    }
+
+    #[test]
+    fn owners_are_ordered_before_owned_objects() {
+        let mut builder = Builder::new();
+        builder
+            .read_objects(
+                "{apiVersion: v1, kind: ConfigMap, metadata: {name: owned, ownerReferences: [{apiVersion: apps/v1, kind: Deployment, name: owner, uid: x}]}}\n\
+                 ---\n\
+                 {apiVersion: apps/v1, kind: Deployment, metadata: {name: owner}}"
+                    .as_bytes(),
+            )
+            .expect("Fixture must be valid");
+
+        let names: Vec<String> = builder
+            .finish()
+            .topo_order()
+            .expect("Objects must not form a cycle")
+            .iter()
+            .map(|identifier| identifier.name().to_string())
+            .collect();
+
+        assert_eq!(names, vec!["owner", "owned"]);
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut builder = Builder::new();
+        builder
+            .read_objects(
+                "{apiVersion: v1, kind: ConfigMap, metadata: {name: a, ownerReferences: [{apiVersion: v1, kind: ConfigMap, name: b, uid: x}]}}\n\
+                 ---\n\
+                 {apiVersion: v1, kind: ConfigMap, metadata: {name: b, ownerReferences: [{apiVersion: v1, kind: ConfigMap, name: a, uid: y}]}}"
+                    .as_bytes(),
+            )
+            .expect("Fixture must be valid");
+
+        let error = builder
+            .finish()
+            .topo_order()
+            .expect_err("Objects must form a cycle");
+
+        assert_eq!(error.identifiers.len(), 2);
+    }
+
+    #[test]
+    fn filters_select_by_kind_and_namespace() {
+        let objects = objects_from(&[0, 1, 2]);
+
+        let config_maps = objects.filter_by_kind("ConfigMap");
+        assert_eq!(config_maps.len(), 1);
+        assert!(config_maps
+            .iter()
+            .all(|(identifier, _)| identifier.name() == "a"));
+
+        let web = objects.filter_by_namespace(Some("web"));
+        assert_eq!(web.len(), 1);
+        assert!(web.iter().all(|(identifier, _)| identifier.name() == "c"));
+
+        assert_eq!(objects.filter_by_namespace(None).len(), 2);
+        assert!(objects.filter_by_kind("Service").is_empty());
+
+        let identifiers = objects.identifiers();
+        let names: HashSet<&str> = identifiers
+            .iter()
+            .map(|identifier| identifier.name())
+            .collect();
+        assert_eq!(names, ["a", "b", "c"].iter().copied().collect());
+    }
+
+    #[test]
+    fn overlay_merges_objects_present_in_both() {
+        let read = |document: &str| {
+            let mut builder = Builder::new();
+            builder
+                .read_objects(document.as_bytes())
+                .expect("Fixture must be valid");
+            builder.finish()
+        };
+
+        let base = read(
+            "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web}, spec: {replicas: 1, template: {spec: {containers: [{name: app, image: 'app:1'}, {name: proxy, image: 'proxy:1'}]}}}}\n\
+             ---\n\
+             {apiVersion: v1, kind: Secret, metadata: {name: base-only}}",
+        );
+        let overlay = read(
+            "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web}, spec: {replicas: 3, template: {spec: {containers: [{name: app, image: 'app:2'}]}}}}\n\
+             ---\n\
+             {apiVersion: v1, kind: ConfigMap, metadata: {name: overlay-only}}",
+        );
+
+        let merged = overlay::apply(base, overlay, &crate::release::verify::ListKeys::default())
+            .expect("Objects must merge");
+        assert_eq!(merged.len(), 3);
+
+        let (_, deployment) = merged
+            .iter()
+            .find(|(identifier, _)| identifier.kind() == "Deployment")
+            .expect("Deployment must be merged");
+        let spec = &deployment.dyn_object.data["spec"];
+        assert_eq!(spec["replicas"], 3);
+
+        let containers = &spec["template"]["spec"]["containers"];
+        assert_eq!(containers[0]["image"], "app:2");
+        assert_eq!(containers[1]["image"], "proxy:1");
+    }
+
+    /// Tar archive with the given members
+    fn archive_of(members: &[(&str, &str)]) -> Vec<u8> {
+        let mut archive = tar::Builder::new(Vec::new());
+
+        for (name, contents) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(&mut header, name, contents.as_bytes())
+                .expect("Member must be added");
+        }
+
+        archive.into_inner().expect("Archive must be finished")
+    }
+
+    #[test]
+    fn archive_members_which_are_not_manifests_are_skipped() {
+        let archive = archive_of(&[("objects.yaml", DOCUMENTS[0]), ("README.md", "# Objects")]);
+
+        let mut builder = Builder::new();
+        builder
+            .read_objects_from_archive(Path::new("release.tar"), archive.as_slice())
+            .expect("Archive must be read");
+
+        assert_eq!(builder.len(), 1);
+        assert_eq!(builder.skipped(), &[PathBuf::from("release.tar/README.md")]);
+    }
+
+    #[test]
+    fn archive_members_with_sops_config_are_skipped() {
+        let archive = archive_of(&[
+            ("objects.yaml", DOCUMENTS[0]),
+            (".sops.yaml", "creation_rules: []"),
+        ]);
+
+        let mut builder = Builder::new();
+        builder
+            .read_objects_from_archive(Path::new("release.tar"), archive.as_slice())
+            .expect("Archive must be read");
+
+        assert_eq!(builder.len(), 1);
+        assert_eq!(
+            builder.skipped(),
+            &[PathBuf::from("release.tar/.sops.yaml")]
+        );
+    }
+
+    #[cfg(not(feature = "sops"))]
+    #[test]
+    fn encrypted_archive_members_are_refused() {
+        let archive = archive_of(&[("secrets.enc.yaml", DOCUMENTS[1])]);
+
+        let mut builder = Builder::new();
+        let result =
+            builder.read_objects_from_archive(Path::new("release.tar"), archive.as_slice());
+
+        assert!(matches!(result, Err(BuilderError::DecryptError { .. })));
+        assert_eq!(builder.len(), 0);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::objects::BuilderError;
+use crate::objects::Object;
+use crate::objects::Objects;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Number of hexadecimal digits of the content hash appended to names
+const SUFFIX_LENGTH: usize = 10;
+
+/// Fields which hold the contents of ConfigMaps and Secrets
+const CONTENT_FIELDS: [&str; 4] = ["data", "binaryData", "stringData", "type"];
+
+/// Suffix the names of all ConfigMaps and Secrets with a hash of their contents and mark them as
+/// immutable. References to them from other objects in the same namespace are rewritten, so
+/// changing their contents rolls out the referring workloads and leaves the old versions to be
+/// pruned.
+pub fn apply(objects: Objects) -> Result<Objects, BuilderError> {
+    let mut renames = HashMap::new();
+
+    for (identifier, object) in objects.iter() {
+        if let Some(kind) = config_kind(object) {
+            let new_name = format!("{}-{}", identifier.name(), content_hash(object));
+            let key = (
+                kind,
+                identifier.namespace().map(str::to_string),
+                identifier.name().to_string(),
+            );
+            renames.insert(key, new_name);
+        }
+    }
+
+    let mut results = Vec::with_capacity(objects.len());
+
+    for (identifier, object) in objects {
+        let namespace = identifier.namespace().map(str::to_string);
+        let rename = |kind: &'static str, name: &str| {
+            renames
+                .get(&(kind, namespace.clone(), name.to_string()))
+                .cloned()
+        };
+
+        let kind = config_kind(&object);
+        let mut dyn_object = object.dyn_object;
+
+        match kind {
+            Some(kind) => {
+                if let Some(new_name) = rename(kind, identifier.name()) {
+                    dyn_object.metadata.name = Some(new_name);
+                }
+                dyn_object.data["immutable"] = Value::Bool(true);
+            }
+
+            None => rewrite_references(&mut dyn_object.data, &rename),
+        }
+
+        results.push(dyn_object);
+    }
+
+    Objects::from_iter_checked(results)
+}
+
+/// Kind of the object if it is a ConfigMap or Secret
+fn config_kind(object: &Object) -> Option<&'static str> {
+    if !object.api_resource.group.is_empty() {
+        return None;
+    }
+
+    match object.api_resource.kind.as_str() {
+        "ConfigMap" => Some("ConfigMap"),
+        "Secret" => Some("Secret"),
+        _ => None,
+    }
+}
+
+/// Hash of the contents, which is stable across runs and platforms
+fn content_hash(object: &Object) -> String {
+    let contents: serde_json::Map<String, Value> = CONTENT_FIELDS
+        .iter()
+        .filter_map(|field| {
+            object
+                .dyn_object
+                .data
+                .get(*field)
+                .map(|value| (field.to_string(), value.clone()))
+        })
+        .collect();
+
+    // Keys of JSON objects are serialized in sorted order.
+    let serialized = Value::Object(contents).to_string();
+
+    // 64-bit FNV-1a
+    let hash = serialized
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+
+    format!("{:016x}", hash)[..SUFFIX_LENGTH].to_string()
+}
+
+/// Replace the names in references to ConfigMaps and Secrets, see `collect_references` for the
+/// fields which are considered.
+fn rewrite_references<F>(value: &mut Value, rename: &F)
+where
+    F: Fn(&'static str, &str) -> Option<String>,
+{
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let reference = match key.as_str() {
+                    "configMapRef" | "configMapKeyRef" | "configMap" => Some(("ConfigMap", "name")),
+                    "secretRef" | "secretKeyRef" => Some(("Secret", "name")),
+
+                    // Secret volumes use "secretName", projected Secret volumes use "name".
+                    "secret" if field.get("secretName").is_some() => Some(("Secret", "secretName")),
+                    "secret" => Some(("Secret", "name")),
+
+                    _ => None,
+                };
+
+                if let Some((kind, name_key)) = reference {
+                    let new_name = field
+                        .get(name_key)
+                        .and_then(Value::as_str)
+                        .and_then(|name| rename(kind, name));
+
+                    if let Some(new_name) = new_name {
+                        field[name_key] = Value::String(new_name);
+                    }
+                }
+
+                rewrite_references(field, rename);
+            }
+        }
+
+        Value::Array(items) => {
+            for item in items {
+                rewrite_references(item, rename);
+            }
+        }
+
+        _ => {}
+    }
+}
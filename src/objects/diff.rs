@@ -0,0 +1,85 @@
+use serde_json::Value;
+use std::fmt;
+
+/// Change of a single field between two versions of an object
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// Dotted path to the field, e.g. `spec.template.spec.containers.0.image`
+    pub path: String,
+
+    /// Value before the change, if the field existed
+    pub old: Option<Value>,
+
+    /// Value after the change, if the field still exists
+    pub new: Option<Value>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(formatter, "{}: {} -> {}", self.path, old, new),
+            (None, Some(new)) => write!(formatter, "{}: + {}", self.path, new),
+            (Some(old), None) => write!(formatter, "{}: - {}", self.path, old),
+            (None, None) => write!(formatter, "{}", self.path),
+        }
+    }
+}
+
+/// Find the fields that differ between both values. Objects and arrays are descended into, so
+/// only the innermost changed fields are reported.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    walk(&mut Vec::new(), old, new, &mut changes);
+    changes
+}
+
+fn walk(path: &mut Vec<String>, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        _ if old == new => {}
+
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            let keys = old_fields.keys().chain(
+                new_fields
+                    .keys()
+                    .filter(|key| !old_fields.contains_key(*key)),
+            );
+
+            for key in keys {
+                path.push(key.clone());
+                walk_optional(path, old_fields.get(key), new_fields.get(key), changes);
+                path.pop();
+            }
+        }
+
+        (Value::Array(old_items), Value::Array(new_items)) => {
+            for index in 0..old_items.len().max(new_items.len()) {
+                path.push(index.to_string());
+                walk_optional(path, old_items.get(index), new_items.get(index), changes);
+                path.pop();
+            }
+        }
+
+        _ => changes.push(Change {
+            path: path.join("."),
+            old: Some(old.clone()),
+            new: Some(new.clone()),
+        }),
+    }
+}
+
+fn walk_optional(
+    path: &mut Vec<String>,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    changes: &mut Vec<Change>,
+) {
+    match (old, new) {
+        (Some(old), Some(new)) => walk(path, old, new, changes),
+        (None, None) => {}
+        (old, new) => changes.push(Change {
+            path: path.join("."),
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+    }
+}
@@ -0,0 +1,94 @@
+use crate::objects::BuilderError;
+use crate::objects::Objects;
+use crate::release::verify::ListKeys;
+use kube::core::DynamicObject;
+use serde_json::Value;
+
+/// Merge the overlay into the base. Objects present in both are merged deeply, objects present in
+/// only one of them are included as they are. Within merged objects the overlay wins: maps are
+/// merged recursively, items of keyed lists (see `ListKeys`) are merged by their key, everything
+/// else including other lists is replaced.
+pub fn apply(
+    base: Objects,
+    mut overlay: Objects,
+    list_keys: &ListKeys,
+) -> Result<Objects, BuilderError> {
+    let bad_object = |error: serde_json::Error| BuilderError::BadDynamicObject {
+        error: error.to_string(),
+    };
+
+    let mut results = Vec::with_capacity(base.len() + overlay.len());
+
+    for (identifier, object) in base {
+        let overlay_object = match overlay.remove(&identifier) {
+            Some(overlay_object) => overlay_object,
+            None => {
+                results.push(object.dyn_object);
+                continue;
+            }
+        };
+
+        let mut value = serde_json::to_value(&object.dyn_object).map_err(bad_object)?;
+        let patch = serde_json::to_value(&overlay_object.dyn_object).map_err(bad_object)?;
+        merge_value(&mut value, &patch, None, list_keys);
+
+        results.push(serde_json::from_value::<DynamicObject>(value).map_err(bad_object)?);
+    }
+
+    results.extend(overlay.into_iter().map(|(_, object)| object.dyn_object));
+
+    Objects::from_iter_checked(results)
+}
+
+/// Merge the patch into the subject, which is found in the field of the given name.
+fn merge_value(subject: &mut Value, patch: &Value, field: Option<&str>, list_keys: &ListKeys) {
+    match (subject, patch) {
+        (Value::Object(fields), Value::Object(patch_fields)) => {
+            for (key, patch_value) in patch_fields {
+                match fields.get_mut(key) {
+                    Some(value) => merge_value(value, patch_value, Some(key.as_str()), list_keys),
+                    None => {
+                        fields.insert(key.clone(), patch_value.clone());
+                    }
+                }
+            }
+        }
+
+        (Value::Array(items), Value::Array(patch_items)) => {
+            match field.and_then(|field| list_keys.key_of(field)) {
+                Some(key) if is_keyed(items, key) && is_keyed(patch_items, key) => {
+                    merge_keyed_list(items, patch_items, key, list_keys)
+                }
+
+                _ => *items = patch_items.clone(),
+            }
+        }
+
+        (subject, patch) => *subject = patch.clone(),
+    }
+}
+
+/// Merge the items of the patch into the items with the same key. Items without a counterpart
+/// are appended.
+fn merge_keyed_list(
+    items: &mut Vec<Value>,
+    patch_items: &[Value],
+    key: &str,
+    list_keys: &ListKeys,
+) {
+    for patch_item in patch_items {
+        let existing = items
+            .iter_mut()
+            .find(|item| item.get(key) == patch_item.get(key));
+
+        match existing {
+            Some(item) => merge_value(item, patch_item, None, list_keys),
+            None => items.push(patch_item.clone()),
+        }
+    }
+}
+
+/// Do all items carry the key?
+fn is_keyed(items: &[Value], key: &str) -> bool {
+    items.iter().all(|item| item.get(key).is_some())
+}
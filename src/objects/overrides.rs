@@ -0,0 +1,262 @@
+use crate::identifier::Identifier;
+use crate::objects::BuilderError;
+use crate::objects::Objects;
+use kube::core::DynamicObject;
+use serde_json::Map;
+use serde_json::Value;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error that occurs when parsing a malformed override
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub input: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "Invalid override {:?}: {}",
+            self.input, self.reason
+        )
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// Error that occurs when applying an override
+#[derive(Debug)]
+pub enum Error {
+    /// The override did not match any object
+    NoMatch { input: String },
+
+    /// The path of the override could not be followed
+    BadPath {
+        input: String,
+        identifier: Identifier,
+        reason: String,
+    },
+
+    /// The overridden object could not be converted
+    JSONError(serde_json::Error),
+
+    /// The overridden objects could not be assembled
+    BuildError(BuilderError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::NoMatch { input } => {
+                write!(formatter, "Override {:?} does not match any object", input)
+            }
+            Error::BadPath {
+                input,
+                identifier,
+                reason,
+            } => write!(
+                formatter,
+                "Override {:?} cannot be applied to {}: {}",
+                input, identifier, reason
+            ),
+            Error::JSONError(error) => write!(formatter, "Malformed object: {}", error),
+            Error::BuildError(error) => write!(formatter, "{}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::JSONError(error) => Some(error),
+            Error::BuildError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::JSONError(error)
+    }
+}
+
+impl From<BuilderError> for Error {
+    fn from(error: BuilderError) -> Self {
+        Error::BuildError(error)
+    }
+}
+
+/// Override of a field, e.g. `spec.replicas=3` or `Deployment/frontend:spec.replicas=3`
+///
+/// Overrides that target an object (`Kind/name:`) create missing fields along the path. Overrides
+/// without a target apply to all objects in which the field already exists.
+#[derive(Clone, Debug)]
+pub struct Override {
+    input: String,
+    target: Option<(String, String)>,
+    path: Vec<String>,
+    value: Value,
+}
+
+impl Override {
+    fn targets(&self, identifier: &Identifier) -> bool {
+        match &self.target {
+            Some((kind, name)) => {
+                identifier.kind() == kind.as_str() && identifier.name() == name.as_str()
+            }
+            None => true,
+        }
+    }
+
+    /// Apply the override to the given objects.
+    pub fn apply(&self, objects: Objects) -> Result<Objects, Error> {
+        let create = self.target.is_some();
+        let mut matched = false;
+        let mut results = Vec::with_capacity(objects.len());
+
+        for (identifier, object) in objects {
+            if !self.targets(&identifier) {
+                results.push(object.dyn_object);
+                continue;
+            }
+
+            let mut value = serde_json::to_value(&object.dyn_object)?;
+            let applied = set_path(&mut value, self.path.as_slice(), &self.value, create).map_err(
+                |reason| Error::BadPath {
+                    input: self.input.clone(),
+                    identifier: identifier.clone(),
+                    reason,
+                },
+            )?;
+
+            if applied {
+                matched = true;
+                results.push(serde_json::from_value::<DynamicObject>(value)?);
+            } else {
+                results.push(object.dyn_object);
+            }
+        }
+
+        if !matched {
+            return Err(Error::NoMatch {
+                input: self.input.clone(),
+            });
+        }
+
+        Ok(Objects::from_iter_checked(results)?)
+    }
+}
+
+/// Apply the overrides in order to the given objects.
+pub fn apply_all(overrides: &[Override], mut objects: Objects) -> Result<Objects, Error> {
+    for item in overrides {
+        objects = item.apply(objects)?;
+    }
+
+    Ok(objects)
+}
+
+fn set_path(
+    subject: &mut Value,
+    path: &[String],
+    value: &Value,
+    create: bool,
+) -> Result<bool, String> {
+    let (segment, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *subject = value.clone();
+            return Ok(true);
+        }
+    };
+
+    if create && subject.is_null() {
+        *subject = Value::Object(Map::new());
+    }
+
+    match subject {
+        Value::Object(fields) => {
+            if !fields.contains_key(segment) {
+                if !create {
+                    return Ok(false);
+                }
+
+                fields.insert(segment.clone(), Value::Null);
+            }
+
+            match fields.get_mut(segment) {
+                Some(field) => set_path(field, rest, value, create),
+                None => Ok(false),
+            }
+        }
+
+        Value::Array(items) => {
+            let item = segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index));
+
+            match item {
+                Some(item) => set_path(item, rest, value, create),
+                None if create => Err(format!("{} is not a valid index into the list", segment)),
+                None => Ok(false),
+            }
+        }
+
+        _ if create => Err(format!("Cannot descend into scalar to reach {}", segment)),
+
+        _ => Ok(false),
+    }
+}
+
+impl FromStr for Override {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = |reason: &str| ParseError {
+            input: input.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let (selection, raw_value) = input
+            .split_once('=')
+            .ok_or_else(|| error("Expected '[Kind/name:]path=value'"))?;
+
+        let (target, raw_path) = match selection.split_once(':') {
+            Some((target, raw_path)) => {
+                let (kind, name) = target
+                    .split_once('/')
+                    .filter(|(kind, name)| !kind.is_empty() && !name.is_empty())
+                    .ok_or_else(|| error("Target must be of the form 'Kind/name'"))?;
+
+                (Some((kind.to_string(), name.to_string())), raw_path)
+            }
+
+            None => (None, selection),
+        };
+
+        let path: Vec<String> = raw_path.split('.').map(str::to_string).collect();
+
+        if path.iter().any(String::is_empty) {
+            return Err(error("Path must not contain empty segments"));
+        }
+
+        // Values follow the YAML rules for scalars, e.g. 'true' is a boolean and '3' is a number.
+        let value = if raw_value.is_empty() {
+            Value::String(String::new())
+        } else {
+            serde_yaml::from_str(raw_value).unwrap_or_else(|_| Value::String(raw_value.to_string()))
+        };
+
+        Ok(Override {
+            input: input.to_string(),
+            target,
+            path,
+            value,
+        })
+    }
+}
@@ -0,0 +1,202 @@
+use crate::objects::BuilderError;
+use crate::objects::Objects;
+use kube::core::DynamicObject;
+use serde_json::Value;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Error that occurs when loading or applying a values file
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be read
+    Read { path: PathBuf, error: io::Error },
+
+    /// The file is not a YAML map of `Kind/name` to partial objects
+    Malformed { path: PathBuf, reason: String },
+
+    /// An entry did not match any object
+    NoMatch { path: PathBuf, target: String },
+
+    /// The merged object could not be converted
+    JSONError(serde_json::Error),
+
+    /// The merged objects could not be assembled
+    BuildError(BuilderError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::Read { path, error } => {
+                write!(formatter, "Failed to read {}: {}", path.display(), error)
+            }
+            Error::Malformed { path, reason } => {
+                write!(
+                    formatter,
+                    "Malformed values file {}: {}",
+                    path.display(),
+                    reason
+                )
+            }
+            Error::NoMatch { path, target } => write!(
+                formatter,
+                "{} in {} does not match any object",
+                target,
+                path.display()
+            ),
+            Error::JSONError(error) => write!(formatter, "Malformed object: {}", error),
+            Error::BuildError(error) => write!(formatter, "{}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Read { error, .. } => Some(error),
+            Error::JSONError(error) => Some(error),
+            Error::BuildError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::JSONError(error)
+    }
+}
+
+impl From<BuilderError> for Error {
+    fn from(error: BuilderError) -> Self {
+        Error::BuildError(error)
+    }
+}
+
+/// Partial objects which are merged into the objects of a release, read from a YAML file like
+///
+/// ```yaml
+/// Deployment/frontend:
+///   spec:
+///     replicas: 3
+/// ```
+#[derive(Clone, Debug)]
+pub struct Values {
+    path: PathBuf,
+    entries: Vec<(String, String, Value)>,
+}
+
+impl Values {
+    /// Read the values from the given file.
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        let malformed = |reason: String| Error::Malformed {
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        let contents = fs::read_to_string(path).map_err(|error| Error::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        let document: Value = serde_yaml::from_str(contents.as_str())
+            .map_err(|error| malformed(error.to_string()))?;
+
+        let fields = match document {
+            Value::Object(fields) => fields,
+            Value::Null => Default::default(),
+            _ => {
+                return Err(malformed(
+                    "Expected a map of 'Kind/name' to objects".to_string(),
+                ))
+            }
+        };
+
+        let mut entries = Vec::with_capacity(fields.len());
+
+        for (target, partial) in fields {
+            let (kind, name) = target
+                .split_once('/')
+                .filter(|(kind, name)| !kind.is_empty() && !name.is_empty())
+                .ok_or_else(|| malformed(format!("{:?} is not of the form 'Kind/name'", target)))?;
+
+            entries.push((kind.to_string(), name.to_string(), partial));
+        }
+
+        Ok(Values {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Merge the partial objects into the matching objects.
+    pub fn apply(&self, objects: Objects) -> Result<Objects, Error> {
+        let mut matched = vec![false; self.entries.len()];
+        let mut results = Vec::with_capacity(objects.len());
+
+        for (identifier, object) in objects {
+            let mut value = None;
+
+            for (index, (kind, name, partial)) in self.entries.iter().enumerate() {
+                if identifier.kind() != kind.as_str() || identifier.name() != name.as_str() {
+                    continue;
+                }
+
+                let value = match &mut value {
+                    Some(value) => value,
+                    None => value.insert(serde_json::to_value(&object.dyn_object)?),
+                };
+
+                deep_merge(value, partial);
+                matched[index] = true;
+            }
+
+            match value {
+                Some(value) => results.push(serde_json::from_value::<DynamicObject>(value)?),
+                None => results.push(object.dyn_object),
+            }
+        }
+
+        if let Some(index) = matched.iter().position(|matched| !matched) {
+            let (kind, name, _) = &self.entries[index];
+            return Err(Error::NoMatch {
+                path: self.path.clone(),
+                target: format!("{}/{}", kind, name),
+            });
+        }
+
+        Ok(Objects::from_iter_checked(results)?)
+    }
+}
+
+/// Apply the values files in order, so later files take precedence.
+pub fn apply_all(values: &[Values], mut objects: Objects) -> Result<Objects, Error> {
+    for item in values {
+        objects = item.apply(objects)?;
+    }
+
+    Ok(objects)
+}
+
+/// Merge the patch into the subject. Maps are merged recursively, everything else including lists
+/// is replaced.
+fn deep_merge(subject: &mut Value, patch: &Value) {
+    match (subject, patch) {
+        (Value::Object(fields), Value::Object(patch_fields)) => {
+            for (key, patch_value) in patch_fields {
+                match fields.get_mut(key) {
+                    Some(field) => deep_merge(field, patch_value),
+                    None => {
+                        fields.insert(key.clone(), patch_value.clone());
+                    }
+                }
+            }
+        }
+
+        (subject, patch) => *subject = patch.clone(),
+    }
+}
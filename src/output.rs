@@ -0,0 +1,26 @@
+use std::fmt;
+
+/// Destination of the summaries printed by the commands
+#[derive(Clone, Copy, Debug)]
+pub struct Output {
+    quiet: bool,
+}
+
+impl Output {
+    /// Create an output which suppresses summaries if `quiet` is set.
+    pub fn new(quiet: bool) -> Self {
+        Output { quiet }
+    }
+
+    /// Print a line of summary to stdout unless the output is quiet.
+    pub fn summary<D: fmt::Display>(&self, line: D) {
+        if !self.quiet {
+            println!("{}", line);
+        }
+    }
+
+    /// Print a warning to stderr. Warnings are printed even if the output is quiet.
+    pub fn warning<D: fmt::Display>(&self, line: D) {
+        eprintln!("Warning: {}", line);
+    }
+}
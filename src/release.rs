@@ -1,16 +1,35 @@
+pub mod checkpoint;
+pub mod normalize;
 pub mod plan;
+pub mod policy;
 pub mod rollback;
 pub mod verify;
 
 use crate::identifier::Identifier;
-use crate::k8s::lock::Lock;
+use crate::k8s;
+use crate::k8s::labels::WithLabels;
+use crate::k8s::selector::Selector;
 use crate::k8s::transaction;
+use crate::meta;
+use crate::objects;
+use crate::objects::config_hash;
+use crate::objects::overlay;
+use crate::objects::overrides;
+use crate::objects::values;
 use crate::objects::Objects;
+use crate::release::normalize::Normalization;
+use crate::release::plan::Applied;
+use crate::release::plan::ExecuteOptions;
 use crate::release::plan::ReleasePlan;
-use k8s_openapi::api::core::v1::ConfigMap;
 use std::collections::hash_map;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum Error {
@@ -22,6 +41,160 @@ pub enum Error {
     ReleaseError {
         error: transaction::Error,
     },
+
+    CheckpointError {
+        error: checkpoint::Error,
+        rollback: Option<rollback::Error>,
+    },
+
+    /// A phase has not been confirmed, see `plan::StepConfirmation`
+    Aborted {
+        phase: plan::Phase,
+        rollback: Option<rollback::Error>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::RollbackError { error, cause } => write!(
+                formatter,
+                "Rollback after failure ({}) failed as well: {}",
+                cause, error
+            ),
+            Error::ReleaseError { error } => write!(formatter, "Release failed: {}", error),
+            Error::CheckpointError {
+                error,
+                rollback: None,
+            } => write!(formatter, "Checkpoint failed: {}", error),
+            Error::CheckpointError {
+                error,
+                rollback: Some(rollback),
+            } => write!(
+                formatter,
+                "Checkpoint failed ({}) and so did the rollback: {}",
+                error, rollback
+            ),
+            Error::Aborted {
+                phase,
+                rollback: None,
+            } => write!(formatter, "Aborted before {}", phase),
+            Error::Aborted {
+                phase,
+                rollback: Some(rollback),
+            } => write!(
+                formatter,
+                "Aborted before {} and the rollback failed: {}",
+                phase, rollback
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::RollbackError { error, .. } => Some(error),
+            Error::ReleaseError { error } => Some(error),
+            Error::CheckpointError { error, .. } => Some(error),
+            Error::Aborted { rollback, .. } => rollback
+                .as_ref()
+                .map(|error| error as &(dyn error::Error + 'static)),
+        }
+    }
+}
+
+/// Name of the ConfigMap used to lock the release with the given name
+pub fn lock_name(release_name: &str) -> String {
+    format!("{}-lock-{}", meta::CRATE_NAME, release_name)
+}
+
+/// Name of the ConfigMap used to persist the rollback plan of the release with the given name
+pub fn checkpoint_name(release_name: &str) -> String {
+    format!("{}-rollback", release_name)
+}
+
+/// Builder for releases
+#[derive(Debug, Default)]
+pub struct Builder {
+    objects: objects::Builder,
+}
+
+impl Builder {
+    /// Create a new builder without any objects.
+    pub fn new() -> Self {
+        Builder {
+            objects: objects::Builder::new(),
+        }
+    }
+
+    /// Expand environment variables in the inputs before parsing them.
+    pub fn with_env_expansion(mut self, expand_env: bool) -> Self {
+        self.objects = self.objects.with_env_expansion(expand_env);
+        self
+    }
+
+    /// Number of objects added so far
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Have no objects been added so far?
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Members of archives which have been skipped, see `objects::Builder::skipped`
+    pub fn skipped(&self) -> &[PathBuf] {
+        self.objects.skipped()
+    }
+
+    /// Add the objects contained in a YAML document.
+    pub fn add_objects<SomeRead>(&mut self, input: SomeRead) -> Result<(), objects::BuilderError>
+    where
+        SomeRead: io::Read,
+    {
+        self.objects.read_objects(input)
+    }
+
+    /// Add an object given as JSON value.
+    pub fn add_value(&mut self, value: serde_json::Value) -> Result<(), objects::BuilderError> {
+        self.objects.add_value(value)
+    }
+
+    /// Add many objects given as JSON values.
+    pub fn add_values<I>(&mut self, values: I) -> Result<(), objects::BuilderError>
+    where
+        I: IntoIterator<Item = serde_json::Value>,
+    {
+        self.objects.add_values(values)
+    }
+
+    /// Add the objects contained in a file, a directory or an archive.
+    pub fn add_objects_from_path(&mut self, input: &Path) -> Result<(), objects::BuilderError> {
+        self.objects.read_objects_from_path(input)
+    }
+
+    /// Use the objects added so far as an overlay on the objects of the base, see
+    /// `overlay::apply`.
+    pub fn with_base(
+        self,
+        base: Builder,
+        list_keys: &verify::ListKeys,
+    ) -> Result<Self, objects::BuilderError> {
+        let objects = overlay::apply(base.objects.finish(), self.objects.finish(), list_keys)?;
+
+        let mut builder = Builder::new();
+        builder
+            .objects
+            .extend(objects.into_iter().map(|(_, object)| object.dyn_object))?;
+        Ok(builder)
+    }
+
+    /// Finalize the building process.
+    pub fn finish(self, name: String) -> Release {
+        Release::from_builder(name, self)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -35,40 +208,83 @@ impl Release {
         Release { name, objects }
     }
 
-    #[allow(clippy::needless_lifetimes)]
-    pub async fn lock<'a>(
-        &self,
-        api: &'a kube::Api<ConfigMap>,
-    ) -> Result<Lock<'a, ConfigMap>, kube::Error> {
-        Lock::new(api, format!("{}-lock", self.name)).await
+    pub fn from_builder(name: String, builder: Builder) -> Self {
+        Self::from_objects(name, builder.objects.finish())
+    }
+
+    /// Apply the overrides in order to the objects of the release.
+    pub fn with_overrides(
+        self,
+        overrides: &[overrides::Override],
+    ) -> Result<Self, overrides::Error> {
+        let objects = overrides::apply_all(overrides, self.objects)?;
+        Ok(Release { objects, ..self })
+    }
+
+    /// Merge the values files in order into the objects of the release.
+    pub fn with_values(self, values: &[values::Values]) -> Result<Self, values::Error> {
+        let objects = values::apply_all(values, self.objects)?;
+        Ok(Release { objects, ..self })
+    }
+
+    /// Label the objects with the recommended `app.kubernetes.io/managed-by` and
+    /// `app.kubernetes.io/instance` labels. Unlike the labels added when deploying, these are part
+    /// of the objects and therefore of the release state.
+    pub fn with_recommended_labels(self) -> Self {
+        let instance = k8s::Instance(self.name.clone());
+        let objects = self
+            .objects
+            .into_iter()
+            .map(|(identifier, object)| {
+                let object = object.with_label(&k8s::ManagedBy).with_label(&instance);
+                (identifier, object)
+            })
+            .collect();
+
+        Release { objects, ..self }
+    }
+
+    /// Suffix the names of ConfigMaps and Secrets with a hash of their contents, see
+    /// `config_hash::apply`.
+    pub fn with_hashed_config(self) -> Result<Self, objects::BuilderError> {
+        let objects = config_hash::apply(self.objects)?;
+        Ok(Release { objects, ..self })
     }
 
     pub async fn upgrade(
         &self,
         old: &Self,
-        mut client: kube::Client,
-    ) -> Result<(kube::Client, ReleasePlan), Error> {
+        client: kube::Client,
+        options: &ExecuteOptions,
+    ) -> Result<(kube::Client, ReleasePlan, Applied), Error> {
         let plan = ReleasePlan::new(&self.name, &self.objects, &old.objects);
-        client = plan.execute(client).await?;
-        Ok((client, plan))
+        let (client, applied) = plan.execute(client, options).await?;
+        Ok((client, plan, applied))
     }
 
     pub async fn install(
         &self,
-        mut client: kube::Client,
-    ) -> Result<(kube::Client, ReleasePlan), Error> {
+        client: kube::Client,
+        options: &ExecuteOptions,
+    ) -> Result<(kube::Client, ReleasePlan, Applied), Error> {
         let plan = ReleasePlan::new(&self.name, &self.objects, &Objects::empty());
-        client = plan.execute(client).await?;
-        Ok((client, plan))
+        let (client, applied) = plan.execute(client, options).await?;
+        Ok((client, plan, applied))
+    }
+
+    /// Plan which removes all objects of the release.
+    pub fn uninstall_plan(&self) -> ReleasePlan {
+        ReleasePlan::new(&self.name, &Objects::empty(), &self.objects)
     }
 
     pub async fn uninstall(
         &self,
-        mut client: kube::Client,
-    ) -> Result<(kube::Client, ReleasePlan), Error> {
-        let plan = ReleasePlan::new(&self.name, &Objects::empty(), &self.objects);
-        client = plan.execute(client).await?;
-        Ok((client, plan))
+        client: kube::Client,
+        options: &ExecuteOptions,
+    ) -> Result<(kube::Client, ReleasePlan, Applied), Error> {
+        let plan = self.uninstall_plan();
+        let (client, applied) = plan.execute(client, options).await?;
+        Ok((client, plan, applied))
     }
 
     pub fn hash_value(&self) -> u64 {
@@ -77,6 +293,111 @@ impl Release {
         hasher.finish()
     }
 
+    /// Separate the CustomResourceDefinitions from the rest of the release.
+    pub fn without_crds(self) -> (Self, Vec<objects::Object>) {
+        let (crds, objects): (HashMap<_, _>, HashMap<_, _>) = self
+            .objects
+            .into_iter()
+            .partition(|(_, object)| plan::is_crd(object));
+
+        let release = Release {
+            objects: Objects::from(objects),
+            ..self
+        };
+
+        (
+            release,
+            crds.into_iter().map(|(_, object)| object).collect(),
+        )
+    }
+
+    /// Compare the objects of both releases field by field. Unlike comparing `hash_value`, this
+    /// cannot be fooled by hash collisions.
+    pub fn objects_equal(&self, other: &Self) -> bool {
+        self.objects.len() == other.objects.len()
+            && self.objects.iter().all(|(identifier, object)| {
+                other.objects.get(identifier).map_or(false, |other_object| {
+                    match (
+                        serde_json::to_value(object),
+                        serde_json::to_value(other_object),
+                    ) {
+                        (Ok(value), Ok(other_value)) => value == other_value,
+                        _ => false,
+                    }
+                })
+            })
+    }
+
+    /// Apply the normalization to every object of the release.
+    pub fn normalized(&self, normalization: &Normalization) -> Self {
+        let objects = self
+            .objects
+            .iter()
+            .map(|(identifier, object)| (identifier.clone(), normalization.apply(object.clone())))
+            .collect();
+
+        Release {
+            name: self.name.clone(),
+            objects,
+        }
+    }
+
+    /// Do both releases consist of the same objects, once the differences disregarded by the
+    /// normalization are removed?
+    pub fn is_equivalent(&self, other: &Self, normalization: &Normalization) -> bool {
+        matches!(
+            self.compare(other, normalization),
+            Comparison::Identical | Comparison::Equivalent
+        )
+    }
+
+    /// Compare the objects of both releases, see `Comparison`.
+    pub fn compare(&self, other: &Self, normalization: &Normalization) -> Comparison {
+        if self.objects_equal(other) {
+            return Comparison::Identical;
+        }
+
+        let lhs = self.normalized(normalization);
+        let rhs = other.normalized(normalization);
+
+        // The hash is only a quick check, equal hashes are confirmed by comparing the objects.
+        if lhs.hash_value() != rhs.hash_value() {
+            Comparison::Different
+        } else if lhs.objects_equal(&rhs) {
+            Comparison::Equivalent
+        } else {
+            Comparison::HashCollision
+        }
+    }
+
+    /// Compare the objects of the release with the objects in the cluster which are labelled as
+    /// belonging to it. Objects without a namespace are expected in the given namespace.
+    pub async fn detect_drift(
+        &self,
+        client: kube::Client,
+        namespace: &str,
+    ) -> Result<verify::DriftReport, kube::Error> {
+        let (live, skipped) = verify::find_release_objects(
+            client,
+            self.name.clone(),
+            &Selector::default(),
+            false,
+            verify::DEFAULT_CONCURRENCY,
+        )
+        .await?;
+
+        let mut report = verify::detect_drift(
+            self.name.as_str(),
+            &self.objects,
+            live,
+            namespace,
+            &verify::ListKeys::default(),
+        );
+        report.skipped = skipped;
+
+        Ok(report)
+    }
+
     pub fn name(&self) -> &String {
         &self.name
     }
@@ -86,6 +407,22 @@ impl Release {
     }
 }
 
+/// Outcome of comparing two releases
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// The objects are identical.
+    Identical,
+
+    /// The objects only differ in parts disregarded by the normalization.
+    Equivalent,
+
+    /// The objects differ.
+    Different,
+
+    /// The objects differ even though their hashes are equal, which points to a flaw in hashing.
+    HashCollision,
+}
+
 impl Hash for Release {
     fn hash<SomeHasher>(&self, hasher: &mut SomeHasher)
     where
@@ -93,7 +430,8 @@ impl Hash for Release {
     {
         self.name.hash(hasher);
 
-        for (name, object) in &self.objects {
+        // The order of iteration differs between collections with the same objects.
+        for (name, object) in self.objects.iter_ordered() {
             name.hash(hasher);
 
             match serde_json::to_string(object) {
@@ -103,3 +441,99 @@ impl Hash for Release {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYMENT: &str = "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web}}";
+
+    fn release_from(documents: &[&str]) -> Release {
+        let mut builder = Builder::new();
+        for document in documents {
+            builder
+                .add_objects(document.as_bytes())
+                .expect("Fixture must be valid");
+        }
+        builder.finish("test".to_string())
+    }
+
+    #[test]
+    fn status_is_disregarded_if_ignored() {
+        let release = release_from(&[DEPLOYMENT]);
+        let with_status = release_from(&[
+            "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web}, status: {replicas: 1}}",
+        ]);
+
+        assert!(!release.is_equivalent(&with_status, &Normalization::new()));
+        assert!(release.is_equivalent(&with_status, &Normalization::new().ignore_status(true)));
+    }
+
+    #[test]
+    fn ignored_annotations_are_disregarded() {
+        let release = release_from(&[DEPLOYMENT]);
+        let annotated = release_from(&[
+            "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web, annotations: {kubectl.kubernetes.io/last-applied-configuration: '{}'}}}",
+        ]);
+
+        assert!(!release.is_equivalent(&annotated, &Normalization::new()));
+        assert!(release.is_equivalent(&annotated, &Normalization::new().ignore_injected_metadata()));
+    }
+
+    #[test]
+    fn equivalence_is_independent_of_insertion_order() {
+        let documents = [
+            DEPLOYMENT,
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: a}}",
+            "{apiVersion: v1, kind: Secret, metadata: {name: b}}",
+        ];
+        let forward = release_from(&documents);
+        let backward = release_from(&[documents[2], documents[1], documents[0]]);
+
+        assert!(forward.is_equivalent(&backward, &Normalization::new()));
+    }
+
+    #[test]
+    fn comparison_tells_identical_from_equivalent() {
+        let release = release_from(&[DEPLOYMENT]);
+        let with_status = release_from(&[
+            "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web}, status: {replicas: 1}}",
+        ]);
+        let ignore_status = Normalization::new().ignore_status(true);
+
+        assert_eq!(
+            release.compare(&release.clone(), &ignore_status),
+            Comparison::Identical
+        );
+        assert_eq!(
+            release.compare(&with_status, &ignore_status),
+            Comparison::Equivalent
+        );
+        assert_eq!(
+            release.compare(&with_status, &Normalization::new()),
+            Comparison::Different
+        );
+    }
+
+    #[test]
+    fn objects_equal_detects_a_single_changed_field() {
+        let documents = [
+            "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web}, spec: {replicas: 2, template: {spec: {containers: [{name: web, image: 'web:1'}]}}}}",
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: a}, data: {x: '1'}}",
+        ];
+        let release = release_from(&documents);
+        let changed_image = release_from(&[
+            "{apiVersion: apps/v1, kind: Deployment, metadata: {name: web}, spec: {replicas: 2, template: {spec: {containers: [{name: web, image: 'web:2'}]}}}}",
+            documents[1],
+        ]);
+        let changed_label = release_from(&[
+            documents[0],
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: a, labels: {tier: web}}, data: {x: '1'}}",
+        ]);
+
+        assert!(release.objects_equal(&release_from(&[documents[1], documents[0]])));
+        assert!(!release.objects_equal(&changed_image));
+        assert!(!changed_image.objects_equal(&release));
+        assert!(!release.objects_equal(&changed_label));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::k8s;
+use crate::k8s::annotations::WithAnnotations;
+use crate::k8s::labels::WithLabels;
+use crate::k8s::transaction;
+use crate::objects::Object;
+use crate::release::rollback;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api;
+use serde::Deserialize;
+use serde::Serialize;
+use std::error;
+use std::fmt;
+
+const CHECKPOINT_KEY: &str = "rollback_plan";
+
+#[derive(Debug)]
+pub enum Error {
+    Kube(kube::Error),
+    Malformed(serde_json::Error),
+    Corrupt(ConfigMap),
+    Update(transaction::Error),
+
+    /// A ConfigMap with the name of the checkpoint exists, but it isn't a checkpoint
+    Foreign {
+        name: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::Kube(error) => write!(formatter, "Kubernetes error: {}", error),
+            Error::Malformed(error) => write!(formatter, "Malformed checkpoint: {}", error),
+            Error::Corrupt(config_map) => write!(
+                formatter,
+                "Checkpoint {} does not contain a rollback plan",
+                config_map.metadata.name.as_deref().unwrap_or("")
+            ),
+            Error::Update(error) => write!(formatter, "Failed to save checkpoint: {}", error),
+            Error::Foreign { name } => write!(
+                formatter,
+                "ConfigMap {} exists but is not labelled as a checkpoint",
+                name
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Kube(error) => Some(error),
+            Error::Malformed(error) => Some(error),
+            Error::Corrupt(_) => None,
+            Error::Update(error) => Some(error),
+            Error::Foreign { .. } => None,
+        }
+    }
+}
+
+impl From<kube::Error> for Error {
+    fn from(error: kube::Error) -> Self {
+        Error::Kube(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Malformed(error)
+    }
+}
+
+/// Rollback plan persisted in a ConfigMap, so an interrupted execution can still be rolled back
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub(crate) creations: Vec<Object>,
+    pub(crate) upgrades: Vec<Object>,
+    pub(crate) deletions: Vec<Object>,
+    pub(crate) recreations: Vec<Object>,
+}
+
+impl Checkpoint {
+    /// Rollback plan contained in the checkpoint
+    pub fn to_plan(&self) -> rollback::Plan<'_> {
+        let mut plan = rollback::Plan::new();
+
+        let actions = self
+            .creations
+            .iter()
+            .map(|object| (transaction::Action::Create, object))
+            .chain(
+                self.upgrades
+                    .iter()
+                    .map(|object| (transaction::Action::Apply, object)),
+            )
+            .chain(
+                self.deletions
+                    .iter()
+                    .map(|object| (transaction::Action::Delete, object)),
+            )
+            .chain(
+                self.recreations
+                    .iter()
+                    .map(|object| (transaction::Action::Recreate, object)),
+            );
+
+        for action in actions {
+            plan.register_action(action);
+        }
+
+        plan
+    }
+
+    fn to_config_map(&self, name: &str) -> Result<ConfigMap, Error> {
+        let mut config_map = ConfigMap::default()
+            .with_label(&k8s::ObjectType::Checkpoint)
+            .with_annotation(&k8s::CrateVersion);
+
+        config_map.metadata.name = Some(name.to_string());
+        config_map
+            .data
+            .insert(CHECKPOINT_KEY.to_string(), serde_json::to_string(&self)?);
+
+        Ok(config_map)
+    }
+
+    fn from_config_map(config_map: ConfigMap) -> Result<Self, Error> {
+        match config_map.data.get(CHECKPOINT_KEY) {
+            Some(data) => Ok(serde_json::from_str(data.as_str())?),
+            None => Err(Error::Corrupt(config_map)),
+        }
+    }
+
+    /// Look up the checkpoint with the given name. ConfigMaps which aren't labelled as checkpoints
+    /// are never read, overwritten or deleted.
+    async fn get(api: &kube::Api<ConfigMap>, name: &str) -> Result<Option<ConfigMap>, Error> {
+        match api.get(name).await {
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(None),
+            Err(error) => Err(Error::Kube(error)),
+            Ok(config_map) if !config_map.has_label(&k8s::ObjectType::Checkpoint) => {
+                Err(Error::Foreign {
+                    name: name.to_string(),
+                })
+            }
+            Ok(config_map) => Ok(Some(config_map)),
+        }
+    }
+
+    /// Save the checkpoint under the given name.
+    pub async fn save(&self, api: &kube::Api<ConfigMap>, name: &str) -> Result<(), Error> {
+        Self::get(api, name).await?;
+
+        transaction::apply(api, &self.to_config_map(name)?)
+            .await
+            .map_err(Error::Update)?;
+
+        Ok(())
+    }
+
+    /// Load the checkpoint with the given name, if there is one.
+    pub async fn load(api: &kube::Api<ConfigMap>, name: &str) -> Result<Option<Self>, Error> {
+        match Self::get(api, name).await? {
+            Some(config_map) => Ok(Some(Self::from_config_map(config_map)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove the checkpoint with the given name. Removing a checkpoint which doesn't exist is not
+    /// an error.
+    pub async fn discard(api: &kube::Api<ConfigMap>, name: &str) -> Result<(), Error> {
+        if Self::get(api, name).await?.is_none() {
+            return Ok(());
+        }
+
+        match api.delete(name, &api::DeleteParams::default()).await {
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) | Ok(_) => Ok(()),
+            Err(error) => Err(Error::Kube(error)),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::k8s;
+use crate::k8s::annotations::ToAnnotation;
+use crate::k8s::labels::ToLabel;
+use crate::objects::Object;
+use std::collections::BTreeSet;
+
+/// Annotation written by `kubectl apply`, which merely repeats the object
+const LAST_APPLIED_KEY: &str = "kubectl.kubernetes.io/last-applied-configuration";
+
+/// Rules for disregarding differences between objects which don't change what is deployed
+#[derive(Clone, Debug, Default)]
+pub struct Normalization {
+    ignored_annotations: BTreeSet<String>,
+    ignored_labels: BTreeSet<String>,
+    ignore_status: bool,
+}
+
+impl Normalization {
+    /// Normalization that disregards nothing.
+    pub fn new() -> Self {
+        Normalization {
+            ignored_annotations: BTreeSet::new(),
+            ignored_labels: BTreeSet::new(),
+            ignore_status: false,
+        }
+    }
+
+    /// Disregard the given annotations.
+    pub fn ignore_annotations(mut self, keys: Vec<String>) -> Self {
+        self.ignored_annotations.extend(keys);
+        self
+    }
+
+    /// Disregard the given labels.
+    pub fn ignore_labels(mut self, keys: Vec<String>) -> Self {
+        self.ignored_labels.extend(keys);
+        self
+    }
+
+    /// Disregard the status of objects.
+    pub fn ignore_status(mut self, ignore_status: bool) -> Self {
+        self.ignore_status = ignore_status;
+        self
+    }
+
+    /// Disregard the labels and annotations which are added when deploying, as well as the
+    /// annotation written by `kubectl apply`.
+    pub fn ignore_injected_metadata(self) -> Self {
+        let labels = vec![
+            k8s::ObjectType::Managed.to_label().0.to_string(),
+            k8s::ReleaseName(String::new()).to_label().0.to_string(),
+        ];
+        let annotations = vec![
+            k8s::CrateVersion.to_annotation().0.to_string(),
+            LAST_APPLIED_KEY.to_string(),
+        ];
+
+        self.ignore_labels(labels).ignore_annotations(annotations)
+    }
+
+    /// Remove the disregarded parts from the object.
+    pub fn apply(&self, mut object: Object) -> Object {
+        let metadata = &mut object.dyn_object.metadata;
+        metadata
+            .annotations
+            .retain(|key, _| !self.ignored_annotations.contains(key));
+        metadata
+            .labels
+            .retain(|key, _| !self.ignored_labels.contains(key));
+
+        if self.ignore_status {
+            if let Some(fields) = object.dyn_object.data.as_object_mut() {
+                fields.remove("status");
+            }
+        }
+
+        object
+    }
+}
@@ -1,18 +1,418 @@
+use crate::identifier::Identifier;
 use crate::k8s;
 use crate::k8s::annotations::WithAnnotations;
+use crate::k8s::api_resource;
 use crate::k8s::labels::WithLabels;
 use crate::k8s::transaction;
+use crate::meta;
+use crate::metrics;
+use crate::objects::CycleError;
 use crate::objects::Object;
 use crate::release;
+use crate::release::checkpoint::Checkpoint;
 use crate::release::rollback;
+use crate::utils::rate_limit::RateLimiter;
 use async_trait::async_trait;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::core::DynamicObject;
+use kube::core::GroupVersionKind;
 use kube::Client;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How upgraded objects are applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApplyStrategy {
+    /// Server-side apply, which takes ownership of all configured fields
+    ServerSide,
+
+    /// Client-side three-way merge like `kubectl apply`, which preserves changes made to fields
+    /// outside of the configuration
+    ThreeWay,
+}
+
+/// Objects as returned by the API server after they have been created or upgraded, including the
+/// fields assigned by the server such as UIDs and resource versions
+pub type AppliedObjects = HashMap<Identifier, DynamicObject>;
+
+/// Outcome of an executed plan
+#[derive(Debug, Default)]
+pub struct Applied {
+    /// Objects which have been created or upgraded
+    pub objects: AppliedObjects,
+
+    /// Problems which did not fail the execution
+    pub warnings: Vec<ExecuteWarning>,
+}
+
+/// Problem which occurred while executing a plan without failing it
+#[derive(Clone, Debug)]
+pub enum ExecuteWarning {
+    /// The checkpoint outlived the finished plan and has to be deleted by hand, or recovering the
+    /// release would roll the plan back.
+    CheckpointNotDiscarded { name: String, reason: String },
+
+    /// The applied object could not be written to the output directory, see
+    /// `ExecuteOptions::output_dir`.
+    ManifestNotWritten {
+        name: String,
+        directory: PathBuf,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ExecuteWarning {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            ExecuteWarning::CheckpointNotDiscarded { name, reason } => {
+                write!(
+                    formatter,
+                    "Failed to discard checkpoint {}: {}",
+                    name, reason
+                )
+            }
+        }
+    }
+}
+
+/// Group of actions of the same type, which are executed one after the other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Creations,
+    Upgrades,
+    Deletions,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Phase::Creations => write!(formatter, "creations"),
+            Phase::Upgrades => write!(formatter, "upgrades"),
+            Phase::Deletions => write!(formatter, "deletions"),
+        }
+    }
+}
+
+/// Decides whether the execution of a plan proceeds with the next phase, e.g. by asking the user
+pub trait StepConfirmation: fmt::Debug + Send + Sync {
+    /// Should the phase which affects the given objects be executed? Declining aborts the
+    /// execution and rolls back the phases executed so far.
+    fn confirm(&self, phase: Phase, identifiers: &[Identifier]) -> bool;
+}
+
+/// Settings for the execution of a plan
+#[derive(Clone, Debug)]
+pub struct ExecuteOptions {
+    /// Interval at which discovery is polled while waiting for new CustomResourceDefinitions
+    pub crd_poll_interval: Duration,
+
+    /// Maximum time to wait for new CustomResourceDefinitions to become available
+    pub crd_timeout: Duration,
+
+    /// Recreate objects whose upgrade fails because it changes immutable fields
+    pub force_recreate: bool,
+
+    /// Name of the ConfigMap in which the rollback plan is persisted after every step
+    pub checkpoint: Option<String>,
+
+    /// Namespace of the checkpoint ConfigMap, defaults to the default namespace of the client
+    pub checkpoint_namespace: Option<String>,
+
+    /// Field manager on whose behalf objects are applied
+    pub field_manager: String,
+
+    /// Maximum time a single action on an object may take
+    pub request_timeout: Option<Duration>,
+
+    /// Point in time after which no action may take place anymore
+    pub deadline: Option<Instant>,
+
+    /// Strategy used to apply upgraded objects
+    pub apply_strategy: ApplyStrategy,
+
+    /// Directory into which created and upgraded objects are written as they were applied
+    pub output_dir: Option<PathBuf>,
+
+    /// Confirmation which is asked for before every phase
+    pub step: Option<Arc<dyn StepConfirmation>>,
+
+    /// Limit on the rate of actions on objects, which is shared by all clones of the options
+    pub rate_limit: Option<RateLimiter>,
+}
+
+impl Default for ExecuteOptions {
+    fn default() -> Self {
+        ExecuteOptions {
+            crd_poll_interval: Duration::from_secs(1),
+            crd_timeout: Duration::from_secs(30),
+            force_recreate: false,
+            checkpoint: None,
+            checkpoint_namespace: None,
+            field_manager: meta::CRATE_NAME.to_string(),
+            request_timeout: None,
+            deadline: None,
+            apply_strategy: ApplyStrategy::ServerSide,
+            output_dir: None,
+            step: None,
+            rate_limit: None,
+        }
+    }
+}
+
+impl ExecuteOptions {
+    /// Wait until the rate limit permits another action.
+    pub(crate) async fn throttle(&self) {
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.acquire().await;
+        }
+    }
+
+    /// Time a single action may take, considering both the request timeout and the deadline
+    fn action_timeout(&self) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+        match (self.request_timeout, remaining) {
+            (Some(request_timeout), Some(remaining)) => Some(request_timeout.min(remaining)),
+            (request_timeout, remaining) => request_timeout.or(remaining),
+        }
+    }
+
+    /// API through which the checkpoint ConfigMap is accessed
+    fn checkpoint_api(&self, client: Client) -> kube::Api<ConfigMap> {
+        match &self.checkpoint_namespace {
+            Some(namespace) => kube::Api::namespaced(client, namespace.as_str()),
+            None => kube::Api::default_namespaced(client),
+        }
+    }
+}
+
+/// Write the object as YAML to `<namespace>/<kind>.<group>-<name>.yaml` in the given directory.
+/// Cluster-scoped objects are written to the directory itself and objects of the core group leave
+/// out the group, so objects which only differ in their namespace or group are kept apart.
+pub fn write_manifest(directory: &Path, object: &DynamicObject) -> Result<(), io::Error> {
+    let (group, kind) = object.types.as_ref().map_or(("", ""), |types| {
+        let group = types
+            .api_version
+            .split_once('/')
+            .map_or("", |(group, _version)| group);
+        (group, types.kind.as_str())
+    });
+    let kind = if group.is_empty() {
+        kind.to_lowercase()
+    } else {
+        format!("{}.{}", kind.to_lowercase(), group)
+    };
+    let name = object.metadata.name.as_deref().unwrap_or_default();
+    let yaml = serde_yaml::to_string(object)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let directory = match &object.metadata.namespace {
+        Some(namespace) => directory.join(namespace),
+        None => directory.to_path_buf(),
+    };
+
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join(format!("{}-{}.yaml", kind, name)), yaml)
+}
+
+/// Order in which the objects are applied, see `Objects::topo_order`. Objects which depend on each
+/// other in a cycle are applied in the order of their kinds instead, in which case the cycle is
+/// returned as well.
+fn apply_order(objects: &release::Objects) -> (Vec<Identifier>, Option<CycleError>) {
+    match objects.topo_order() {
+        Ok(order) => (order, None),
+        Err(cycle) => {
+            let order = objects
+                .iter_ordered()
+                .map(|(identifier, _)| identifier.clone())
+                .collect();
+            (order, Some(cycle))
+        }
+    }
+}
+
+/// Keep a record of the applied object if an output directory is configured. Failing to do so does
+/// not affect the deployment, it is added to the warnings instead.
+fn record_applied(
+    options: &ExecuteOptions,
+    object: &DynamicObject,
+    warnings: &mut Vec<ExecuteWarning>,
+) {
+    if let Some(directory) = &options.output_dir {
+        if let Err(error) = write_manifest(directory, object) {
+            warnings.push(ExecuteWarning::ManifestNotWritten {
+                name: object.metadata.name.clone().unwrap_or_default(),
+                directory: directory.clone(),
+                reason: error.to_string(),
+            });
+        }
+    }
+}
+
+/// Does the live object carry the labels which mark it as managed by the same release as the
+/// tagged object?
+fn is_managed_by_release(live: &DynamicObject, tagged: &Object) -> bool {
+    match k8s::ReleaseName::of(&tagged.dyn_object) {
+        Some(release_name) => {
+            live.has_label(&k8s::ObjectType::Managed) && live.has_label(&release_name)
+        }
+        None => false,
+    }
+}
+
+/// Make sure the object may be deleted, which it may if it is gone already or labelled as part of
+/// the release. A mistake in tracking the release must never remove objects that belong to
+/// something else.
+async fn check_deletable(client: Client, object: &Object) -> Result<(), transaction::Error> {
+    let object_name = object.name().cloned().unwrap_or_default();
+
+    match transaction::get_object(client, object).await {
+        Ok(Some(live)) if !is_managed_by_release(&live, object) => {
+            Err(transaction::Error::NotManaged {
+                kind: object.api_resource.kind.clone(),
+                object_name,
+            })
+        }
+        Ok(_) => Ok(()),
+        Err(kube_error) => Err(transaction::Error::Kube {
+            kube_error,
+            action: transaction::Action::Delete,
+            object_name,
+        }),
+    }
+}
+
+/// Is the object a CustomResourceDefinition?
+pub fn is_crd(object: &Object) -> bool {
+    object.api_resource.group == "apiextensions.k8s.io"
+        && object.api_resource.kind == "CustomResourceDefinition"
+}
+
+/// Kinds that are defined by a CustomResourceDefinition
+pub fn defined_kinds(crd: &Object) -> Vec<GroupVersionKind> {
+    let spec = &crd.dyn_object.data["spec"];
+    let group = spec["group"].as_str().unwrap_or("");
+    let kind = spec["names"]["kind"].as_str().unwrap_or("");
+
+    let mut versions: Vec<&str> = spec["versions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|version| version["served"].as_bool().unwrap_or(true))
+        .filter_map(|version| version["name"].as_str())
+        .collect();
+
+    // Older CustomResourceDefinitions may only specify a single version.
+    versions.extend(spec["version"].as_str());
+
+    versions
+        .into_iter()
+        .map(|version| GroupVersionKind::gvk(group, version, kind))
+        .collect()
+}
+
+/// Wait until the given kinds show up in discovery or the timeout elapses.
+async fn wait_for_kinds(client: &Client, kinds: &[GroupVersionKind], options: &ExecuteOptions) {
+    let started = Instant::now();
+
+    loop {
+        if let Ok((resources, _)) = api_resource::find_api_resources(client, false).await {
+            let available = kinds.iter().all(|kind| {
+                resources.iter().any(|resource| {
+                    resource.group == kind.group
+                        && resource.version == kind.version
+                        && resource.kind == kind.kind
+                })
+            });
+
+            if available {
+                return;
+            }
+        }
+
+        if started.elapsed() >= options.crd_timeout {
+            return;
+        }
+
+        tokio::time::sleep(options.crd_poll_interval).await;
+    }
+}
+
+/// Ask for confirmation of the phase if stepping is enabled. If it is declined, the changes made so
+/// far are rolled back.
+async fn confirm_phase<'a, I>(
+    client: &Client,
+    plan: &rollback::Plan<'_>,
+    options: &ExecuteOptions,
+    phase: Phase,
+    objects: I,
+) -> Result<(), release::Error>
+where
+    I: Iterator<Item = &'a Object>,
+{
+    let step = match &options.step {
+        Some(step) => step,
+        None => return Ok(()),
+    };
+
+    let identifiers: Vec<Identifier> = objects.filter_map(Object::identifier).collect();
+
+    if identifiers.is_empty() || step.confirm(phase, identifiers.as_slice()) {
+        return Ok(());
+    }
+
+    metrics::record_rollback();
+    let rollback = plan.execute(client.clone(), options).await.err();
+    Err(release::Error::Aborted { phase, rollback })
+}
+
+/// Persist the rollback plan if checkpoints are enabled. If the rollback plan can't be persisted,
+/// it is executed right away.
+async fn save_checkpoint(
+    client: &Client,
+    plan: &rollback::Plan<'_>,
+    options: &ExecuteOptions,
+) -> Result<(), release::Error> {
+    let name = match &options.checkpoint {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+
+    let api = options.checkpoint_api(client.clone());
+
+    match plan.to_checkpoint().save(&api, name).await {
+        Ok(()) => Ok(()),
+
+        Err(error) => {
+            metrics::record_rollback();
+            let rollback = plan.execute(client.clone(), options).await.err();
+            Err(release::Error::CheckpointError { error, rollback })
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Create {
     pub(crate) new: Object,
 }
 
+impl Create {
+    /// Object to be created
+    pub fn object(&self) -> &Object {
+        &self.new
+    }
+}
+
 impl rollback::Rollbackable for Create {
     fn to_rollback(&self) -> (transaction::Action, &Object) {
         (transaction::Action::Delete, &self.new)
@@ -25,6 +425,23 @@ pub struct Upgrade {
     pub(crate) old: Object,
 }
 
+impl Upgrade {
+    /// Object as it will be after the upgrade
+    pub fn new_object(&self) -> &Object {
+        &self.new
+    }
+
+    /// Object as it was before the upgrade
+    pub fn old_object(&self) -> &Object {
+        &self.old
+    }
+
+    /// Rollback of the upgrade after it has been carried out by recreating the object
+    fn to_recreate_rollback(&self) -> (transaction::Action, &Object) {
+        (transaction::Action::Recreate, &self.old)
+    }
+}
+
 impl rollback::Rollbackable for Upgrade {
     fn to_rollback(&self) -> (transaction::Action, &Object) {
         (transaction::Action::Apply, &self.old)
@@ -36,17 +453,45 @@ pub struct Delete {
     pub(crate) old: Object,
 }
 
+impl Delete {
+    /// Object to be deleted
+    pub fn object(&self) -> &Object {
+        &self.old
+    }
+}
+
 impl rollback::Rollbackable for Delete {
     fn to_rollback(&self) -> (transaction::Action, &Object) {
         (transaction::Action::Create, &self.old)
     }
 }
 
+/// Identifiers of the objects affected by a release plan
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PlanSummary {
+    pub creations: Vec<Identifier>,
+    pub upgrades: Vec<Identifier>,
+    pub deletions: Vec<Identifier>,
+}
+
+impl PlanSummary {
+    /// Number of objects affected by the plan
+    pub fn len(&self) -> usize {
+        self.creations.len() + self.upgrades.len() + self.deletions.len()
+    }
+
+    /// Does the plan leave everything as it is?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ReleasePlan {
     pub(crate) creations: Vec<Create>,
     pub(crate) upgrades: Vec<Upgrade>,
     pub(crate) deletions: Vec<Delete>,
+    pub(crate) cycles: Vec<CycleError>,
 }
 
 impl ReleasePlan {
@@ -66,19 +511,24 @@ impl ReleasePlan {
             Self::tag_object(release_name.to_string(), object.clone())
         };
 
+        let (new_order, new_cycle) = apply_order(new_objects);
+        let (old_order, old_cycle) = apply_order(old_objects);
+
         // Find things to create.
-        let creations = new_objects
+        let creations = new_order
             .iter()
-            .filter(|(key, _)| !old_objects.contains(*key))
-            .map(|(_, new)| Create {
+            .filter(|key| !old_objects.contains(key))
+            .filter_map(|key| new_objects.get(key))
+            .map(|new| Create {
                 new: with_meta(new),
             })
             .collect();
 
         // Find things to upgrade.
-        let upgrades = new_objects
+        let upgrades = new_order
             .iter()
-            .filter_map(|(key, new)| {
+            .filter_map(|key| {
+                let new = new_objects.get(key)?;
                 old_objects.get(key).map(|old| Upgrade {
                     new: with_meta(new),
                     old: with_meta(old),
@@ -86,63 +536,330 @@ impl ReleasePlan {
             })
             .collect();
 
-        // Find things to delete.
-        let deletions = old_objects
+        // Find things to delete. Objects are deleted before the objects they depend on.
+        let deletions = old_order
             .iter()
-            .filter(|(key, _)| !new_objects.contains(*key))
-            .map(|(_, old)| Delete {
+            .rev()
+            .filter(|key| !new_objects.contains(key))
+            .filter_map(|key| old_objects.get(key))
+            .map(|old| Delete {
                 old: with_meta(old),
             })
             .collect();
 
+        // Cycles among the old objects only matter for the objects which are deleted.
+        let old_cycle = old_cycle
+            .map(|cycle| CycleError {
+                identifiers: cycle
+                    .identifiers
+                    .into_iter()
+                    .filter(|identifier| !new_objects.contains(identifier))
+                    .collect(),
+            })
+            .filter(|cycle| !cycle.identifiers.is_empty());
+
         ReleasePlan {
             creations,
             upgrades,
             deletions,
+            cycles: new_cycle.into_iter().chain(old_cycle).collect(),
+        }
+    }
+
+    /// Objects that the plan creates
+    pub fn creations(&self) -> &[Create] {
+        self.creations.as_slice()
+    }
+
+    /// Objects that the plan upgrades
+    pub fn upgrades(&self) -> &[Upgrade] {
+        self.upgrades.as_slice()
+    }
+
+    /// Objects that the plan deletes
+    pub fn deletions(&self) -> &[Delete] {
+        self.deletions.as_slice()
+    }
+
+    /// Objects which depend on each other in a cycle and are therefore applied or deleted in the
+    /// order of their kinds
+    pub fn cycles(&self) -> &[CycleError] {
+        self.cycles.as_slice()
+    }
+
+    /// Summarise which objects the plan affects.
+    pub fn summary(&self) -> PlanSummary {
+        PlanSummary {
+            creations: self
+                .creations
+                .iter()
+                .filter_map(|creation| creation.new.identifier())
+                .collect(),
+            upgrades: self
+                .upgrades
+                .iter()
+                .filter_map(|upgrade| upgrade.new.identifier())
+                .collect(),
+            deletions: self
+                .deletions
+                .iter()
+                .filter_map(|deletion| deletion.old.identifier())
+                .collect(),
+        }
+    }
+
+    /// Execute the plan, rolling back all changes if a step fails. Returns the objects which have
+    /// been created or upgraded as they are stored in the cluster.
+    pub async fn execute(
+        &self,
+        client: Client,
+        options: &ExecuteOptions,
+    ) -> Result<(Client, Applied), release::Error> {
+        let checkpoint_api = options.checkpoint_api(client.clone());
+        let mut result = self.execute_steps(client, options).await;
+
+        // The checkpoint is only needed while a rollback may still be outstanding.
+        let finished = !matches!(
+            result,
+            Err(release::Error::RollbackError { .. })
+                | Err(release::Error::CheckpointError {
+                    rollback: Some(_),
+                    ..
+                })
+                | Err(release::Error::Aborted {
+                    rollback: Some(_),
+                    ..
+                })
+        );
+
+        if let (Some(name), true) = (&options.checkpoint, finished) {
+            if let Err(error) = Checkpoint::discard(&checkpoint_api, name).await {
+                let warning = ExecuteWarning::CheckpointNotDiscarded {
+                    name: name.clone(),
+                    reason: error.to_string(),
+                };
+
+                // A failed execution is reported by its error, which takes precedence.
+                if let Ok((_client, applied)) = &mut result {
+                    applied.warnings.push(warning);
+                }
+            }
         }
+
+        result
     }
 
-    pub async fn execute(&self, mut client: Client) -> Result<Client, release::Error> {
+    async fn execute_steps(
+        &self,
+        mut client: Client,
+        options: &ExecuteOptions,
+    ) -> Result<(Client, Applied), release::Error> {
+        let mut applied = Applied::default();
         let mut rollback_plan = rollback::Plan::new();
         let mut rollback_client = client.clone();
 
-        for creation in &self.creations {
-            let result = transaction::create_object(client, &creation.new)
-                .await
-                .on_err_rollback(rollback_client, &rollback_plan)
-                .await?;
+        // CustomResourceDefinitions are handled first, so their kinds are available by the time
+        // instances of them are created or upgraded.
+        for crds in &[true, false] {
+            let creations = self
+                .creations
+                .iter()
+                .filter(|creation| is_crd(&creation.new) == *crds);
 
-            client = result.result.client;
-            rollback_client = result.rollback_client;
+            confirm_phase(
+                &rollback_client,
+                &rollback_plan,
+                options,
+                Phase::Creations,
+                creations.clone().map(|creation| &creation.new),
+            )
+            .await?;
 
-            rollback_plan.register(creation);
-        }
+            for creation in creations {
+                options.throttle().await;
+                let started = Instant::now();
+                let field_manager = options.field_manager.as_str();
+                let create = async {
+                    let result = transaction::create_object(client, &creation.new).await?;
+                    transaction::apply_status(result, &creation.new, field_manager).await
+                };
 
-        for upgrade in &self.upgrades {
-            let result = transaction::apply_object(client, &upgrade.new)
-                .await
-                .on_err_rollback(rollback_client, &rollback_plan)
-                .await?;
+                let result = transaction::with_timeout(
+                    options.action_timeout(),
+                    transaction::Action::Create,
+                    &creation.new,
+                    create,
+                )
+                .await;
+                metrics::observe_object_action(&transaction::Action::Create, started.elapsed());
 
-            client = result.result.client;
-            rollback_client = result.rollback_client;
+                let result = result
+                    .on_err_rollback(rollback_client, &rollback_plan, options)
+                    .await?;
+
+                record_applied(options, &result.result.result_object, &mut applied.warnings);
+                if let Some(identifier) = creation.new.identifier() {
+                    applied
+                        .objects
+                        .insert(identifier, result.result.result_object);
+                }
+                client = result.result.client;
+                rollback_client = result.rollback_client;
+
+                rollback_plan.register(creation);
+                save_checkpoint(&rollback_client, &rollback_plan, options).await?;
+            }
+
+            let upgrades = self
+                .upgrades
+                .iter()
+                .filter(|upgrade| is_crd(&upgrade.new) == *crds);
+
+            confirm_phase(
+                &rollback_client,
+                &rollback_plan,
+                options,
+                Phase::Upgrades,
+                upgrades.clone().map(|upgrade| &upgrade.new),
+            )
+            .await?;
+
+            for upgrade in upgrades {
+                options.throttle().await;
+                let started = Instant::now();
+                let field_manager = options.field_manager.as_str();
+                let apply = async {
+                    let result = match options.apply_strategy {
+                        ApplyStrategy::ServerSide => {
+                            transaction::apply_object(client.clone(), &upgrade.new, field_manager)
+                                .await?
+                        }
+
+                        ApplyStrategy::ThreeWay => {
+                            transaction::merge_object(
+                                client.clone(),
+                                &upgrade.new,
+                                &upgrade.old,
+                                field_manager,
+                            )
+                            .await?
+                        }
+                    };
+
+                    transaction::apply_status(result, &upgrade.new, field_manager).await
+                };
+
+                let mut result = transaction::with_timeout(
+                    options.action_timeout(),
+                    transaction::Action::Apply,
+                    &upgrade.new,
+                    apply,
+                )
+                .await;
+                metrics::observe_object_action(&transaction::Action::Apply, started.elapsed());
+
+                // Immutable fields can only be changed by deleting and creating the object again.
+                let recreate = options.force_recreate
+                    && matches!(&result, Err(error) if error.is_immutable_field_change());
+
+                if recreate {
+                    // The old object is gone as soon as it has been deleted, so the rollback has to
+                    // know about it before, in case creating the new object fails or times out.
+                    rollback_plan.register_action(upgrade.to_recreate_rollback());
+                    save_checkpoint(&rollback_client, &rollback_plan, options).await?;
+
+                    options.throttle().await;
+                    let started = Instant::now();
+                    result = transaction::with_timeout(
+                        options.action_timeout(),
+                        transaction::Action::Recreate,
+                        &upgrade.new,
+                        async {
+                            let result = transaction::recreate_object(client, &upgrade.new).await?;
+                            transaction::apply_status(result, &upgrade.new, field_manager).await
+                        },
+                    )
+                    .await;
+                    metrics::observe_object_action(
+                        &transaction::Action::Recreate,
+                        started.elapsed(),
+                    );
+                }
+
+                let result = result
+                    .on_err_rollback(rollback_client, &rollback_plan, options)
+                    .await?;
+
+                record_applied(options, &result.result.result_object, &mut applied.warnings);
+                if let Some(identifier) = upgrade.new.identifier() {
+                    applied
+                        .objects
+                        .insert(identifier, result.result.result_object);
+                }
+                client = result.result.client;
+                rollback_client = result.rollback_client;
 
-            rollback_plan.register(upgrade);
+                if !recreate {
+                    rollback_plan.register(upgrade);
+                    save_checkpoint(&rollback_client, &rollback_plan, options).await?;
+                }
+            }
+
+            if *crds {
+                let kinds: Vec<GroupVersionKind> = self
+                    .creations
+                    .iter()
+                    .map(|creation| &creation.new)
+                    .chain(self.upgrades.iter().map(|upgrade| &upgrade.new))
+                    .filter(|object| is_crd(object))
+                    .flat_map(defined_kinds)
+                    .collect();
+
+                if !kinds.is_empty() {
+                    wait_for_kinds(&client, kinds.as_slice(), options).await;
+                }
+            }
         }
 
+        confirm_phase(
+            &rollback_client,
+            &rollback_plan,
+            options,
+            Phase::Deletions,
+            self.deletions.iter().map(|deletion| &deletion.old),
+        )
+        .await?;
+
         for deletion in &self.deletions {
-            let result = transaction::delete_object(client, &deletion.old)
+            let checked = check_deletable(client.clone(), &deletion.old)
                 .await
-                .on_err_rollback(rollback_client, &rollback_plan)
+                .on_err_rollback(rollback_client, &rollback_plan, options)
+                .await?;
+            rollback_client = checked.rollback_client;
+
+            options.throttle().await;
+            let started = Instant::now();
+            let result = transaction::with_timeout(
+                options.action_timeout(),
+                transaction::Action::Delete,
+                &deletion.old,
+                transaction::delete_object(client, &deletion.old),
+            )
+            .await;
+            metrics::observe_object_action(&transaction::Action::Delete, started.elapsed());
+
+            let result = result
+                .on_err_rollback(rollback_client, &rollback_plan, options)
                 .await?;
 
             client = result.result;
             rollback_client = result.rollback_client;
 
             rollback_plan.register(deletion);
+            save_checkpoint(&rollback_client, &rollback_plan, options).await?;
         }
 
-        Ok(client)
+        Ok((client, applied))
     }
 
     pub fn undo(&self) -> Self {
@@ -169,6 +886,7 @@ impl ReleasePlan {
                     old: upgrade.new.clone(),
                 })
                 .collect(),
+            cycles: self.cycles.clone(),
         }
     }
 }
@@ -180,7 +898,12 @@ struct RollbackTriggerResult<T> {
 
 #[async_trait]
 pub trait RollbackTrigger<T, E> {
-    async fn on_err_rollback(self, client: Client, plan: &rollback::Plan) -> Result<T, E>;
+    async fn on_err_rollback(
+        self,
+        client: Client,
+        plan: &rollback::Plan,
+        options: &ExecuteOptions,
+    ) -> Result<T, E>;
 }
 
 #[async_trait]
@@ -192,6 +915,7 @@ where
         self,
         client: Client,
         plan: &rollback::Plan,
+        options: &ExecuteOptions,
     ) -> Result<RollbackTriggerResult<T>, release::Error> {
         match self {
             Ok(result) => Ok(RollbackTriggerResult {
@@ -200,7 +924,8 @@ where
             }),
 
             Err(cause) => {
-                let rollback_result = plan.execute(client).await;
+                metrics::record_rollback();
+                let rollback_result = plan.execute(client, options).await;
                 Err(match rollback_result {
                     Ok(_) => release::Error::ReleaseError { error: cause },
                     Err(error) => release::Error::RollbackError { error, cause },
@@ -209,3 +934,239 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects;
+
+    const CONFIG_MAP: &str = "
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: settings
+data:
+  level: info
+";
+
+    const CONFIG_MAP_CHANGED: &str = "
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: settings
+data:
+  level: debug
+";
+
+    const SERVICE: &str = "
+apiVersion: v1
+kind: Service
+metadata:
+  name: frontend
+spec:
+  ports:
+    - port: 80
+";
+
+    fn objects_from(documents: &[&str]) -> objects::Objects {
+        let mut builder = objects::Builder::new();
+        builder
+            .read_objects(documents.join("\n---\n").as_bytes())
+            .expect("Fixture must be valid");
+        builder.finish()
+    }
+
+    fn names<'a, I: Iterator<Item = &'a Object>>(objects: I) -> Vec<String> {
+        let mut names: Vec<String> = objects
+            .map(|object| format!("{}/{}", object.api_resource.kind, object.name().unwrap()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn partition(plan: &ReleasePlan) -> (Vec<String>, Vec<String>, Vec<String>) {
+        (
+            names(plan.creations().iter().map(Create::object)),
+            names(plan.upgrades().iter().map(Upgrade::new_object)),
+            names(plan.deletions().iter().map(Delete::object)),
+        )
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn install_creates_everything() {
+        let new = objects_from(&[CONFIG_MAP, SERVICE]);
+        let plan = ReleasePlan::new("test", &new, &objects::Objects::empty());
+
+        assert_eq!(
+            partition(&plan),
+            (
+                strings(&["ConfigMap/settings", "Service/frontend"]),
+                Vec::new(),
+                Vec::new()
+            )
+        );
+    }
+
+    #[test]
+    fn unchanged_release_upgrades_everything() {
+        let objects = objects_from(&[CONFIG_MAP, SERVICE]);
+        let plan = ReleasePlan::new("test", &objects, &objects);
+
+        assert_eq!(
+            partition(&plan),
+            (
+                Vec::new(),
+                strings(&["ConfigMap/settings", "Service/frontend"]),
+                Vec::new()
+            )
+        );
+    }
+
+    #[test]
+    fn added_object_is_created() {
+        let old = objects_from(&[CONFIG_MAP]);
+        let new = objects_from(&[CONFIG_MAP, SERVICE]);
+        let plan = ReleasePlan::new("test", &new, &old);
+
+        assert_eq!(
+            partition(&plan),
+            (
+                strings(&["Service/frontend"]),
+                strings(&["ConfigMap/settings"]),
+                Vec::new()
+            )
+        );
+    }
+
+    #[test]
+    fn removed_object_is_deleted() {
+        let old = objects_from(&[CONFIG_MAP, SERVICE]);
+        let new = objects_from(&[CONFIG_MAP]);
+        let plan = ReleasePlan::new("test", &new, &old);
+
+        assert_eq!(
+            partition(&plan),
+            (
+                Vec::new(),
+                strings(&["ConfigMap/settings"]),
+                strings(&["Service/frontend"])
+            )
+        );
+    }
+
+    #[test]
+    fn changed_object_is_upgraded() {
+        let old = objects_from(&[CONFIG_MAP]);
+        let new = objects_from(&[CONFIG_MAP_CHANGED]);
+        let plan = ReleasePlan::new("test", &new, &old);
+
+        assert_eq!(plan.upgrades().len(), 1);
+
+        let upgrade = &plan.upgrades()[0];
+        assert_eq!(
+            upgrade.old_object().dyn_object.data["data"]["level"],
+            "info"
+        );
+        assert_eq!(
+            upgrade.new_object().dyn_object.data["data"]["level"],
+            "debug"
+        );
+    }
+
+    #[test]
+    fn uninstall_deletes_everything() {
+        let old = objects_from(&[CONFIG_MAP, SERVICE]);
+        let plan = ReleasePlan::new("test", &objects::Objects::empty(), &old);
+
+        assert_eq!(
+            partition(&plan),
+            (
+                Vec::new(),
+                Vec::new(),
+                strings(&["ConfigMap/settings", "Service/frontend"])
+            )
+        );
+    }
+
+    #[test]
+    fn objects_are_tagged_with_the_release() {
+        let old = objects_from(&[CONFIG_MAP]);
+        let new = objects_from(&[SERVICE]);
+        let plan = ReleasePlan::new("test", &new, &old);
+
+        let objects = plan
+            .creations()
+            .iter()
+            .map(Create::object)
+            .chain(plan.deletions().iter().map(Delete::object));
+
+        for object in objects {
+            assert!(object.dyn_object.has_label(&k8s::ObjectType::Managed));
+            assert!(object
+                .dyn_object
+                .has_label(&k8s::ReleaseName("test".to_string())));
+        }
+    }
+
+    #[test]
+    fn cycles_are_part_of_the_plan() {
+        let cycle = objects_from(&[
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: a, ownerReferences: [{apiVersion: v1, kind: ConfigMap, name: b, uid: x}]}}",
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: b, ownerReferences: [{apiVersion: v1, kind: ConfigMap, name: a, uid: y}]}}",
+        ]);
+
+        let install = ReleasePlan::new("test", &cycle, &objects::Objects::empty());
+        assert_eq!(install.creations().len(), 2);
+        assert_eq!(install.cycles().len(), 1);
+
+        // The cycle is only reported once when the objects stay.
+        let upgrade = ReleasePlan::new("test", &cycle, &cycle);
+        assert_eq!(upgrade.cycles().len(), 1);
+
+        let uninstall = ReleasePlan::new("test", &objects::Objects::empty(), &cycle);
+        assert_eq!(uninstall.deletions().len(), 2);
+        assert_eq!(uninstall.cycles().len(), 1);
+
+        let unrelated = ReleasePlan::new(
+            "test",
+            &objects_from(&[SERVICE]),
+            &objects::Objects::empty(),
+        );
+        assert!(unrelated.cycles().is_empty());
+    }
+
+    #[test]
+    fn manifests_of_different_namespaces_and_groups_are_kept_apart() {
+        let directory = std::env::temp_dir().join(format!(
+            "{}-manifests-{}",
+            meta::CRATE_NAME,
+            std::process::id()
+        ));
+
+        let documents = [
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: settings, namespace: payments}}",
+            "{apiVersion: v1, kind: ConfigMap, metadata: {name: settings, namespace: shipping}}",
+            "{apiVersion: example.com/v1, kind: ConfigMap, metadata: {name: settings, namespace: payments}}",
+            "{apiVersion: v1, kind: Namespace, metadata: {name: payments}}",
+        ];
+        for document in &documents {
+            let object: DynamicObject = serde_yaml::from_str(document).unwrap();
+            write_manifest(&directory, &object).unwrap();
+        }
+
+        for path in &[
+            "payments/configmap-settings.yaml",
+            "shipping/configmap-settings.yaml",
+            "payments/configmap.example.com-settings.yaml",
+            "namespace-payments.yaml",
+        ] {
+            assert!(directory.join(path).is_file(), "{} is missing", path);
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
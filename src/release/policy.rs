@@ -0,0 +1,51 @@
+use crate::identifier::Identifier;
+use crate::objects::Objects;
+
+/// Policy that restricts which kinds of objects a release may contain
+#[derive(Clone, Debug, Default)]
+pub struct KindPolicy {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+}
+
+impl KindPolicy {
+    /// Policy that permits all kinds.
+    pub fn new() -> Self {
+        KindPolicy {
+            allowed: Vec::new(),
+            denied: Vec::new(),
+        }
+    }
+
+    /// Only permit the given kinds. Without any allowed kinds, all kinds that are not denied are
+    /// permitted.
+    pub fn allow(mut self, kinds: Vec<String>) -> Self {
+        self.allowed.extend(kinds);
+        self
+    }
+
+    /// Forbid the given kinds.
+    pub fn deny(mut self, kinds: Vec<String>) -> Self {
+        self.denied.extend(kinds);
+        self
+    }
+
+    /// Does the policy permit objects of the given kind?
+    pub fn permits(&self, kind: &str) -> bool {
+        let allowed = self.allowed.is_empty() || self.allowed.iter().any(|allowed| allowed == kind);
+        allowed && !self.denied.iter().any(|denied| denied == kind)
+    }
+
+    /// Find the objects which are not permitted by the policy.
+    pub fn violations(&self, objects: &Objects) -> Vec<Identifier> {
+        let mut violations: Vec<Identifier> = objects
+            .iter()
+            .map(|(identifier, _)| identifier)
+            .filter(|identifier| !self.permits(identifier.kind()))
+            .cloned()
+            .collect();
+
+        violations.sort_by(|lhs, rhs| (lhs.kind(), lhs.name()).cmp(&(rhs.kind(), rhs.name())));
+        violations
+    }
+}
@@ -1,5 +1,7 @@
 use crate::k8s::transaction;
 use crate::objects::Object;
+use crate::release::checkpoint::Checkpoint;
+use crate::release::plan::ExecuteOptions;
 use std::error;
 use std::fmt;
 
@@ -35,6 +37,7 @@ pub struct Plan<'a> {
     creations: Vec<&'a Object>,
     upgrades: Vec<&'a Object>,
     deletions: Vec<&'a Object>,
+    recreations: Vec<&'a Object>,
 }
 
 impl<'a> Plan<'a> {
@@ -43,10 +46,15 @@ impl<'a> Plan<'a> {
             creations: Vec::new(),
             upgrades: Vec::new(),
             deletions: Vec::new(),
+            recreations: Vec::new(),
         }
     }
 
-    pub async fn execute(&self, mut client: kube::Client) -> Result<kube::Client, Error> {
+    pub async fn execute(
+        &self,
+        mut client: kube::Client,
+        options: &ExecuteOptions,
+    ) -> Result<kube::Client, Error> {
         let with_error = |action: transaction::Action, object: &Object| {
             let object = object.clone();
             move |error| Error {
@@ -57,30 +65,78 @@ impl<'a> Plan<'a> {
         };
 
         for creation in &self.creations {
-            client = transaction::create_object(client, creation)
-                .await
-                .map_err(with_error(transaction::Action::Create, creation))?
-                .client;
+            options.throttle().await;
+            client = transaction::with_timeout(
+                options.request_timeout,
+                transaction::Action::Create,
+                creation,
+                transaction::create_object(client, creation),
+            )
+            .await
+            .map_err(with_error(transaction::Action::Create, creation))?
+            .client;
         }
 
         for upgrade in &self.upgrades {
-            client = transaction::apply_object(client, upgrade)
-                .await
-                .map_err(with_error(transaction::Action::Apply, upgrade))?
-                .client;
+            options.throttle().await;
+            client = transaction::with_timeout(
+                options.request_timeout,
+                transaction::Action::Apply,
+                upgrade,
+                transaction::apply_object(client, upgrade, options.field_manager.as_str()),
+            )
+            .await
+            .map_err(with_error(transaction::Action::Apply, upgrade))?
+            .client;
+        }
+
+        for recreation in &self.recreations {
+            options.throttle().await;
+            client = transaction::with_timeout(
+                options.request_timeout,
+                transaction::Action::Recreate,
+                recreation,
+                transaction::recreate_object(client, recreation),
+            )
+            .await
+            .map_err(with_error(transaction::Action::Recreate, recreation))?
+            .client;
         }
 
         for deletion in &self.deletions {
-            client = transaction::delete_object(client, deletion)
-                .await
-                .map_err(with_error(transaction::Action::Delete, deletion))?;
+            options.throttle().await;
+            client = transaction::with_timeout(
+                options.request_timeout,
+                transaction::Action::Delete,
+                deletion,
+                transaction::delete_object(client, deletion),
+            )
+            .await
+            .map_err(with_error(transaction::Action::Delete, deletion))?;
         }
 
         Ok(client)
     }
 
+    /// Copy of the plan which can be persisted
+    pub fn to_checkpoint(&self) -> Checkpoint {
+        let owned =
+            |objects: &Vec<&Object>| objects.iter().map(|object| (*object).clone()).collect();
+
+        Checkpoint {
+            creations: owned(&self.creations),
+            upgrades: owned(&self.upgrades),
+            deletions: owned(&self.deletions),
+            recreations: owned(&self.recreations),
+        }
+    }
+
     pub fn register<T: Rollbackable>(&mut self, action: &'a T) {
-        match action.to_rollback() {
+        self.register_action(action.to_rollback())
+    }
+
+    pub fn register_action(&mut self, action: (transaction::Action, &'a Object)) {
+        match action {
             (transaction::Action::Create, object) => {
                 self.creations.push(object);
             }
@@ -92,6 +148,10 @@ impl<'a> Plan<'a> {
             (transaction::Action::Delete, object) => {
                 self.deletions.push(object);
             }
+
+            (transaction::Action::Recreate, object) => {
+                self.recreations.push(object);
+            }
         }
     }
 }
@@ -1,47 +1,173 @@
 use crate::k8s;
 use crate::k8s::api_resource;
 use crate::k8s::labels;
+use crate::k8s::selector::Selector;
 use crate::objects::Object;
 use crate::objects::Objects;
 use crate::release;
+use futures::stream;
+use futures::StreamExt;
+use kube::api::ListParams;
+use kube::core::ApiResource;
+use kube::core::DynamicObject;
 use serde_json::value::Value;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 
+/// Number of objects requested per page when listing a resource
+const PAGE_SIZE: u32 = 250;
+
+/// Number of resources that are listed concurrently by default
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// List all objects of the resource that match the list parameters, one page at a time. Resources
+/// which are not accessible yield no objects.
+async fn list_resource(
+    client: kube::Client,
+    resource: ApiResource,
+    params: ListParams,
+) -> Result<Vec<(release::Identifier, Object)>, kube::Error> {
+    let api: kube::Api<DynamicObject> = kube::Api::all_with(client, &resource);
+    let mut params = params.limit(PAGE_SIZE);
+    let mut objects = Vec::new();
+
+    loop {
+        let page = match api.list(&params).await {
+            Ok(page) => page,
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 403, .. }))
+            | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => break,
+            Err(error) => return Err(error),
+        };
+
+        objects.extend(page.items.into_iter().filter_map(|item| {
+            let name = item.metadata.name.clone()?;
+            let identifier = release::Identifier::from_api_resource(name, &resource)
+                .with_namespace(item.metadata.namespace.clone());
+            let object = Object::try_from_dynamic_object(item)?;
+            Some((identifier, object))
+        }));
+
+        match page.metadata.continue_ {
+            Some(token) if !token.is_empty() => params = params.continue_token(token.as_str()),
+            _ => break,
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Find the objects of a release in all namespaces. The objects are identified including their
+/// namespace, so objects with the same name in different namespaces are kept apart. See
+/// `api_resource::find_api_resources` regarding `strict` discovery. Up to `concurrency` resources
+/// are listed at the same time. Groups which have been skipped are returned along with the
+/// objects.
 pub async fn find_release_objects(
-    mut client: kube::Client,
+    client: kube::Client,
     release_name: String,
-) -> Result<Objects, kube::Error> {
-    let all_resources = api_resource::find_api_resources(&client).await?;
+    selector: &Selector,
+    strict: bool,
+    concurrency: usize,
+) -> Result<(Objects, Vec<api_resource::SkippedGroup>), kube::Error> {
+    let (all_resources, skipped) = api_resource::find_api_resources(&client, strict).await?;
     let labels = labels::Labels::from(k8s::ObjectType::Managed)
         .add(k8s::ReleaseName(release_name))
-        .to_listparams();
+        .to_listparams_with(selector);
+
+    let mut listings = stream::iter(all_resources)
+        .map(|resource| list_resource(client.clone(), resource, labels.clone()))
+        .buffer_unordered(concurrency.max(1));
 
     let mut all_items = HashMap::new();
 
-    for resource in all_resources {
-        let api: kube::Api<kube::core::DynamicObject> = kube::Api::all_with(client, &resource);
-        let items = api.list(&labels).await?.items;
+    while let Some(items) = listings.next().await {
+        all_items.extend(items?);
+    }
 
-        all_items.extend(items.into_iter().filter_map(|item| {
-            let name = item.metadata.name.clone()?;
-            let identifier = release::Identifier::from_api_resource(name, &resource);
-            let object = Object::try_from_dynamic_object(item)?;
-            Some((identifier, object))
-        }));
+    Ok((Objects::from(all_items), skipped))
+}
+
+/// Divergence between the desired and the real value of a field
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    /// Path to the field at which the values diverge
+    pub path: VecDeque<String>,
+
+    /// Desired value
+    pub expected: Value,
+
+    /// Real value, unless the field is missing
+    pub actual: Option<Value>,
+}
+
+/// Fields which hold lists whose items are identified by a key instead of their position, like
+/// the patch merge keys of Kubernetes. Items of such lists are matched by their key, so reordering
+/// them is not a mismatch.
+#[derive(Clone, Debug)]
+pub struct ListKeys {
+    keys: BTreeMap<String, String>,
+}
+
+impl ListKeys {
+    /// Compare all lists by position.
+    pub fn empty() -> Self {
+        ListKeys {
+            keys: BTreeMap::new(),
+        }
+    }
 
-        client = api.into_client();
+    /// Identify the items of lists in fields of the given name by the given key.
+    pub fn with_key(mut self, field: String, key: String) -> Self {
+        self.keys.insert(field, key);
+        self
     }
 
-    Ok(Objects::from(all_items))
+    pub(crate) fn key_of(&self, field: &str) -> Option<&str> {
+        self.keys.get(field).map(String::as_str)
+    }
+}
+
+impl Default for ListKeys {
+    /// Keyed lists of Pod templates
+    fn default() -> Self {
+        [
+            ("containers", "name"),
+            ("initContainers", "name"),
+            ("ephemeralContainers", "name"),
+            ("env", "name"),
+            ("volumes", "name"),
+            ("volumeMounts", "mountPath"),
+            ("volumeDevices", "devicePath"),
+            ("imagePullSecrets", "name"),
+        ]
+        .iter()
+        .fold(ListKeys::empty(), |list_keys, (field, key)| {
+            list_keys.with_key(field.to_string(), key.to_string())
+        })
+    }
 }
 
+/// Check that the instance contains the spec. Objects may have additional fields. Lists have to
+/// match item by item, unless they are keyed, in which case every item of the spec has to be
+/// matched by an item of the instance with the same key.
 pub fn check_value(
     spec: &serde_json::Value,
     instance: &serde_json::Value,
     path: VecDeque<String>,
-) -> Result<(), VecDeque<String>> {
+    list_keys: &ListKeys,
+) -> Result<(), Mismatch> {
+    if let (Value::Array(spec_items), Value::Array(instance_items)) = (spec, instance) {
+        let key = path.back().and_then(|field| list_keys.key_of(field));
+
+        if let Some(key) = key {
+            let has_key = |items: &Vec<Value>| items.iter().all(|item| item.get(key).is_some());
+
+            if has_key(spec_items) && has_key(instance_items) {
+                return check_keyed_list(spec_items, instance_items, key, path, list_keys);
+            }
+        }
+    }
+
     match (spec, instance) {
         (Value::Null, Value::Null) => {}
 
@@ -55,26 +181,65 @@ pub fn check_value(
             for index in 0..i.len() {
                 let mut path = path.clone();
                 path.push_back(format!("{}", index));
-                check_value(&spec[index], &i[index], path)?;
+                check_value(&spec[index], &i[index], path, list_keys)?;
             }
         }
 
         (Value::Object(spec), Value::Object(instance)) => {
             for (key, spec_value) in spec {
-                let cloned_path = || {
-                    let mut path = path.clone();
-                    path.push_back(key.clone());
-                    path
-                };
-
-                instance.get(key).map_or_else(
-                    || Err(cloned_path()),
-                    |instance_value| check_value(spec_value, instance_value, cloned_path()),
-                )?;
+                let mut path = path.clone();
+                path.push_back(key.clone());
+
+                match instance.get(key) {
+                    Some(instance_value) => {
+                        check_value(spec_value, instance_value, path, list_keys)?
+                    }
+                    None => {
+                        return Err(Mismatch {
+                            path,
+                            expected: spec_value.clone(),
+                            actual: None,
+                        })
+                    }
+                }
             }
         }
 
-        _ => return Err(path),
+        _ => {
+            return Err(Mismatch {
+                path,
+                expected: spec.clone(),
+                actual: Some(instance.clone()),
+            })
+        }
+    }
+
+    Ok(())
+}
+
+fn check_keyed_list(
+    spec_items: &[Value],
+    instance_items: &[Value],
+    key: &str,
+    path: VecDeque<String>,
+    list_keys: &ListKeys,
+) -> Result<(), Mismatch> {
+    for spec_item in spec_items {
+        let id = &spec_item[key];
+
+        let mut path = path.clone();
+        path.push_back(id.as_str().map_or_else(|| id.to_string(), str::to_string));
+
+        match instance_items.iter().find(|item| &item[key] == id) {
+            Some(instance_item) => check_value(spec_item, instance_item, path, list_keys)?,
+            None => {
+                return Err(Mismatch {
+                    path,
+                    expected: spec_item.clone(),
+                    actual: None,
+                })
+            }
+        }
     }
 
     Ok(())
@@ -87,3 +252,166 @@ pub fn check_mapping(spec: &BTreeMap<String, String>, instance: &BTreeMap<String
             .map_or(false, |instance_value| instance_value == spec_value)
     })
 }
+
+/// Differences between the objects of a release and the objects in the cluster, see
+/// `Release::detect_drift`
+#[derive(Clone, Debug, Default)]
+pub struct DriftReport {
+    /// Objects in the cluster which are labelled as part of the release but not declared by it
+    pub added: Vec<release::Identifier>,
+
+    /// Objects of the release which are missing from the cluster
+    pub removed: Vec<release::Identifier>,
+
+    /// Objects which differ from the release, with the first divergent field of each
+    pub changed: Vec<(release::Identifier, Mismatch)>,
+
+    /// API groups whose objects could not be listed and therefore have not been compared
+    pub skipped: Vec<api_resource::SkippedGroup>,
+}
+
+impl DriftReport {
+    /// Do the objects in the cluster match the release?
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare the desired objects of a release with the live ones. Desired objects without a
+/// namespace are expected in the given namespace. Only the fields, labels and annotations which
+/// are desired are compared, see `check_value` regarding lists.
+pub fn detect_drift(
+    release_name: &str,
+    desired: &Objects,
+    mut live: Objects,
+    namespace: &str,
+    list_keys: &ListKeys,
+) -> DriftReport {
+    let mut report = DriftReport::default();
+
+    for (identifier, object) in desired.iter_ordered() {
+        let identifier = identifier.clone().with_default_namespace(namespace);
+        let desired =
+            release::plan::ReleasePlan::tag_object(release_name.to_string(), object.clone());
+
+        let reality = match live.remove(&identifier) {
+            Some(reality) => reality,
+            None => {
+                report.removed.push(identifier);
+                continue;
+            }
+        };
+
+        if let Some(mismatch) = check_object(&desired, &reality, list_keys) {
+            report.changed.push((identifier, mismatch));
+        }
+    }
+
+    report.added = live.identifiers().into_iter().collect();
+    report.added.sort();
+    report
+}
+
+/// Find the first field of the desired object which the real object doesn't match.
+fn check_object(desired: &Object, reality: &Object, list_keys: &ListKeys) -> Option<Mismatch> {
+    let desired_meta = &desired.dyn_object.metadata;
+    let reality_meta = &reality.dyn_object.metadata;
+
+    let mappings = [
+        ("labels", &desired_meta.labels, &reality_meta.labels),
+        (
+            "annotations",
+            &desired_meta.annotations,
+            &reality_meta.annotations,
+        ),
+    ];
+
+    for (field, desired_mapping, real_mapping) in mappings.iter() {
+        if !check_mapping(desired_mapping, real_mapping) {
+            return Some(Mismatch {
+                path: ["metadata", *field]
+                    .iter()
+                    .map(|part| part.to_string())
+                    .collect(),
+                expected: serde_json::to_value(desired_mapping).unwrap_or_default(),
+                actual: serde_json::to_value(real_mapping).ok(),
+            });
+        }
+    }
+
+    check_value(
+        &desired.dyn_object.data,
+        &reality.dyn_object.data,
+        VecDeque::new(),
+        list_keys,
+    )
+    .err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::Builder;
+
+    const CONFIG_MAP: &str =
+        "{apiVersion: v1, kind: ConfigMap, metadata: {name: frontend, namespace: payments}, data: {port: '80'}}";
+
+    const SERVICE: &str =
+        "{apiVersion: v1, kind: Service, metadata: {name: frontend, namespace: payments}, spec: {ports: [{port: 80}]}}";
+
+    fn objects_from(documents: &[&str]) -> Objects {
+        let mut builder = Builder::new();
+        for document in documents {
+            builder
+                .read_objects(document.as_bytes())
+                .expect("Fixture must be valid");
+        }
+        builder.finish()
+    }
+
+    /// Objects as they are found in the cluster, labelled as part of the release "web"
+    fn live_objects_from(documents: &[&str]) -> Objects {
+        objects_from(documents)
+            .into_iter()
+            .map(|(identifier, object)| {
+                let object = release::plan::ReleasePlan::tag_object("web".to_string(), object);
+                (identifier, object)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn objects_of_different_kinds_with_the_same_name_are_compared_separately() {
+        let desired = objects_from(&[CONFIG_MAP, SERVICE]);
+        let live = live_objects_from(&[
+            CONFIG_MAP,
+            "{apiVersion: v1, kind: Service, metadata: {name: frontend, namespace: payments}, spec: {ports: [{port: 8080}]}}",
+        ]);
+        assert_eq!(live.len(), 2);
+
+        let report = detect_drift("web", &desired, live, "payments", &ListKeys::default());
+
+        assert!(report.added.is_empty());
+        assert!(report.removed.is_empty());
+        assert_eq!(report.changed.len(), 1);
+
+        let (identifier, mismatch) = &report.changed[0];
+        assert_eq!(identifier.kind(), "Service");
+        assert_eq!(identifier.name(), "frontend");
+        assert_eq!(mismatch.path.front().map(String::as_str), Some("spec"));
+    }
+
+    #[test]
+    fn missing_objects_are_reported_by_kind() {
+        let desired = objects_from(&[CONFIG_MAP, SERVICE]);
+        let live = live_objects_from(&[SERVICE]);
+
+        let report = detect_drift("web", &desired, live, "payments", &ListKeys::default());
+
+        assert!(report.added.is_empty());
+        assert!(report.changed.is_empty());
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.removed[0].kind(), "ConfigMap");
+        assert_eq!(report.removed[0].name(), "frontend");
+    }
+}
@@ -1 +1,5 @@
-pub(crate) mod fs;
+pub mod backoff;
+pub mod env;
+pub mod fs;
+pub mod git;
+pub mod rate_limit;
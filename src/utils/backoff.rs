@@ -0,0 +1,123 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::time::Duration;
+
+/// Successive delays for polling or retrying, growing exponentially up to a maximum
+///
+/// With jitter, each delay is shortened by a random fraction of up to `jitter`, so clients that
+/// started at the same time spread out instead of hitting the API server in lockstep.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    next: Duration,
+    factor: f64,
+    max: Duration,
+    jitter: f64,
+}
+
+impl Backoff {
+    /// Start with the base delay and multiply it by the factor after every step.
+    pub fn new(base: Duration, factor: f64) -> Self {
+        Backoff {
+            next: base,
+            factor,
+            max: Duration::MAX,
+            jitter: 0.0,
+        }
+    }
+
+    /// Never delay longer than the given maximum.
+    pub fn with_max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Shorten each delay by a random fraction of up to `jitter`, which is clamped to `0..=1`.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    /// Wait for the next delay.
+    pub async fn sleep(&mut self) {
+        if let Some(delay) = self.next() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+impl Iterator for Backoff {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let delay = self.next.min(self.max);
+        self.next = Duration::try_from_secs_f64(self.next.as_secs_f64() * self.factor)
+            .unwrap_or(self.max)
+            .min(self.max);
+
+        if self.jitter == 0.0 {
+            return Some(delay);
+        }
+
+        Some(delay.mul_f64(1.0 - self.jitter * random_fraction()))
+    }
+}
+
+/// Random number in `0..1`, drawn from the randomly seeded hasher of the standard library
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_by_the_factor() {
+        let delays: Vec<Duration> = Backoff::new(Duration::from_millis(100), 2.0)
+            .take(4)
+            .collect();
+
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(800),
+            ]
+        );
+    }
+
+    #[test]
+    fn delays_are_capped() {
+        let delays: Vec<Duration> = Backoff::new(Duration::from_secs(1), 3.0)
+            .with_max(Duration::from_secs(5))
+            .take(4)
+            .collect();
+
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_secs(1),
+                Duration::from_secs(3),
+                Duration::from_secs(5),
+                Duration::from_secs(5),
+            ]
+        );
+    }
+
+    #[test]
+    fn jitter_only_shortens_delays() {
+        let delays = Backoff::new(Duration::from_secs(1), 2.0)
+            .with_jitter(0.5)
+            .take(8);
+        let expected = Backoff::new(Duration::from_secs(1), 2.0).take(8);
+
+        for (delay, expected) in delays.zip(expected) {
+            assert!(delay <= expected);
+            assert!(delay >= expected / 2);
+        }
+    }
+}
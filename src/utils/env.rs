@@ -0,0 +1,149 @@
+use std::error;
+use std::fmt;
+
+/// Error that occurs during variable expansion
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Variable is not defined and has no default
+    Undefined { name: String },
+
+    /// `${` without matching `}`
+    Unterminated { position: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::Undefined { name } => write!(formatter, "Variable {} is not defined", name),
+            Error::Unterminated { position } => {
+                write!(formatter, "Unterminated variable at position {}", position)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Expand `$VAR`, `${VAR}` and `${VAR:-default}` using the given lookup function. `$$` expands to
+/// a literal `$`, a `$` which is not followed by a variable name is kept as is.
+pub fn expand<F>(input: &str, lookup: F) -> Result<String, Error>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            output.push('$');
+            rest = after;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let end = braced.find('}').ok_or(Error::Unterminated {
+                position: input.len() - rest.len() + index,
+            })?;
+
+            let expression = &braced[..end];
+            let (name, default) = match expression.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expression, None),
+            };
+
+            match (lookup(name), default) {
+                (Some(value), Some(default)) if value.is_empty() => output.push_str(default),
+                (Some(value), _) => output.push_str(value.as_str()),
+                (None, Some(default)) => output.push_str(default),
+                (None, None) => {
+                    return Err(Error::Undefined {
+                        name: name.to_string(),
+                    })
+                }
+            }
+
+            rest = &braced[end + 1..];
+        } else {
+            let length = after
+                .find(|c: char| !is_name_char(c))
+                .unwrap_or_else(|| after.len());
+            let name = &after[..length];
+
+            if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+                output.push('$');
+                rest = after;
+                continue;
+            }
+
+            let value = lookup(name).ok_or_else(|| Error::Undefined {
+                name: name.to_string(),
+            })?;
+
+            output.push_str(value.as_str());
+            rest = &after[length..];
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "NAME" => Some("frontend".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        assert_eq!(
+            expand("$NAME-${NAME}:$NAME.yaml", lookup).unwrap(),
+            "frontend-frontend:frontend.yaml"
+        );
+    }
+
+    #[test]
+    fn defaults_apply_to_missing_and_empty_variables() {
+        assert_eq!(expand("${MISSING:-web}", lookup).unwrap(), "web");
+        assert_eq!(expand("${EMPTY:-web}", lookup).unwrap(), "web");
+        assert_eq!(expand("${NAME:-web}", lookup).unwrap(), "frontend");
+        assert_eq!(expand("${MISSING:-}", lookup).unwrap(), "");
+        assert_eq!(expand("${EMPTY}", lookup).unwrap(), "");
+    }
+
+    #[test]
+    fn missing_variables_are_errors() {
+        for input in &["$MISSING", "${MISSING}", "prefix-$MISSING-suffix"] {
+            match expand(input, lookup) {
+                Err(Error::Undefined { name }) => assert_eq!(name, "MISSING"),
+                result => panic!("Unexpected result for {:?}: {:?}", input, result),
+            }
+        }
+    }
+
+    #[test]
+    fn unterminated_variables_are_errors() {
+        match expand("name: ${NAME", lookup) {
+            Err(Error::Unterminated { position }) => assert_eq!(position, 6),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn dollars_without_names_are_kept() {
+        assert_eq!(
+            expand("$$NAME costs $5 $", lookup).unwrap(),
+            "$NAME costs $5 $"
+        );
+    }
+}
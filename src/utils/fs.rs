@@ -1,8 +1,52 @@
 use std::io;
 use std::path;
 
+/// Names of the files which mark a directory as a kustomization
+const KUSTOMIZATION_FILES: [&str; 3] = ["kustomization.yaml", "kustomization.yml", "Kustomization"];
+
+/// Is the path a directory which contains a kustomization?
+pub fn is_kustomization(path: &path::Path) -> bool {
+    path.is_dir()
+        && KUSTOMIZATION_FILES
+            .iter()
+            .any(|name| path.join(name).is_file())
+}
+
+/// Suffixes of files which have been encrypted with SOPS
+const SOPS_SUFFIXES: [&str; 6] = [
+    ".sops.yaml",
+    ".sops.yml",
+    ".sops.json",
+    ".enc.yaml",
+    ".enc.yml",
+    ".enc.json",
+];
+
+/// Name of the SOPS configuration file, which carries one of the suffixes but is not encrypted
+const SOPS_CONFIG_FILE: &str = ".sops.yaml";
+
+/// Is the file name that of a file which has been encrypted with SOPS?
+pub fn is_sops_file_name(name: &str) -> bool {
+    name != SOPS_CONFIG_FILE && SOPS_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// Is the path a file which has been encrypted with SOPS?
+pub fn is_sops_encrypted(path: &path::Path) -> bool {
+    path.is_file()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, is_sops_file_name)
+}
+
+/// Is the path the SOPS configuration file, which does not contain any objects?
+pub fn is_sops_config(path: &path::Path) -> bool {
+    path.file_name()
+        .map_or(false, |name| name == SOPS_CONFIG_FILE)
+}
+
 fn list_files_vec(paths: &mut Vec<path::PathBuf>, path: &path::Path) -> Result<(), io::Error> {
-    if path.is_dir() {
+    if path.is_dir() && !is_kustomization(path) {
         for entry in path.read_dir()? {
             let dir = entry?.path();
             list_files_vec(paths, dir.as_path())?;
@@ -19,6 +63,8 @@ fn list_files_vec(paths: &mut Vec<path::PathBuf>, path: &path::Path) -> Result<(
     Ok(())
 }
 
+/// List all files under the given path. Directories containing a kustomization are not traversed
+/// but listed themselves.
 pub fn list_files(path: &path::Path) -> Result<Vec<path::PathBuf>, io::Error> {
     let mut paths = Vec::new();
     list_files_vec(&mut paths, path)?;
@@ -0,0 +1,254 @@
+use crate::meta;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Prefix which marks an input as a Git source
+const PREFIX: &str = "git+";
+
+/// Environment variable holding a token used to authenticate against HTTP(S) repositories
+const TOKEN_VAR: &str = "ABLE_SEAMAN_GIT_TOKEN";
+
+/// Environment variable holding the user name that goes with the token
+const USERNAME_VAR: &str = "ABLE_SEAMAN_GIT_USERNAME";
+
+/// Error that occurs when fetching a Git source
+#[derive(Debug)]
+pub enum Error {
+    /// The source could not be parsed
+    Parse { input: String, reason: String },
+
+    /// Git could not be executed
+    Spawn(io::Error),
+
+    /// Git exited unsuccessfully
+    Git { command: String, stderr: String },
+
+    /// The temporary checkout directory could not be created
+    Checkout(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Error::Parse { input, reason } => {
+                write!(formatter, "Invalid Git source {:?}: {}", input, reason)
+            }
+            Error::Spawn(error) => write!(formatter, "Failed to run git: {}", error),
+            Error::Git { command, stderr } => {
+                write!(formatter, "git {} failed: {}", command, stderr.trim())
+            }
+            Error::Checkout(error) => {
+                write!(formatter, "Failed to create checkout directory: {}", error)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Directory in a Git repository, e.g. `git+https://github.com/org/repo//path?ref=v1.2.3`
+///
+/// The path and the ref are optional. Without a ref, the default branch is used.
+#[derive(Clone, Debug)]
+pub struct Source {
+    repository: String,
+    path: String,
+    reference: Option<String>,
+}
+
+impl Source {
+    /// Does the input refer to a Git source?
+    pub fn is_source(input: &str) -> bool {
+        input.starts_with(PREFIX)
+    }
+
+    /// Shallowly fetch the source into a temporary directory.
+    pub fn fetch(&self) -> Result<Checkout, Error> {
+        let directory = std::env::temp_dir().join(format!(
+            "{}-{}-{}",
+            meta::CRATE_NAME,
+            process::id(),
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|duration| duration.as_nanos())
+                .unwrap_or_default()
+        ));
+
+        fs::create_dir_all(directory.as_path()).map_err(Error::Checkout)?;
+
+        // Removes the directory again if anything below fails.
+        let checkout = Checkout {
+            directory,
+            path: self.path.clone(),
+        };
+
+        let reference = self.reference.as_deref().unwrap_or("HEAD");
+
+        self.git(&checkout.directory, &["init", "--quiet"])?;
+        self.git(
+            &checkout.directory,
+            &[
+                "fetch",
+                "--quiet",
+                "--depth",
+                "1",
+                self.repository.as_str(),
+                reference,
+            ],
+        )?;
+        self.git(&checkout.directory, &["checkout", "--quiet", "FETCH_HEAD"])?;
+
+        Ok(checkout)
+    }
+
+    fn git(&self, directory: &Path, args: &[&str]) -> Result<(), Error> {
+        let mut command = process::Command::new("git");
+        command
+            .args(args)
+            .current_dir(directory)
+            .env("GIT_TERMINAL_PROMPT", "0");
+
+        let is_http =
+            self.repository.starts_with("https://") || self.repository.starts_with("http://");
+
+        // SSH authentication is left to the SSH agent, tokens are only used for HTTP(S).
+        if let (true, Ok(token)) = (is_http, std::env::var(TOKEN_VAR)) {
+            let username =
+                std::env::var(USERNAME_VAR).unwrap_or_else(|_| "x-access-token".to_string());
+            let credentials = base64(format!("{}:{}", username, token).as_bytes());
+
+            // Passing the header through the environment keeps the token off the command line.
+            command
+                .env("GIT_CONFIG_COUNT", "1")
+                .env("GIT_CONFIG_KEY_0", "http.extraHeader")
+                .env(
+                    "GIT_CONFIG_VALUE_0",
+                    format!("Authorization: Basic {}", credentials),
+                );
+        }
+
+        let output = command.output().map_err(Error::Spawn)?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::Git {
+                command: args.first().copied().unwrap_or("").to_string(),
+                stderr: String::from_utf8_lossy(output.stderr.as_slice()).into_owned(),
+            })
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = |reason: &str| Error::Parse {
+            input: input.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let location = input
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| error("Git sources must start with 'git+'"))?;
+
+        let (location, reference) = match location.split_once('?') {
+            Some((location, query)) => {
+                let reference = query
+                    .split('&')
+                    .find_map(|parameter| parameter.strip_prefix("ref="))
+                    .filter(|reference| !reference.is_empty())
+                    .ok_or_else(|| error("The only supported query parameter is 'ref'"))?;
+
+                (location, Some(reference.to_string()))
+            }
+
+            None => (location, None),
+        };
+
+        // The path within the repository is separated by a double slash, which must not be
+        // confused with the one following the scheme.
+        let path_start = location.find("://").map_or(0, |index| index + 3);
+
+        let (repository, path) = match location[path_start..].find("//") {
+            Some(index) => (
+                &location[..path_start + index],
+                &location[path_start + index + 2..],
+            ),
+            None => (location, ""),
+        };
+
+        if repository.is_empty() {
+            return Err(error("Repository must not be empty"));
+        }
+
+        if path.split('/').any(|segment| segment == "..") {
+            return Err(error("Path must not leave the repository"));
+        }
+
+        Ok(Source {
+            repository: repository.to_string(),
+            path: path.to_string(),
+            reference,
+        })
+    }
+}
+
+/// Temporary checkout of a Git source, which is removed when dropped
+#[derive(Debug)]
+pub struct Checkout {
+    directory: PathBuf,
+    path: String,
+}
+
+impl Checkout {
+    /// Path of the requested directory within the checkout
+    pub fn path(&self) -> PathBuf {
+        self.directory.join(self.path.as_str())
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_dir_all(self.directory.as_path()) {
+            eprintln!(
+                "Failed to remove checkout {}: {}",
+                self.directory.display(),
+                error
+            );
+        }
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let combined = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32;
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push(ALPHABET[((combined >> (18 - 6 * index)) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Token bucket which limits the rate of requests. Clones share the same bucket, so all users of
+/// a limiter are throttled together.
+///
+/// The bucket holds up to `burst` tokens and is refilled with `qps` tokens per second. Every
+/// request takes one token and waits for it if the bucket is empty.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    qps: f64,
+    burst: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Permit `qps` requests per second on average and up to `burst` requests at once. Both are
+    /// at least 1, lower values would block forever.
+    pub fn new(qps: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+
+        RateLimiter {
+            qps: if qps > 0.0 { qps } else { 1.0 },
+            burst,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Wait until another request is permitted.
+    pub async fn acquire(&self) {
+        while let Some(delay) = self.try_acquire() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Take a token if there is one, otherwise return how long it takes for one to become
+    /// available.
+    fn try_acquire(&self) -> Option<Duration> {
+        let mut bucket = self
+            .bucket
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.qps).min(self.burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / self.qps))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_available_up_front() {
+        let limiter = RateLimiter::new(1.0, 3);
+
+        assert_eq!(limiter.try_acquire(), None);
+        assert_eq!(limiter.try_acquire(), None);
+        assert_eq!(limiter.try_acquire(), None);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn clones_share_the_bucket() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let clone = limiter.clone();
+
+        assert_eq!(limiter.try_acquire(), None);
+        assert!(clone.try_acquire().is_some());
+    }
+}